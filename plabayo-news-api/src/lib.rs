@@ -0,0 +1,54 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::sync::Arc;
+
+use actix_web::dev::Server;
+use actix_web::{middleware as actix_middleware, web, App, HttpServer};
+use anyhow::{Context, Result};
+
+use plabayo_news_data::Database;
+
+pub mod middleware;
+pub mod quota;
+pub mod v0;
+
+/// Start the API server on the given interface, serving the content of the given database.
+/// The database is kept in memory, so the API is served by the same process as the website,
+/// such that it serves the same content and accepts the keys issued by its admins.
+pub fn server(db: Database, interface: &str) -> Result<Server> {
+    let quotas = Arc::new(quota::Quotas::new());
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(db.clone()))
+            .wrap(actix_middleware::Logger::default())
+            .wrap(actix_middleware::Compress::default())
+            .wrap(middleware::ApiKeyAuth::new(db.clone(), quotas.clone()))
+            .wrap(actix_middleware::NormalizePath::new(
+                actix_middleware::normalize::TrailingSlash::Trim,
+            ))
+            .service(v0::factory())
+    })
+    .bind(interface)
+    .with_context(|| {
+        format!(
+            "bind Plabayo News API HTTPServer to interface: {}",
+            interface
+        )
+    })?
+    .run();
+    Ok(server)
+}
@@ -14,6 +14,49 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-fn main() {
-    println!("Hello, world!");
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use structopt::StructOpt;
+
+use plabayo_news_api::server;
+use plabayo_news_data::Database;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "plabayo-news-api")]
+struct Opt {
+    /// enable debugging features such as the logger
+    #[structopt(short, long)]
+    debug: bool,
+
+    /// interface to bind to
+    #[structopt(short, long, default_value = "127.0.0.1:8081")]
+    interface: String,
+
+    /// file the database of the website is saved to, served as saved at startup
+    #[structopt(long, parse(from_os_str))]
+    database: Option<PathBuf>,
+}
+
+#[actix_web::main]
+async fn main() -> Result<()> {
+    let opt = Opt::from_args();
+
+    if opt.debug {
+        std::env::set_var("RUST_LOG", "actix_web=info");
+    } else {
+        std::env::set_var("RUST_LOG", "actix_web=error");
+    }
+    env_logger::init();
+
+    // a database of its own, the website serves the API of its database itself
+    let db = match &opt.database {
+        Some(path) => {
+            Database::open(path).with_context(|| format!("open database {}", path.display()))?
+        }
+        None => Database::new(),
+    };
+    server(db, &opt.interface)?.await?;
+
+    Ok(())
 }
@@ -0,0 +1,278 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{
+    HeaderName, HeaderValue, AUTHORIZATION, RETRY_AFTER, WWW_AUTHENTICATE,
+};
+use actix_web::http::{HeaderMap, StatusCode};
+use actix_web::{Error, HttpResponse, ResponseError};
+use futures::future::{ok, Ready};
+use futures::Future;
+
+use plabayo_news_data::Database;
+
+use crate::quota::{QuotaStatus, Quotas};
+
+/// Only allow requests using a valid (unrevoked) API key,
+/// given as a bearer token, within the rate limit and daily quota of that key.
+/// All responses contain the `RateLimit-*` headers of the used key.
+pub struct ApiKeyAuth {
+    db: Database,
+    quotas: Arc<Quotas>,
+}
+
+impl ApiKeyAuth {
+    pub fn new(db: Database, quotas: Arc<Quotas>) -> ApiKeyAuth {
+        ApiKeyAuth { db, quotas }
+    }
+}
+
+impl<S, B> Transform<S> for ApiKeyAuth
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ApiKeyAuthMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ApiKeyAuthMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            db: self.db.clone(),
+            quotas: self.quotas.clone(),
+        })
+    }
+}
+
+pub struct ApiKeyAuthMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    db: Database,
+    quotas: Arc<Quotas>,
+}
+
+impl<S, B> Service for ApiKeyAuthMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let db = self.db.clone();
+        let quotas = self.quotas.clone();
+
+        Box::pin(async move {
+            let token = bearer_token(req.headers()).ok_or(ApiKeyError::Missing)?;
+            let key = db
+                .verify_api_key(&token)
+                .await
+                .ok_or(ApiKeyError::Invalid)?;
+
+            let status = quotas.consume(&key, SystemTime::now());
+            if status.exceeded {
+                return Err(ApiKeyError::LimitExceeded(status).into());
+            }
+
+            let fut = service.borrow_mut().call(req);
+            let mut res = fut.await?;
+            insert_rate_limit_headers(res.headers_mut(), &status);
+            Ok(res)
+        })
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.trim().split_once(' ')?;
+    if scheme.eq_ignore_ascii_case("bearer") {
+        Some(token.trim().to_owned())
+    } else {
+        None
+    }
+}
+
+fn insert_rate_limit_headers(headers: &mut HeaderMap, status: &QuotaStatus) {
+    for (name, value) in [
+        ("ratelimit-limit", status.limit.to_string()),
+        ("ratelimit-remaining", status.remaining.to_string()),
+        ("ratelimit-reset", status.reset.to_string()),
+        ("ratelimit-policy", status.policy.clone()),
+    ] {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(HeaderName::from_static(name), value);
+        }
+    }
+}
+
+#[derive(Debug)]
+enum ApiKeyError {
+    Missing,
+    Invalid,
+    LimitExceeded(QuotaStatus),
+}
+
+impl fmt::Display for ApiKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiKeyError::Missing => write!(f, "an API key is required as bearer token"),
+            ApiKeyError::Invalid => write!(f, "the API key is invalid or revoked"),
+            ApiKeyError::LimitExceeded(_) => {
+                write!(
+                    f,
+                    "the rate limit or daily quota of the API key is exceeded"
+                )
+            }
+        }
+    }
+}
+
+impl ResponseError for ApiKeyError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiKeyError::Missing | ApiKeyError::Invalid => StatusCode::UNAUTHORIZED,
+            ApiKeyError::LimitExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut res = HttpResponse::build(self.status_code()).body(self.to_string());
+        match self {
+            ApiKeyError::Missing | ApiKeyError::Invalid => {
+                res.headers_mut()
+                    .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            }
+            ApiKeyError::LimitExceeded(status) => {
+                insert_rate_limit_headers(res.headers_mut(), status);
+                res.headers_mut()
+                    .insert(RETRY_AFTER, HeaderValue::from(status.reset));
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, web, App};
+    use plabayo_news_data::models::{ApiKeyLimits, ApiKeyOwner};
+
+    use super::*;
+
+    /// Request `/` with the given bearer token,
+    /// returning the response, also in case the middleware refused the request.
+    async fn get(db: &Database, quotas: &Arc<Quotas>, token: Option<&str>) -> HttpResponse {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(ApiKeyAuth::new(db.clone(), quotas.clone()))
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let mut req = test::TestRequest::get().uri("/");
+        if let Some(token) = token {
+            req = req.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+        match app.call(req.to_request()).await {
+            Ok(res) => res.into(),
+            Err(err) => HttpResponse::from_error(err),
+        }
+    }
+
+    fn header<'a>(res: &'a HttpResponse, name: &str) -> &'a str {
+        res.headers().get(name).unwrap().to_str().unwrap()
+    }
+
+    #[test]
+    fn only_valid_keys_are_authorized() {
+        actix_web::rt::System::new("test").block_on(async {
+            let db = Database::new();
+            let quotas = Arc::new(Quotas::new());
+            let key = db
+                .issue_api_key(ApiKeyOwner::User(100), ApiKeyLimits::default())
+                .await;
+
+            let res = get(&db, &quotas, None).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(header(&res, "www-authenticate"), "Bearer");
+
+            let res = get(&db, &quotas, Some("pn_unknown_key")).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+            let res = get(&db, &quotas, Some(&key)).await;
+            assert_eq!(res.status(), StatusCode::OK);
+
+            let id = db.get_api_keys().await[0].id;
+            assert!(db.revoke_api_key(id).await);
+            let res = get(&db, &quotas, Some(&key)).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        });
+    }
+
+    #[test]
+    fn requests_are_refused_once_the_quota_is_used() {
+        actix_web::rt::System::new("test").block_on(async {
+            let db = Database::new();
+            let quotas = Arc::new(Quotas::new());
+            let key = db
+                .issue_api_key(
+                    ApiKeyOwner::User(100),
+                    ApiKeyLimits {
+                        requests_per_minute: 100,
+                        requests_per_day: 2,
+                    },
+                )
+                .await;
+
+            for remaining in ["1", "0"] {
+                let res = get(&db, &quotas, Some(&key)).await;
+                assert_eq!(res.status(), StatusCode::OK);
+                assert_eq!(header(&res, "ratelimit-limit"), "2");
+                assert_eq!(header(&res, "ratelimit-remaining"), remaining);
+                assert_eq!(header(&res, "ratelimit-policy"), "100;w=60, 2;w=86400");
+                assert!(res.headers().contains_key("ratelimit-reset"));
+                assert!(!res.headers().contains_key(RETRY_AFTER));
+            }
+
+            let res = get(&db, &quotas, Some(&key)).await;
+            assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+            assert_eq!(header(&res, "ratelimit-remaining"), "0");
+            assert_eq!(header(&res, "retry-after"), header(&res, "ratelimit-reset"));
+        });
+    }
+}
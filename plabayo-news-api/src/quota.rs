@@ -0,0 +1,210 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Tracking of the API usage per key, enforcing their rate limit and daily quota.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use plabayo_news_data::models::{ApiKey, ApiKeyID};
+
+const MINUTE: u64 = 60;
+const DAY: u64 = 24 * 60 * MINUTE;
+
+/// The usage of all API keys, kept in memory of the API service.
+#[derive(Default)]
+pub struct Quotas {
+    usage: Mutex<HashMap<ApiKeyID, Usage>>,
+}
+
+#[derive(Default)]
+struct Usage {
+    minute: u64,
+    minute_count: u32,
+    day: u64,
+    day_count: u32,
+}
+
+/// The state of the most restrictive limit of a key,
+/// as to be communicated using the `RateLimit-*` headers.
+#[derive(Debug)]
+pub struct QuotaStatus {
+    /// The maximum amount of requests in the current window.
+    pub limit: u32,
+    /// The amount of requests remaining in the current window.
+    pub remaining: u32,
+    /// Seconds until the current window resets.
+    pub reset: u64,
+    /// True in case the request was refused as a limit is exceeded.
+    pub exceeded: bool,
+    /// All limits of the key, in the format of the `RateLimit-Policy` header.
+    pub policy: String,
+}
+
+impl Quotas {
+    pub fn new() -> Quotas {
+        Quotas::default()
+    }
+
+    /// Consume a single request for the given key,
+    /// unless its rate limit or daily quota is already exceeded.
+    pub fn consume(&self, key: &ApiKey, now: SystemTime) -> QuotaStatus {
+        let secs = now
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let limits = key.limits;
+
+        let mut usage = self.usage.lock().unwrap();
+        let usage = usage.entry(key.id).or_default();
+        if usage.minute != secs / MINUTE {
+            usage.minute = secs / MINUTE;
+            usage.minute_count = 0;
+        }
+        if usage.day != secs / DAY {
+            usage.day = secs / DAY;
+            usage.day_count = 0;
+        }
+
+        let exceeded = usage.minute_count >= limits.requests_per_minute
+            || usage.day_count >= limits.requests_per_day;
+        if !exceeded {
+            usage.minute_count += 1;
+            usage.day_count += 1;
+        }
+
+        let minute = (
+            limits.requests_per_minute,
+            limits
+                .requests_per_minute
+                .saturating_sub(usage.minute_count),
+            MINUTE - secs % MINUTE,
+        );
+        let day = (
+            limits.requests_per_day,
+            limits.requests_per_day.saturating_sub(usage.day_count),
+            DAY - secs % DAY,
+        );
+        // report the window with the least remaining requests,
+        // or the one resetting last in case both are exhausted
+        let (limit, remaining, reset) = if day.1 < minute.1 || day.1 == 0 {
+            day
+        } else {
+            minute
+        };
+
+        QuotaStatus {
+            limit,
+            remaining,
+            reset,
+            exceeded,
+            policy: format!(
+                "{};w={}, {};w={}",
+                limits.requests_per_minute, MINUTE, limits.requests_per_day, DAY
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use plabayo_news_data::models::{ApiKeyLimits, ApiKeyOwner};
+
+    use super::*;
+
+    fn key(requests_per_minute: u32, requests_per_day: u32) -> ApiKey {
+        ApiKey {
+            id: 1,
+            prefix: "pn_test".to_owned(),
+            hash: String::new(),
+            owner: ApiKeyOwner::User(100),
+            limits: ApiKeyLimits {
+                requests_per_minute,
+                requests_per_day,
+            },
+            create_time: UNIX_EPOCH,
+            revoke_time: None,
+        }
+    }
+
+    /// The given amount of seconds into the 100th day since the epoch.
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(100 * DAY + secs)
+    }
+
+    #[test]
+    fn rate_limit_resets_every_minute() {
+        let quotas = Quotas::new();
+        let key = key(2, 100);
+
+        let status = quotas.consume(&key, at(10));
+        assert!(!status.exceeded);
+        assert_eq!((status.limit, status.remaining, status.reset), (2, 1, 50));
+        assert_eq!(status.policy, "2;w=60, 100;w=86400");
+
+        let status = quotas.consume(&key, at(20));
+        assert!(!status.exceeded);
+        assert_eq!((status.limit, status.remaining, status.reset), (2, 0, 40));
+
+        let status = quotas.consume(&key, at(59));
+        assert!(status.exceeded);
+        assert_eq!((status.limit, status.remaining, status.reset), (2, 0, 1));
+
+        let status = quotas.consume(&key, at(60));
+        assert!(!status.exceeded);
+        assert_eq!((status.limit, status.remaining, status.reset), (2, 1, 60));
+    }
+
+    #[test]
+    fn daily_quota_resets_every_day() {
+        let quotas = Quotas::new();
+        let key = key(10, 2);
+
+        let status = quotas.consume(&key, at(0));
+        assert!(!status.exceeded);
+        assert_eq!((status.limit, status.remaining, status.reset), (2, 1, DAY));
+        assert!(!quotas.consume(&key, at(MINUTE)).exceeded);
+
+        // a new minute does not reset the daily quota
+        let status = quotas.consume(&key, at(2 * MINUTE));
+        assert!(status.exceeded);
+        assert_eq!(
+            (status.limit, status.remaining, status.reset),
+            (2, 0, DAY - 2 * MINUTE)
+        );
+
+        let status = quotas.consume(&key, at(DAY));
+        assert!(!status.exceeded);
+        assert_eq!((status.limit, status.remaining), (2, 1));
+    }
+
+    #[test]
+    fn keys_are_limited_separately() {
+        let quotas = Quotas::new();
+        let first = key(1, 100);
+        let second = ApiKey {
+            id: 2,
+            ..key(1, 100)
+        };
+
+        assert!(!quotas.consume(&first, at(0)).exceeded);
+        assert!(quotas.consume(&first, at(1)).exceeded);
+        assert!(!quotas.consume(&second, at(1)).exceeded);
+    }
+}
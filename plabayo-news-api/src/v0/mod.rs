@@ -0,0 +1,158 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Version 0 of the read-only Plabayo News API,
+//! giving access to all public content: submissions, comments and public profiles.
+
+use std::time::Duration;
+
+use actix_web::dev::{BodyEncoding, HttpServiceFactory};
+use actix_web::error::ErrorNotFound;
+use actix_web::http::header::{ContentEncoding, CACHE_CONTROL};
+use actix_web::rt::time::delay_for;
+use actix_web::web::Bytes;
+use actix_web::{web, Error, HttpRequest, HttpResponse, Result};
+use futures::stream::{self, StreamExt};
+use serde::Deserialize;
+
+use plabayo_news_data::models::{self as data, ActionID, ItemID, ItemState, UserID};
+use plabayo_news_data::Database;
+
+mod models;
+
+pub use models::{Item, Updates, User};
+
+/// Maximum amount of item ids returned by the story listings.
+const MAX_STORIES: usize = 500;
+
+/// Maximum (and default) amount of actions a single `/updates` response covers.
+const MAX_UPDATES: usize = 1000;
+
+/// Interval at which a comment is sent on an idle updates stream,
+/// to keep the connection from being closed by proxies.
+const STREAM_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+pub fn factory() -> impl HttpServiceFactory + 'static {
+    web::scope("/v0")
+        .route("/item/{id}", web::get().to(get_item))
+        .route("/user/{id}", web::get().to(get_user))
+        .route("/topstories", web::get().to(get_top_stories))
+        .route("/newstories", web::get().to(get_new_stories))
+        .route("/askstories", web::get().to(get_ask_stories))
+        .route("/updates", web::get().to(get_updates))
+        .route("/updates/stream", web::get().to(stream_updates))
+}
+
+async fn get_item(db: web::Data<Database>, id: web::Path<ItemID>) -> Result<HttpResponse> {
+    let item = db
+        .get_item(id.into_inner(), None)
+        .await
+        .ok_or_else(|| ErrorNotFound("item not found"))?;
+    let author = db.get_user(item.by).await;
+    Ok(HttpResponse::Ok().json(Item::from_data(item, author.as_ref())))
+}
+
+async fn get_user(db: web::Data<Database>, id: web::Path<UserID>) -> Result<HttpResponse> {
+    match db.get_user(id.into_inner()).await.and_then(User::from_data) {
+        Some(user) => Ok(HttpResponse::Ok().json(user)),
+        None => Err(ErrorNotFound("user not found")),
+    }
+}
+
+async fn get_top_stories(db: web::Data<Database>) -> HttpResponse {
+    story_ids(db.get_news_ranked(None).await)
+}
+
+async fn get_new_stories(db: web::Data<Database>) -> HttpResponse {
+    story_ids(db.get_news_newest(None).await)
+}
+
+async fn get_ask_stories(db: web::Data<Database>) -> HttpResponse {
+    story_ids(db.get_ask_ranked(None).await)
+}
+
+fn story_ids(items: Vec<data::Item>) -> HttpResponse {
+    let ids: Vec<ItemID> = items
+        .into_iter()
+        .filter(|item| matches!(item.state, ItemState::Alive))
+        .take(MAX_STORIES)
+        .map(|item| item.id)
+        .collect();
+    HttpResponse::Ok().json(ids)
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdatesQuery {
+    /// Cursor of the last update received, starting from the first action when omitted.
+    after: Option<ActionID>,
+    limit: Option<usize>,
+}
+
+/// The items and profiles changed after the given cursor.
+async fn get_updates(db: web::Data<Database>, query: web::Query<UpdatesQuery>) -> HttpResponse {
+    let after = query.after.unwrap_or_default();
+    let limit = query.limit.unwrap_or(MAX_UPDATES).clamp(1, MAX_UPDATES);
+    // fetch one extra action to know whether more are available
+    let mut actions = db.get_actions(after, limit + 1).await;
+    let more = actions.len() > limit;
+    actions.truncate(limit);
+
+    let mut updates = Updates::from_actions(after, &actions);
+    updates.more = more;
+    HttpResponse::Ok().json(updates)
+}
+
+/// A Server-Sent Events stream of the items and profiles changed after the given cursor,
+/// first those already logged and afterwards as they happen. The ID of each event is its cursor,
+/// such that a reconnecting client resumes where it left off using the `Last-Event-ID` header.
+async fn stream_updates(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    query: web::Query<UpdatesQuery>,
+) -> HttpResponse {
+    let after = req
+        .headers()
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .or(query.after)
+        .unwrap_or_default();
+
+    let (logged, live) = db.subscribe_actions(after).await;
+    let events = stream::iter(logged)
+        .chain(live)
+        .map(|action| Ok::<_, Error>(update_event(&action)));
+    let keep_alive = stream::unfold((), |_| async {
+        delay_for(STREAM_KEEP_ALIVE).await;
+        Some((Ok(Bytes::from_static(b": keep-alive\n\n")), ()))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .set_header(CACHE_CONTROL, "no-cache")
+        // compression would buffer the events
+        .encoding(ContentEncoding::Identity)
+        .streaming(stream::select(events, Box::pin(keep_alive)))
+}
+
+fn update_event(action: &data::Action) -> Bytes {
+    let updates = Updates::from_actions(action.id, std::slice::from_ref(action));
+    let data = serde_json::to_string(&updates).unwrap_or_default();
+    Bytes::from(format!(
+        "id: {}\nevent: update\ndata: {}\n\n",
+        action.id, data
+    ))
+}
@@ -0,0 +1,211 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use plabayo_news_data::models::{
+    self as data, ActionID, ItemID, ItemKind, ItemState, UserID, UserState,
+};
+
+/// API Representation of an item,
+/// of which only the id, kind and relations are kept once it is deleted.
+#[derive(Debug, Serialize)]
+pub struct Item {
+    pub id: ItemID,
+    #[serde(skip_serializing_if = "is_false")]
+    pub deleted: bool,
+    /// Locked items are hidden from the listings,
+    /// but can still be accessed directly.
+    #[serde(skip_serializing_if = "is_false")]
+    pub locked: bool,
+    /// One of `story`, `question` or `comment`.
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// The author, left out in case their profile can't be seen.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub by: Option<UserID>,
+    /// Creation time, in seconds since the unix epoch.
+    pub time: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub votes: Option<i64>,
+    /// Markdown-formatted text of a comment or question.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<ItemID>,
+    pub kids: Vec<ItemID>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+impl Item {
+    /// Create the representation of an item, written by the given author (if known).
+    pub fn from_data(item: data::Item, author: Option<&data::User>) -> Item {
+        let kind = match item.kind {
+            ItemKind::Story => "story",
+            ItemKind::Question => "question",
+            ItemKind::Comment => "comment",
+        };
+        let time = unix_time(item.time);
+        match item.state {
+            ItemState::Deleted => Item {
+                id: item.id,
+                deleted: true,
+                locked: false,
+                kind,
+                by: None,
+                time,
+                votes: None,
+                text: None,
+                parent: item.parent,
+                kids: item.kids,
+                url: None,
+                title: None,
+            },
+            state => Item {
+                id: item.id,
+                deleted: false,
+                locked: matches!(state, ItemState::Locked),
+                kind,
+                by: author
+                    .filter(|author| User::is_visible(author))
+                    .map(|author| author.id),
+                time,
+                votes: Some(item.votes),
+                text: item.text,
+                parent: item.parent,
+                kids: item.kids,
+                url: item.url,
+                title: item.title,
+            },
+        }
+    }
+}
+
+/// API Representation of a public user profile,
+/// of which only the id and submissions are kept once it is deleted.
+#[derive(Debug, Serialize)]
+pub struct User {
+    pub id: UserID,
+    #[serde(skip_serializing_if = "is_false")]
+    pub deleted: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    /// Creation time, in seconds since the unix epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub karma: Option<i64>,
+    /// Markdown-formatted self-description of the user.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub about: Option<String>,
+    pub submitted: Vec<ItemID>,
+}
+
+impl User {
+    /// Returns true if the profile of the user can be seen (see [`User::from_data`]),
+    /// such that they can be named as the author of their items.
+    pub fn is_visible(user: &data::User) -> bool {
+        matches!(user.state, UserState::Public | UserState::Deleted)
+    }
+
+    /// Create the public representation of a user,
+    /// `None` in case the user's profile isn't public.
+    pub fn from_data(user: data::User) -> Option<User> {
+        match user.state {
+            UserState::Public => Some(User {
+                id: user.id,
+                deleted: false,
+                username: user.username,
+                name: user.name,
+                location: user.location,
+                created: Some(unix_time(user.create_time)),
+                karma: Some(user.karma),
+                about: user.about,
+                submitted: user.items,
+            }),
+            UserState::Deleted => Some(User {
+                id: user.id,
+                deleted: true,
+                username: None,
+                name: None,
+                location: None,
+                created: None,
+                karma: None,
+                about: None,
+                submitted: user.items,
+            }),
+            UserState::Hidden | UserState::Locked => None,
+        }
+    }
+}
+
+/// API Representation of the changes found in (a part of) the action log,
+/// listing each changed item and profile only once.
+#[derive(Debug, Serialize)]
+pub struct Updates {
+    pub items: Vec<ItemID>,
+    pub profiles: Vec<UserID>,
+    /// ID of the last action included, to be used as cursor
+    /// to resume from, or the given cursor in case no action is included.
+    pub cursor: ActionID,
+    /// True in case more actions are logged after the cursor.
+    #[serde(skip_serializing_if = "is_false")]
+    pub more: bool,
+}
+
+impl Updates {
+    pub fn from_actions(after: ActionID, actions: &[data::Action]) -> Updates {
+        let mut items = Vec::new();
+        let mut profiles = Vec::new();
+        for action in actions {
+            if let Some(id) = action.item() {
+                if !items.contains(&id) {
+                    items.push(id);
+                }
+            }
+            if let Some(id) = action.user() {
+                if !profiles.contains(&id) {
+                    profiles.push(id);
+                }
+            }
+        }
+        Updates {
+            items,
+            profiles,
+            cursor: actions.last().map(|action| action.id).unwrap_or(after),
+            more: false,
+        }
+    }
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
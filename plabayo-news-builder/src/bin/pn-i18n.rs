@@ -0,0 +1,216 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use structopt::StructOpt;
+
+use plabayo_news_builder::i18n::coverage::SourceLock;
+use plabayo_news_builder::i18n::locales::{self, Locales, Storage, TypedValuePathPair};
+use plabayo_news_builder::i18n::{config, lint, po};
+
+/// Tooling for translators of the Plabayo News locale files.
+#[derive(StructOpt, Debug)]
+#[structopt(name = "pn-i18n")]
+struct Opt {
+    /// Cargo.toml of the package containing the i18n config
+    #[structopt(short, long, default_value = "./plabayo-news-web/Cargo.toml")]
+    manifest: PathBuf,
+
+    #[structopt(subcommand)]
+    cmd: Command,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// list the strings of the default locale which are not translated for the given locale
+    Missing { locale: String },
+    /// create a locale file containing all strings, using the default locale's text for missing ones
+    Scaffold {
+        locale: String,
+        /// file to write to instead of STDOUT
+        #[structopt(short, long)]
+        out: Option<PathBuf>,
+    },
+    /// lint all locale files for structure mismatches, placeholders and invalid markdown
+    Lint,
+    /// export a locale as a gettext PO file
+    Export {
+        locale: String,
+        /// file to write to instead of STDOUT
+        #[structopt(short, long)]
+        out: Option<PathBuf>,
+    },
+    /// import a gettext PO file on top of a locale, creating the resulting locale file
+    Import {
+        locale: String,
+        /// the PO file to import
+        po: PathBuf,
+        /// file to write to instead of STDOUT
+        #[structopt(short, long)]
+        out: Option<PathBuf>,
+    },
+    /// record the source text of all translated strings in the lock file,
+    /// such that they are no longer reported as outdated once their translation changed
+    Lock,
+}
+
+/// The locales as configured in the manifest.
+struct Project {
+    locales_path: String,
+    locales: Vec<String>,
+    lock_path: Option<String>,
+}
+
+fn main() -> Result<()> {
+    let opt = Opt::from_args();
+
+    let manifest = opt.manifest.to_string_lossy();
+    let cfg = config::load(&manifest)?;
+    let root = opt.manifest.parent().unwrap_or_else(|| Path::new("."));
+    let project = Project {
+        locales_path: root.join(&cfg.path).to_string_lossy().into_owned(),
+        locales: cfg.locales,
+        lock_path: cfg
+            .lock
+            .map(|lock| root.join(lock).to_string_lossy().into_owned()),
+    };
+
+    match opt.cmd {
+        Command::Missing { locale } => missing(&project, &locale),
+        Command::Scaffold { locale, out } => scaffold(&project, &locale, out),
+        Command::Lint => lint(&project),
+        Command::Export { locale, out } => export(&project, &locale, out),
+        Command::Import { locale, po, out } => import(&project, &locale, &po, out),
+        Command::Lock => lock(&project),
+    }
+}
+
+fn missing(project: &Project, locale: &str) -> Result<()> {
+    let default = load_default(project)?;
+    let translations = load(project, locale)?;
+    let translations: HashMap<_, _> = translations
+        .sources()
+        .map(|pair| (pair.path, pair.value))
+        .collect();
+    for pair in default.sources() {
+        match translations.get(&pair.path) {
+            Some(value) if value.same_kind(&pair.value) => (),
+            _ => println!("{}", pair.path.join(".")),
+        }
+    }
+    Ok(())
+}
+
+fn scaffold(project: &Project, locale: &str, out: Option<PathBuf>) -> Result<()> {
+    let default = load_default(project)?;
+    let translations = load(project, locale)?;
+    let mut translations: HashMap<_, _> = translations
+        .sources()
+        .map(|pair| (pair.path, pair.value))
+        .collect();
+    let values = default.sources().map(|pair| {
+        let value = match translations.remove(&pair.path) {
+            Some(value) if value.same_kind(&pair.value) => value,
+            _ => pair.value,
+        };
+        TypedValuePathPair {
+            value,
+            path: pair.path,
+        }
+    });
+    let content = serde_yaml::to_string(&locales::to_yaml(values))?;
+    write_output(out, content.as_bytes())
+}
+
+fn lint(project: &Project) -> Result<()> {
+    let storage = Storage::load(&project.locales_path, &project.locales[..])?;
+    let mut issues = lint::structure(&storage)?;
+    issues.extend(lint::markdown(&storage)?);
+    if let Err(err) = storage.check_placeholders() {
+        issues.push(err.to_string());
+    }
+    for issue in issues.iter() {
+        println!("{}", issue);
+    }
+    if issues.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "found {} issue(s) in the locale files",
+            issues.len()
+        ))
+    }
+}
+
+fn export(project: &Project, locale: &str, out: Option<PathBuf>) -> Result<()> {
+    let default = load_default(project)?;
+    let translations = load(project, locale)?;
+    let mut content = Vec::new();
+    po::export(&mut content, locale, &default, &translations)?;
+    write_output(out, &content)
+}
+
+fn import(project: &Project, locale: &str, po_path: &Path, out: Option<PathBuf>) -> Result<()> {
+    let default = load_default(project)?;
+    let translations = load(project, locale)?;
+    let po_content = std::fs::read_to_string(po_path)
+        .with_context(|| format!("read PO file {}", po_path.display()))?;
+    let value = po::import(&po_content, &default, &translations)?;
+    let content = serde_yaml::to_string(&value)?;
+    write_output(out, content.as_bytes())
+}
+
+fn lock(project: &Project) -> Result<()> {
+    let path = project
+        .lock_path
+        .as_deref()
+        .ok_or_else(|| anyhow!("no lock file configured"))?;
+    let storage = Storage::load(&project.locales_path, &project.locales[..])?;
+    SourceLock::load(path)?.update(&storage)?.store(path)
+}
+
+fn load_default(project: &Project) -> Result<Locales> {
+    let locale = project
+        .locales
+        .first()
+        .ok_or_else(|| anyhow!("no locales configured"))?;
+    Locales::load(&project.locales_path, locale)
+}
+
+/// Load the strings of the given locale, which are empty for a locale without file.
+fn load(project: &Project, locale: &str) -> Result<Locales> {
+    if Locales::path(&project.locales_path, locale).exists() {
+        Locales::load(&project.locales_path, locale)
+    } else {
+        Ok(Locales::default())
+    }
+}
+
+fn write_output(out: Option<PathBuf>, content: &[u8]) -> Result<()> {
+    match out {
+        Some(path) => File::create(&path)
+            .and_then(|mut file| file.write_all(content))
+            .with_context(|| format!("write {}", path.display())),
+        None => std::io::stdout()
+            .write_all(content)
+            .context("write to STDOUT"),
+    }
+}
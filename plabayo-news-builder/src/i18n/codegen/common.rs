@@ -39,3 +39,12 @@ pub fn generate_copyright_file_header(mut w: impl std::io::Write) -> Result<()>
 
     Ok(())
 }
+
+/// Wrap a string as a raw string literal, such that it can be
+/// embedded as-is in generated rust code.
+pub fn raw_str_literal(s: &str) -> String {
+    format!(
+        r#################"r################"{}"################"#################,
+        s
+    )
+}
@@ -21,8 +21,11 @@ use anyhow::{anyhow, Context, Result};
 use convert_case::{Case, Casing};
 use itertools::Itertools;
 
-use crate::i18n::codegen::common::generate_copyright_file_header;
-use crate::i18n::locales::{Storage, StringValuePathPair};
+use crate::i18n::codegen::common::{generate_copyright_file_header, raw_str_literal};
+use crate::i18n::codegen::plurals::{generate_plural_types, plural_rule_code, plural_string_code};
+use crate::i18n::locales::{
+    placeholder_type, LocaleValue, LocaleValuePathPair, Storage, StringValuePathPair,
+};
 
 pub fn generate_locales(file_path: &Path, storage: &Storage) -> Result<()> {
     let file = File::create(file_path)
@@ -53,33 +56,49 @@ pub fn generate_locales(file_path: &Path, storage: &Storage) -> Result<()> {
         )
     })?;
 
-    let default_pairs: Vec<StringValuePathPair> = default_locales.iter().collect();
+    generate_plural_types(&file).with_context(|| {
+        format!(
+            "generate plural types definition and its methods in {}",
+            file_path.display()
+        )
+    })?;
 
-    generate_locales_strings_struct(
-        &file,
-        default_pairs.iter().map(|p| p.path.clone()).collect(),
-    )
-    .with_context(|| {
+    generate_safe_type(&file).with_context(|| {
         format!(
-            "generate locales strings struct (def) in {}",
+            "generate safe type definition and its methods in {}",
             file_path.display()
         )
     })?;
 
-    generate_locales_strings_struct_methods(&file, storage).with_context(|| {
+    generate_interpolate_fn(&file).with_context(|| {
         format!(
-            "generate locales string struct (utility) methods in {}",
+            "generate interpolate function for strings with placeholders in {}",
             file_path.display()
         )
     })?;
 
+    let default_pairs: Vec<LocaleValuePathPair> = default_locales.iter().collect();
+
+    generate_locales_strings_struct(&file, default_pairs.clone()).with_context(|| {
+        format!(
+            "generate locales strings struct (def) in {}",
+            file_path.display()
+        )
+    })?;
+
+    generate_locales_strings_struct_methods(&file, storage, &default_pairs[..]).with_context(
+        || {
+            format!(
+                "generate locales string struct (utility) methods in {}",
+                file_path.display()
+            )
+        },
+    )?;
+
     let default_pairs_stringified: Vec<StringValuePathPair> = default_pairs
         .iter()
         .map(|p| StringValuePathPair {
-            value: format!(
-                r#################"r################"{}"################"#################,
-                p.value
-            ),
+            value: value_code(storage.default_locale(), &p.value),
             path: p.path.clone(),
         })
         .collect();
@@ -134,14 +153,19 @@ fn generate_locales_mod_docs(mut w: impl std::io::Write) -> Result<()> {
 }
 
 fn generate_locales_enum(mut w: impl std::io::Write, storage: &Storage) -> Result<()> {
-    // 1. generate enum type
+    let default_locale = storage.default_locale();
+
+    // 1. generate enum type, deriving its default locale
 
     w.write_all(
-        b"#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, std::hash::Hash)]
+        b"#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, std::hash::Hash)]
 pub enum Locale {
 ",
     )?;
     for locale in storage.all_locales() {
+        if locale == default_locale {
+            w.write_all(b"    #[default]\n")?;
+        }
         w.write_all(
             format!(
                 "    {},
@@ -157,8 +181,6 @@ pub enum Locale {
 ",
     )?;
 
-    let default_locale = storage.default_locale();
-
     // 2. impl associated functions and methods on our enum type (to get strings for a locale)
 
     w.write_all(
@@ -208,6 +230,33 @@ pub enum Locale {
         b"        }
     }
 
+",
+    )?;
+
+    w.write_all(
+        b"    #[allow(unknown_lints, clippy::manual_is_multiple_of)]
+    pub fn plural_category(&self, count: i64) -> PluralCategory {
+        let n = count.unsigned_abs();
+        match self {
+",
+    )?;
+    for locale in storage.all_locales() {
+        w.write_all(
+            format!(
+                "            Self::{} => {{
+                {}
+            }}
+",
+                locale.to_case(Case::Pascal),
+                plural_rule_code(locale)?,
+            )
+            .as_bytes(),
+        )?;
+    }
+    w.write_all(
+        b"        }
+    }
+
 ",
     )?;
 
@@ -235,23 +284,7 @@ pub enum Locale {
 ",
     )?;
 
-    // 3. impl Default for Locale
-
-    w.write_all(
-        format!(
-            "impl std::default::Default for Locale {{
-    fn default() -> Self {{
-        Locale::{}
-    }}
-}}
-
-",
-            default_locale.to_case(Case::Pascal)
-        )
-        .as_bytes(),
-    )?;
-
-    // 4. impl to_string conversation, as to also support Display
+    // 3. impl to_string conversation, as to also support Display
 
     w.write_all(
         b"impl std::fmt::Display for Locale {
@@ -263,7 +296,7 @@ pub enum Locale {
 ",
     )?;
 
-    // 5. impl conversation from str, for our enum type
+    // 4. impl conversation from str, for our enum type
 
     w.write_all(
         b"impl TryFrom<&str> for Locale {
@@ -302,10 +335,10 @@ pub enum Locale {
 
 fn generate_locales_strings_struct(
     mut w: impl std::io::Write,
-    mut paths: Vec<Vec<String>>,
+    mut pairs: Vec<LocaleValuePathPair>,
 ) -> Result<()> {
     let mut layer: usize = 0;
-    while !paths.is_empty() {
+    while !pairs.is_empty() {
         if layer == 0 {
             w.write_all(
                 b"pub struct Strings {
@@ -314,8 +347,9 @@ fn generate_locales_strings_struct(
         }
         let mut previous: Option<String> = None;
         let mut previous_property: Option<String> = None;
-        let mut retained_paths = Vec::new();
-        for path in paths {
+        let mut retained_pairs = Vec::new();
+        for pair in pairs {
+            let path = &pair.path;
             // create new struct if needed
             let current = if layer == 0 {
                 None
@@ -348,15 +382,45 @@ fn generate_locales_strings_struct(
 
             // write struct property
             if drop {
-                // str
-                w.write_all(
-                    format!(
-                        "    pub {}: &'static str,
+                match &pair.value {
+                    // str
+                    LocaleValue::Text { placeholders, .. } if placeholders.is_empty() => w
+                        .write_all(
+                            format!(
+                                "    pub {}: &'static str,
 ",
-                        key.to_case(Case::Snake)
-                    )
-                    .as_bytes(),
-                )?;
+                                key.to_case(Case::Snake)
+                            )
+                            .as_bytes(),
+                        )?,
+                    // str with placeholders, exposed using an accessor method
+                    LocaleValue::Text { .. } => w.write_all(
+                        format!(
+                            "    {}: &'static str,
+",
+                            key.to_case(Case::Snake)
+                        )
+                        .as_bytes(),
+                    )?,
+                    // html rendered from markdown
+                    LocaleValue::Html { .. } => w.write_all(
+                        format!(
+                            "    pub {}: Safe,
+",
+                            key.to_case(Case::Snake)
+                        )
+                        .as_bytes(),
+                    )?,
+                    // plural str, exposed using an accessor method
+                    LocaleValue::Plural { .. } => w.write_all(
+                        format!(
+                            "    {}: PluralString,
+",
+                            key.to_case(Case::Snake)
+                        )
+                        .as_bytes(),
+                    )?,
+                }
             } else if current_property != previous_property {
                 // object
                 w.write_all(
@@ -376,12 +440,12 @@ fn generate_locales_strings_struct(
 
             // retain if we do not wish to drop
             if !drop {
-                retained_paths.push(path);
+                retained_pairs.push(pair);
             }
         }
 
         layer += 1;
-        paths = retained_paths;
+        pairs = retained_pairs;
     }
     w.write_all(
         b"}
@@ -393,6 +457,7 @@ fn generate_locales_strings_struct(
 fn generate_locales_strings_struct_methods(
     mut w: impl std::io::Write,
     storage: &Storage,
+    pairs: &[LocaleValuePathPair],
 ) -> Result<()> {
     w.write_all(
         b"
@@ -420,9 +485,140 @@ impl StringsSiteLocales {
 ",
     )?;
 
+    // accessors for all plural strings and strings with placeholders, grouped per struct
+    for (parent, pairs) in &pairs
+        .iter()
+        .filter(|pair| {
+            matches!(pair.value, LocaleValue::Plural { .. })
+                || !pair.value.placeholders().is_empty()
+        })
+        .group_by(|pair| &pair.path[..pair.path.len() - 1])
+    {
+        w.write_all(
+            format!(
+                "
+impl Strings{} {{
+",
+                parent.iter().map(|s| s.to_case(Case::Pascal)).join("")
+            )
+            .as_bytes(),
+        )?;
+        for pair in pairs {
+            let key = pair.path[pair.path.len() - 1].to_case(Case::Snake);
+            let (mut params, mut args, value) = match pair.value {
+                LocaleValue::Plural { .. } => (
+                    vec!["count: i64".to_owned()],
+                    vec!["(\"count\", &count)".to_owned()],
+                    format!("self.{}.select(count)", key),
+                ),
+                LocaleValue::Text { .. } | LocaleValue::Html { .. } => {
+                    (vec![], vec![], format!("self.{}", key))
+                }
+            };
+            let (output, body) = match pair.value {
+                LocaleValue::Plural { html: true, .. } => {
+                    ("Safe<String>", "Safe(interpolate({value}, &[{args}]))")
+                }
+                _ => ("String", "interpolate({value}, &[{args}])"),
+            };
+            for name in pair.value.placeholders() {
+                params.push(format!("{}: {}", name, placeholder_type(name)));
+                args.push(format!("(\"{name}\", &{name})", name = name));
+            }
+            let body = body
+                .replace("{value}", &value)
+                .replace("{args}", &args.join(", "));
+            w.write_all(
+                format!(
+                    "    pub fn {key}(&self, {params}) -> {output} {{
+        {body}
+    }}
+",
+                    key = key,
+                    params = params.join(", "),
+                    output = output,
+                    body = body,
+                )
+                .as_bytes(),
+            )?;
+        }
+        w.write_all(
+            b"}
+",
+        )?;
+    }
+
+    Ok(())
+}
+
+fn generate_safe_type(mut w: impl std::io::Write) -> Result<()> {
+    w.write_all(
+        b"
+/// HTML which is safe to be rendered as-is (e.g. using the Askama `safe` filter),
+/// such as the HTML rendered from the markdown strings of the trusted locale files.
+/// All other strings are to be escaped.
+pub struct Safe<T = &'static str>(T);
+
+impl<T> Safe<T> {
+    /// Mark the given HTML as safe, only to be used for HTML of a trusted source,
+    /// or user content that has been sanitized.
+    pub fn new(html: T) -> Safe<T> {
+        Safe(html)
+    }
+}
+
+impl<T: std::fmt::Display> std::fmt::Display for Safe<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+",
+    )?;
+    Ok(())
+}
+
+fn generate_interpolate_fn(mut w: impl std::io::Write) -> Result<()> {
+    w.write_all(
+        b"
+/// Replace all `{name}` placeholders in the given locale string with their value,
+/// placeholders without a given value are kept as-is.
+pub(super) fn interpolate(s: &str, args: &[(&str, &dyn std::fmt::Display)]) -> String {
+    let mut output = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let arg = rest
+            .find('}')
+            .and_then(|end| args.iter().find(|(name, _)| *name == &rest[1..end]).map(|(_, value)| (end, value)));
+        match arg {
+            Some((end, value)) => {
+                output.push_str(&value.to_string());
+                rest = &rest[end + 1..];
+            }
+            None => {
+                output.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+",
+    )?;
     Ok(())
 }
 
+/// The rust code used to construct the given value for the given locale.
+fn value_code(locale: &str, value: &LocaleValue) -> String {
+    match value {
+        LocaleValue::Text { value, .. } => raw_str_literal(value),
+        LocaleValue::Html { value } => format!("Safe({})", raw_str_literal(value)),
+        LocaleValue::Plural { value, .. } => plural_string_code(locale, value),
+    }
+}
+
 fn generate_locales_strings_instance<'a>(
     mut w: impl std::io::Write,
     const_name: &str,
@@ -522,17 +718,17 @@ const {}: Strings = Strings {{
 }
 
 struct LocaleStringWithDefaultIter<
-    T: Iterator<Item = StringValuePathPair>,
-    U: Iterator<Item = StringValuePathPair>,
+    T: Iterator<Item = LocaleValuePathPair>,
+    U: Iterator<Item = LocaleValuePathPair>,
 > {
     locale: String,
     pairs: Box<T>,
     default_pairs: Box<U>,
-    next_pair: Option<StringValuePathPair>,
-    next_default_pair: Option<StringValuePathPair>,
+    next_pair: Option<LocaleValuePathPair>,
+    next_default_pair: Option<LocaleValuePathPair>,
 }
 
-impl<T: Iterator<Item = StringValuePathPair>, U: Iterator<Item = StringValuePathPair>>
+impl<T: Iterator<Item = LocaleValuePathPair>, U: Iterator<Item = LocaleValuePathPair>>
     LocaleStringWithDefaultIter<T, U>
 {
     pub fn new(
@@ -551,14 +747,14 @@ impl<T: Iterator<Item = StringValuePathPair>, U: Iterator<Item = StringValuePath
     }
 }
 
-impl<T: Iterator<Item = StringValuePathPair>, U: Iterator<Item = StringValuePathPair>> Iterator
+impl<T: Iterator<Item = LocaleValuePathPair>, U: Iterator<Item = LocaleValuePathPair>> Iterator
     for LocaleStringWithDefaultIter<T, U>
 {
     type Item = StringValuePathPair;
 
     fn next(&mut self) -> Option<Self::Item> {
         // load the next item to render
-        match std::mem::replace(&mut self.next_default_pair, None) {
+        match self.next_default_pair.take() {
             // if there is no next default pair,
             // than we can immediately stop as it means we're finished,
             // with all possible properties,
@@ -569,7 +765,7 @@ impl<T: Iterator<Item = StringValuePathPair>, U: Iterator<Item = StringValuePath
                     // get the last peeked pair if there was one,
                     // or else get the next one, so we can start comparing
                     let pair = if self.next_pair.is_some() {
-                        std::mem::replace(&mut self.next_pair, None)
+                        self.next_pair.take()
                     } else {
                         self.pairs.next()
                     };
@@ -600,12 +796,28 @@ impl<T: Iterator<Item = StringValuePathPair>, U: Iterator<Item = StringValuePath
                     // position and thus we render the correct value
                     if pair == next_default_pair {
                         self.next_default_pair = self.default_pairs.next();
+                        // a value of another kind (e.g. a str instead of plural str) cannot be used,
+                        // and is resolved the same way as a missing value
+                        if !pair.value.same_kind(&next_default_pair.value) {
+                            let value = format!(
+                                "STRINGS_DEFAULT.{}",
+                                next_default_pair
+                                    .path
+                                    .iter()
+                                    .map(|s| s.to_case(Case::Snake))
+                                    .join("."),
+                            );
+                            eprintln!(
+                                "plabayo-news_builder: mismatched string kind for locale {locale}, resolved by using fallback: {value}; Please use the same kind of string in {locale}.yml as found in the default locale!",
+                                value=value, locale=self.locale);
+                            return Some(StringValuePathPair {
+                                path: pair.path,
+                                value,
+                            });
+                        }
                         return Some(StringValuePathPair {
+                            value: value_code(&self.locale, &pair.value),
                             path: pair.path,
-                            value: format!(
-                                r#################"r################"{}"################"#################,
-                                pair.value
-                            ),
                         });
                     }
                     // in case we have not yet reached the current next default pair,
@@ -25,6 +25,9 @@ use crate::i18n::locales::Storage;
 mod common;
 mod locales;
 mod pages;
+mod plurals;
+
+pub(crate) use plurals::plural_categories;
 
 pub fn generate_all(dir: &str, storage: &Storage, pages_cfg: &Pages) -> Result<()> {
     fs::create_dir_all(dir)?;
@@ -23,9 +23,10 @@ use itertools::Itertools;
 
 use crate::i18n::codegen::common::generate_copyright_file_header;
 use crate::i18n::config::Pages;
+use crate::i18n::rerun_if_changed;
 
 pub fn generate_pages(file_path: &Path, cfg: &Pages) -> Result<()> {
-    println!("cargo:rerun-if-changed={}", cfg.path);
+    rerun_if_changed(&cfg.path);
 
     let file = File::create(file_path)
         .with_context(|| format!("create locales rust file at {}", file_path.display()))?;
@@ -92,6 +93,7 @@ fn generate_static_pages(
 
     w.write_all(
         b"pub fn static_response(endpoint: &str, page: PageState) -> Result<HttpResponse> {
+    let locale = page.locale;
     let (mut response, render_result) = match endpoint {
 ",
     )?;
@@ -121,7 +123,10 @@ fn generate_static_pages(
     w.write_all(
         b"    };
     let s = render_result.map_err(ErrorInternalServerError)?;
-    Ok(response.content_type(\"text/html\").body(s))
+    Ok(response
+        .content_type(\"text/html\")
+        .set_header(CONTENT_LANGUAGE, locale.as_str())
+        .body(s))
 }
 
 ",
@@ -143,7 +148,7 @@ fn generate_static_pages(
         w.write_all(
             format!(
                 "#[derive(Template)]
-#[template(path = \"{dir}/{page_orig}.html\", escape = \"none\")]
+#[template(path = \"{dir}/{page_orig}.html\")]
 struct Page{page}<'a> {{
     site_info: &'a SiteInfo,
     page: PageState,
@@ -187,7 +192,7 @@ fn generate_dynamic_pages(
             format!(
                 "
 #[derive(Template)]
-#[template(path = \"{dir}/{page_orig}.html\", escape = \"none\")]
+#[template(path = \"{dir}/{page_orig}.html\")]
 pub struct Page{page}<'a> {{
     site_info: &'a SiteInfo,
     page: PageState,
@@ -195,14 +200,23 @@ pub struct Page{page}<'a> {{
 }}
 
 impl<'a> Page{page}<'a> {{
-    pub fn new_response(page: PageState, content: Content{page}) -> Result<HttpResponse> {{
-        let page = Page{page} {{
+    pub fn new(page: PageState, content: Content{page}) -> Page{page}<'a> {{
+        Page{page} {{
             site_info: &SITE_INFO,
             page,
             content,
-        }};
-        let s = page.render().map_err(ErrorInternalServerError)?;
-        Ok(HttpResponse::Ok().content_type(\"text/html\").body(s))
+        }}
+    }}
+
+    pub fn new_response(page: PageState, content: Content{page}) -> Result<HttpResponse> {{
+        let locale = page.locale;
+        let s = Page{page}::new(page, content)
+            .render()
+            .map_err(ErrorInternalServerError)?;
+        Ok(HttpResponse::Ok()
+            .content_type(\"text/html\")
+            .set_header(CONTENT_LANGUAGE, locale.as_str())
+            .body(s))
     }}
 }}
 ",
@@ -262,6 +276,7 @@ fn generate_pages_mod_docs(mut w: impl std::io::Write) -> Result<()> {
 fn generate_pages_imports(mut w: impl std::io::Write, dynamic_pages: &[String]) -> Result<()> {
     w.write_all(
         b"use actix_web::error::ErrorInternalServerError;
+use actix_web::http::header::CONTENT_LANGUAGE;
 use actix_web::{HttpResponse, Result};
 use askama::Template;
 
@@ -0,0 +1,133 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use anyhow::{bail, Result};
+use convert_case::{Case, Casing};
+
+use crate::i18n::codegen::common::raw_str_literal;
+use crate::i18n::locales::PluralValue;
+
+/// Generate the types used to select the plural variant of a locale value.
+pub fn generate_plural_types(mut w: impl std::io::Write) -> Result<()> {
+    w.write_all(
+        b"
+/// The CLDR plural categories, see
+/// <https://unicode-org.github.io/cldr-staging/charts/latest/supplemental/language_plural_rules.html>
+/// for the rules that define which category is used for a given count and language.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PluralCategory {
+    Zero,
+    One,
+    Two,
+    Few,
+    Many,
+    Other,
+}
+
+/// A locale string with a variant for one or more plural categories,
+/// the locale is kept as the variants are written for that locale's plural rules.
+pub struct PluralString {
+    locale: Locale,
+    zero: Option<&'static str>,
+    one: Option<&'static str>,
+    two: Option<&'static str>,
+    few: Option<&'static str>,
+    many: Option<&'static str>,
+    other: &'static str,
+}
+
+impl PluralString {
+    /// Select the variant to be used for the given count.
+    pub fn select(&self, count: i64) -> &'static str {
+        match self.locale.plural_category(count) {
+            PluralCategory::Zero => self.zero,
+            PluralCategory::One => self.one,
+            PluralCategory::Two => self.two,
+            PluralCategory::Few => self.few,
+            PluralCategory::Many => self.many,
+            PluralCategory::Other => None,
+        }
+        .unwrap_or(self.other)
+    }
+}
+",
+    )?;
+    Ok(())
+}
+
+/// The rust code to construct a PluralString for the given locale.
+pub fn plural_string_code(locale: &str, value: &PluralValue) -> String {
+    let variant = |v: &Option<String>| match v {
+        Some(s) => format!("Some({})", raw_str_literal(s)),
+        None => "None".to_owned(),
+    };
+    format!(
+        "PluralString {{ locale: Locale::{}, zero: {}, one: {}, two: {}, few: {}, many: {}, other: {} }}",
+        locale.to_case(Case::Pascal),
+        variant(&value.zero),
+        variant(&value.one),
+        variant(&value.two),
+        variant(&value.few),
+        variant(&value.many),
+        raw_str_literal(&value.other),
+    )
+}
+
+/// The rust code (expression) to select the plural category
+/// for an unsigned integer `n`, using the CLDR cardinal rules of the given locale.
+///
+/// Only integer counts are supported, which allows us to ignore
+/// the rules that apply to fractions. A locale without known rules is an error,
+/// as the rules of another language would select the wrong variants.
+pub fn plural_rule_code(locale: &str) -> Result<&'static str> {
+    Ok(match locale {
+        "en" | "nl" | "de" => {
+            "if n == 1 { PluralCategory::One } else { PluralCategory::Other }"
+        }
+        "es" => {
+            "if n == 1 { PluralCategory::One } else if n != 0 && n % 1_000_000 == 0 { PluralCategory::Many } else { PluralCategory::Other }"
+        }
+        "fr" => {
+            "if n <= 1 { PluralCategory::One } else if n % 1_000_000 == 0 { PluralCategory::Many } else { PluralCategory::Other }"
+        }
+        _ => bail!(
+            "no plural rules known for locale {}; please add them to plabayo-news-builder's plurals module",
+            locale
+        ),
+    })
+}
+
+/// The plural categories used by the rules of the given locale,
+/// as selected by the code of [`plural_rule_code`].
+pub fn plural_categories(locale: &str) -> &'static [&'static str] {
+    match locale {
+        "es" | "fr" => &["one", "many", "other"],
+        _ => &["one", "other"],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locales_without_plural_rules_are_refused() {
+        for locale in ["en", "nl", "de", "es", "fr"] {
+            assert!(plural_rule_code(locale).is_ok(), "{}", locale);
+        }
+        assert!(plural_rule_code("pl").is_err());
+    }
+}
@@ -18,6 +18,8 @@ use anyhow::Result;
 use serde::Deserialize;
 use toml;
 
+use super::rerun_if_changed;
+
 #[derive(Deserialize)]
 struct CargoConfig {
     package: PackageConfig,
@@ -38,6 +40,15 @@ pub struct I18n {
     pub locales: Vec<String>,
     pub path: String,
     pub out: String,
+    /// Path of the JSON translation coverage report,
+    /// defaults to `coverage.json` within the `out` directory.
+    pub report: Option<String>,
+    /// Path of the lock file used to detect translations
+    /// whose source text changed since they were translated.
+    pub lock: Option<String>,
+    /// Minimum coverage percentage required for each locale,
+    /// failing the build when a locale is below it.
+    pub strict: Option<f64>,
     pub pages: Pages,
 }
 
@@ -52,6 +63,8 @@ pub struct Pages {
 
 /// Load the i18n config from the package's Cargo.toml metadata.
 pub fn load(cargo_toml: &str) -> Result<I18n> {
+    rerun_if_changed(cargo_toml);
+
     let content = std::fs::read_to_string(cargo_toml)?;
     let cfg: CargoConfig = toml::from_str(&content)?;
     Ok(cfg.package.metadata.i18n)
@@ -0,0 +1,323 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::locales::{LocaleValue, Storage};
+use super::rerun_if_changed;
+
+/// The translation coverage of all non-default locales,
+/// compared to the default locale which acts as the source for all translations.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub default_locale: String,
+    /// Amount of strings defined by the default locale.
+    pub total: usize,
+    pub locales: BTreeMap<String, LocaleCoverage>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LocaleCoverage {
+    /// Percentage of the default locale's strings that are translated
+    /// and for which the translation is still up to date.
+    pub coverage: f64,
+    pub translated: usize,
+    /// Strings of the default locale that have no translation.
+    pub missing: Vec<String>,
+    /// Strings that do not (or no longer) exist in the default locale.
+    pub orphaned: Vec<String>,
+    /// Strings whose source text changed since they were translated.
+    pub outdated: Vec<String>,
+}
+
+impl Report {
+    /// Create the coverage report for the given storage,
+    /// using the lock to detect outdated translations.
+    pub fn new(storage: &Storage, lock: &SourceLock) -> Result<Report> {
+        compare(storage, lock).map(|(report, _)| report)
+    }
+
+    /// Write the report as JSON to the given path.
+    pub fn write(&self, path: &str) -> Result<()> {
+        if let Some(dir) = Path::new(path).parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("create directory for coverage report {}", path))?;
+        }
+        let file =
+            File::create(path).with_context(|| format!("create coverage report {}", path))?;
+        serde_json::to_writer_pretty(file, self)
+            .with_context(|| format!("write coverage report {}", path))
+    }
+
+    /// Fail if the coverage of any locale is below the given percentage.
+    pub fn check(&self, threshold: f64) -> Result<()> {
+        let failures: Vec<String> = self
+            .locales
+            .iter()
+            .filter(|(_, c)| c.coverage < threshold)
+            .map(|(locale, c)| {
+                format!(
+                    "{}.yml: {}% ({} missing, {} outdated)",
+                    locale,
+                    c.coverage,
+                    c.missing.len(),
+                    c.outdated.len()
+                )
+            })
+            .collect();
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "translation coverage below strict threshold of {}%:\n{}",
+                threshold,
+                failures.join("\n")
+            ))
+        }
+    }
+}
+
+/// Compare the translations of the storage with the default locale,
+/// returning the coverage report as well as the lock updated with
+/// the source text of any new or changed translations.
+fn compare(storage: &Storage, lock: &SourceLock) -> Result<(Report, SourceLock)> {
+    let default_locale = storage.default_locale();
+    let sources: HashMap<String, LocaleValue> = storage
+        .get_default()
+        .ok_or_else(|| anyhow!("failed to get default locale in i18n storage"))?
+        .iter()
+        .map(|pair| (pair.path.join("."), pair.value))
+        .collect();
+
+    let mut new_lock = SourceLock::default();
+
+    let mut locales = BTreeMap::new();
+    for locale in storage
+        .all_locales()
+        .filter(|locale| locale != &default_locale)
+    {
+        let values = storage
+            .get(locale)
+            .ok_or_else(|| anyhow!("failed to get strings for locale {}", locale))?;
+        let old_entries = lock.0.get(locale);
+        let mut new_entries = BTreeMap::new();
+
+        let mut found = HashSet::new();
+        let mut orphaned = Vec::new();
+        let mut outdated = Vec::new();
+        for pair in values.iter() {
+            let key = pair.path.join(".");
+            match sources.get(&key) {
+                Some(source) if source.same_kind(&pair.value) => {
+                    let entry = LockEntry {
+                        source: fingerprint(source),
+                        translation: fingerprint(&pair.value),
+                    };
+                    match old_entries.and_then(|entries| entries.get(&key)) {
+                        Some(old)
+                            if old.translation == entry.translation
+                                && old.source != entry.source =>
+                        {
+                            outdated.push(key.clone());
+                            new_entries.insert(key.clone(), old.clone());
+                        }
+                        _ => {
+                            new_entries.insert(key.clone(), entry);
+                        }
+                    }
+                    found.insert(key);
+                }
+                Some(_) => (), // a value of another kind is as good as missing
+                None => orphaned.push(key),
+            }
+        }
+
+        let mut missing: Vec<String> = sources
+            .keys()
+            .filter(|key| !found.contains(*key))
+            .cloned()
+            .collect();
+        missing.sort();
+
+        let translated = found.len();
+        let coverage = if sources.is_empty() {
+            100.0
+        } else {
+            let up_to_date = (translated - outdated.len()) as f64;
+            (up_to_date * 1000.0 / sources.len() as f64).round() / 10.0
+        };
+
+        new_lock.0.insert(locale.to_owned(), new_entries);
+        locales.insert(
+            locale.to_owned(),
+            LocaleCoverage {
+                coverage,
+                translated,
+                missing,
+                orphaned,
+                outdated,
+            },
+        );
+    }
+
+    let report = Report {
+        default_locale: default_locale.to_owned(),
+        total: sources.len(),
+        locales,
+    };
+    Ok((report, new_lock))
+}
+
+/// The fingerprints of the source text and translation of each translated string,
+/// as they were when the string was last translated, per locale.
+///
+/// The lock is only read by the build, and updated by translators
+/// (using `pn-i18n lock`) once they translated the strings.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SourceLock(BTreeMap<String, BTreeMap<String, LockEntry>>);
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct LockEntry {
+    source: String,
+    translation: String,
+}
+
+impl SourceLock {
+    pub fn load(path: &str) -> Result<SourceLock> {
+        rerun_if_changed(path);
+
+        if !Path::new(path).exists() {
+            return Ok(SourceLock::default());
+        }
+        let file = File::open(path).with_context(|| format!("open i18n lock file {}", path))?;
+        serde_yaml::from_reader(file).with_context(|| format!("load i18n lock file {}", path))
+    }
+
+    /// The lock updated with the source text of any new or changed translations of the storage,
+    /// marking them as up to date.
+    pub fn update(&self, storage: &Storage) -> Result<SourceLock> {
+        compare(storage, self).map(|(_, lock)| lock)
+    }
+
+    /// Store the lock, only touching the file if its content changed.
+    pub fn store(&self, path: &str) -> Result<()> {
+        let content = serde_yaml::to_string(self)
+            .with_context(|| format!("serialize i18n lock file {}", path))?;
+        if std::fs::read_to_string(path).ok().as_deref() == Some(content.as_str()) {
+            return Ok(());
+        }
+        std::fs::write(path, content).with_context(|| format!("write i18n lock file {}", path))
+    }
+}
+
+/// A stable (FNV-1a) hash of all text variants of a value,
+/// such that the lock file doesn't have to contain the text itself.
+fn fingerprint(value: &LocaleValue) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for (i, variant) in value.variants().enumerate() {
+        if i > 0 {
+            hash = (hash ^ 0xff).wrapping_mul(0x0100_0000_01b3);
+        }
+        for byte in variant.bytes() {
+            hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFAULT: &str = r#"
+page:
+  title: "Plabayo News"
+  about: "About us"
+time:
+  hours:
+    plural:
+      one: "{count} hour ago"
+      other: "{count} hours ago"
+"#;
+
+    const TRANSLATIONS: &str = r#"
+page:
+  title: "Plabayo Nieuws"
+  login: "Aanmelden"
+time:
+  hours:
+    plural:
+      one: "{count} uur geleden"
+      other: "{count} uren geleden"
+"#;
+
+    fn storage(default: &str, translations: &str) -> Storage {
+        Storage::from_yaml(&[("en", default), ("nl", translations)]).unwrap()
+    }
+
+    fn coverage(report: &Report) -> &LocaleCoverage {
+        &report.locales["nl"]
+    }
+
+    #[test]
+    fn missing_and_orphaned_strings_are_reported() {
+        let report = Report::new(&storage(DEFAULT, TRANSLATIONS), &SourceLock::default()).unwrap();
+        assert_eq!(report.total, 3);
+        let nl = coverage(&report);
+        assert_eq!(nl.translated, 2);
+        assert_eq!(nl.missing, ["page.about"]);
+        assert_eq!(nl.orphaned, ["page.login"]);
+        assert!(nl.outdated.is_empty());
+        assert_eq!(nl.coverage, 66.7);
+
+        // a plural translated as a single string is as good as missing
+        let single = TRANSLATIONS.replace(
+            "    plural:\n      one: \"{count} uur geleden\"\n      other: \"{count} uren geleden\"",
+            "    \"uren geleden\"",
+        );
+        let report = Report::new(&storage(DEFAULT, &single), &SourceLock::default()).unwrap();
+        assert_eq!(coverage(&report).missing, ["page.about", "time.hours"]);
+    }
+
+    #[test]
+    fn changed_sources_are_outdated_until_translated() {
+        let lock = SourceLock::default()
+            .update(&storage(DEFAULT, TRANSLATIONS))
+            .unwrap();
+
+        let changed = DEFAULT.replace("Plabayo News", "Plabayo's News");
+        let report = Report::new(&storage(&changed, TRANSLATIONS), &lock).unwrap();
+        assert_eq!(coverage(&report).outdated, ["page.title"]);
+        assert_eq!(coverage(&report).coverage, 33.3);
+
+        // still outdated after locking, as the translation did not change
+        let relocked = lock.update(&storage(&changed, TRANSLATIONS)).unwrap();
+        let report = Report::new(&storage(&changed, TRANSLATIONS), &relocked).unwrap();
+        assert_eq!(coverage(&report).outdated, ["page.title"]);
+
+        let translated = TRANSLATIONS.replace("Plabayo Nieuws", "Plabayo's Nieuws");
+        let report = Report::new(&storage(&changed, &translated), &relocked).unwrap();
+        assert!(coverage(&report).outdated.is_empty());
+        let relocked = relocked.update(&storage(&changed, &translated)).unwrap();
+        let report = Report::new(&storage(&changed, &translated), &relocked).unwrap();
+        assert!(coverage(&report).outdated.is_empty());
+    }
+}
@@ -0,0 +1,236 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{anyhow, Result};
+use pulldown_cmark::{BrokenLink, Event, LinkType, Parser, Tag};
+
+use super::locales::{markdown_options, Locales, Storage, TypedValue, ValueFormat};
+
+/// Find the values of which the structure differs from the one in the default locale,
+/// e.g. a mapping in one locale while it is a plain-text string in the default locale.
+pub fn structure(storage: &Storage) -> Result<Vec<String>> {
+    let default_locale = storage.default_locale();
+    let defaults: HashMap<Vec<String>, TypedValue> = default_locales(storage)?
+        .sources()
+        .map(|pair| (pair.path, pair.value))
+        .collect();
+    let default_mappings: HashSet<&[String]> = defaults
+        .keys()
+        .flat_map(|path| (1..path.len()).map(move |n| &path[..n]))
+        .collect();
+
+    let mut issues = Vec::new();
+    for locale in storage
+        .all_locales()
+        .filter(|locale| locale != &default_locale)
+    {
+        let locales = storage
+            .get(locale)
+            .ok_or_else(|| anyhow!("failed to get strings for locale {}", locale))?;
+        for pair in locales.sources() {
+            let key = pair.path.join(".");
+            if let Some(default) = defaults.get(&pair.path) {
+                if !pair.value.same_kind(default) {
+                    issues.push(format!(
+                        "{}.yml: {} is a {} while it is a {} in {}.yml",
+                        locale,
+                        key,
+                        describe(&pair.value),
+                        describe(default),
+                        default_locale,
+                    ));
+                }
+            } else if default_mappings.contains(&pair.path[..]) {
+                issues.push(format!(
+                    "{}.yml: {} is a {} while it is a mapping in {}.yml",
+                    locale,
+                    key,
+                    describe(&pair.value),
+                    default_locale,
+                ));
+            } else if let Some(n) =
+                (1..pair.path.len()).find(|n| defaults.contains_key(&pair.path[..*n]))
+            {
+                issues.push(format!(
+                    "{}.yml: {} is a mapping while it is a {} in {}.yml",
+                    locale,
+                    pair.path[..n].join("."),
+                    describe(&defaults[&pair.path[..n]]),
+                    default_locale,
+                ));
+            }
+        }
+    }
+    issues.dedup();
+    Ok(issues)
+}
+
+/// Validate all markdown values of all locales,
+/// reporting broken reference links and links without destination.
+pub fn markdown(storage: &Storage) -> Result<Vec<String>> {
+    let mut issues = Vec::new();
+    for locale in storage.all_locales() {
+        let locales = storage
+            .get(locale)
+            .ok_or_else(|| anyhow!("failed to get strings for locale {}", locale))?;
+        for pair in locales.sources() {
+            if pair.value.format() != ValueFormat::Markdown {
+                continue;
+            }
+            let key = pair.path.join(".");
+            for variant in pair.value.variants() {
+                for issue in markdown_issues(variant) {
+                    issues.push(format!("{}.yml: {}: {}", locale, key, issue));
+                }
+            }
+        }
+    }
+    Ok(issues)
+}
+
+fn markdown_issues(value: &str) -> Vec<String> {
+    let mut issues = Vec::new();
+    // shortcut references (e.g. `[text]`) are not reported,
+    // as these are as likely to be meant as regular text
+    let mut callback = |link: BrokenLink| {
+        if matches!(link.link_type, LinkType::Reference | LinkType::Collapsed) {
+            issues.push(format!("undefined link reference [{}]", link.reference));
+        }
+        None
+    };
+    let mut empty_links = 0;
+    for event in
+        Parser::new_with_broken_link_callback(value, markdown_options(), Some(&mut callback))
+    {
+        if let Event::Start(Tag::Link(_, dest, _)) = event {
+            if dest.trim().is_empty() {
+                empty_links += 1;
+            }
+        }
+    }
+    issues.extend((0..empty_links).map(|_| "link without destination".to_owned()));
+    issues
+}
+
+fn default_locales(storage: &Storage) -> Result<&Locales> {
+    storage
+        .get_default()
+        .ok_or_else(|| anyhow!("failed to get default locale in i18n storage"))
+}
+
+fn describe(value: &TypedValue) -> &'static str {
+    match (value, value.format()) {
+        (TypedValue::Single { .. }, ValueFormat::Text) => "plain-text string",
+        (TypedValue::Single { .. }, ValueFormat::Markdown) => "markdown string",
+        (TypedValue::Plural { .. }, ValueFormat::Text) => "plain-text plural",
+        (TypedValue::Plural { .. }, ValueFormat::Markdown) => "markdown plural",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFAULT: &str = r#"
+page:
+  title: "Plabayo News"
+  about:
+    format: md
+    value: "Read [our story](/about)."
+time:
+  hours:
+    plural:
+      one: "{count} hour ago"
+      other: "{count} hours ago"
+"#;
+
+    fn translated(translations: &str) -> Storage {
+        Storage::from_yaml(&[("en", DEFAULT), ("nl", translations)]).unwrap()
+    }
+
+    #[test]
+    fn same_structure_has_no_issues() {
+        let storage = translated(
+            r#"
+page:
+  title: "Plabayo Nieuws"
+  about:
+    format: md
+    value: "Lees [ons verhaal](/about)."
+time:
+  hours:
+    plural:
+      one: "{count} uur geleden"
+      other: "{count} uren geleden"
+"#,
+        );
+        assert!(structure(&storage).unwrap().is_empty());
+        assert!(markdown(&storage).unwrap().is_empty());
+    }
+
+    #[test]
+    fn structure_mismatches_are_reported() {
+        let storage = translated(
+            r#"
+page:
+  title:
+    short: "PN"
+    long: "Plabayo Nieuws"
+  about: "Lees ons verhaal."
+time: "tijd"
+"#,
+        );
+        assert_eq!(
+            structure(&storage).unwrap(),
+            vec![
+                "nl.yml: page.about is a plain-text string while it is a markdown string in en.yml",
+                "nl.yml: page.title is a mapping while it is a plain-text string in en.yml",
+                "nl.yml: time is a plain-text string while it is a mapping in en.yml",
+            ]
+        );
+
+        let storage = translated(r#"time: { hours: "{count} uur geleden" }"#);
+        assert_eq!(
+            structure(&storage).unwrap(),
+            vec!["nl.yml: time.hours is a plain-text string while it is a plain-text plural in en.yml"]
+        );
+    }
+
+    #[test]
+    fn broken_markdown_links_are_reported() {
+        assert!(markdown_issues("[a link](https://plabayo.tech) and [just text]").is_empty());
+        assert_eq!(
+            markdown_issues("see [the faq][faq] or [][] and [this]()"),
+            vec!["undefined link reference [faq]", "link without destination",]
+        );
+        assert!(markdown_issues("[the faq][faq]\n\n[faq]: /faq").is_empty());
+
+        let storage = translated(
+            r#"
+page:
+  about:
+    format: md
+    value: "Lees [ons verhaal]()."
+"#,
+        );
+        assert_eq!(
+            markdown(&storage).unwrap(),
+            vec!["nl.yml: page.about: link without destination"]
+        );
+    }
+}
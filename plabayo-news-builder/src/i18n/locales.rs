@@ -18,13 +18,15 @@ use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use anyhow::{Context, Error, Result};
+use anyhow::{anyhow, Context, Error, Result};
 use itertools::Itertools;
 use pulldown_cmark::{html, Options, Parser};
 use serde::Deserialize;
-use serde_yaml::{from_reader, from_value, Value};
+use serde_yaml::{from_reader, from_value, Mapping, Value};
+
+use super::rerun_if_changed;
 
 #[derive(Debug)]
 pub struct Storage {
@@ -34,7 +36,7 @@ pub struct Storage {
 
 impl Storage {
     pub fn load<T: AsRef<str>>(path: &str, supported_locales: &[T]) -> Result<Storage> {
-        println!("cargo:rerun-if-changed={}", path);
+        rerun_if_changed(path);
 
         let mut locale_to_values_map = HashMap::new();
         for supported_locale in supported_locales.iter().map(|r| r.as_ref()) {
@@ -47,6 +49,19 @@ impl Storage {
         })
     }
 
+    /// A storage of the given locales and their (YAML) strings, the first being the default locale.
+    #[cfg(test)]
+    pub fn from_yaml(locales: &[(&str, &str)]) -> Result<Storage> {
+        let mut locale_to_values_map = HashMap::new();
+        for (locale, yaml) in locales {
+            locale_to_values_map.insert(locale.to_string(), Locales::from_yaml(yaml)?);
+        }
+        Ok(Storage {
+            default_locale: locales[0].0.to_owned(),
+            locale_to_values_map,
+        })
+    }
+
     pub fn default_locale(&self) -> &str {
         self.default_locale.as_str()
     }
@@ -65,16 +80,67 @@ impl Storage {
     pub fn get(&self, locale: &str) -> Option<&Locales> {
         self.locale_to_values_map.get(locale)
     }
+
+    /// Ensure all locales use the same placeholders for a value as the default locale does,
+    /// as the signature of the generated accessor for that value is defined by the default locale.
+    pub fn check_placeholders(&self) -> Result<()> {
+        let default_placeholders: HashMap<Vec<String>, (LocaleValue, Vec<String>)> = self
+            .get_default()
+            .ok_or_else(|| anyhow!("failed to get default locale in i18n storage"))?
+            .iter()
+            .map(|pair| {
+                let placeholders = pair.value.placeholders().iter().cloned().sorted().collect();
+                (pair.path, (pair.value, placeholders))
+            })
+            .collect();
+
+        let mut mismatches = Vec::new();
+        for locale in self
+            .all_locales()
+            .filter(|locale| locale != &self.default_locale())
+        {
+            let locales = self
+                .get(locale)
+                .ok_or_else(|| anyhow!("failed to get strings for locale {}", locale))?;
+            for pair in locales.iter() {
+                if let Some((default_value, expected)) = default_placeholders.get(&pair.path) {
+                    if !pair.value.same_kind(default_value) {
+                        continue;
+                    }
+                    let found: Vec<String> =
+                        pair.value.placeholders().iter().cloned().sorted().collect();
+                    if &found != expected {
+                        mismatches.push(format!(
+                            "{}.yml: {} has placeholders [{}] while [{}] are expected",
+                            locale,
+                            pair.path.join("."),
+                            typed_placeholders(&found),
+                            typed_placeholders(expected),
+                        ));
+                    }
+                }
+            }
+        }
+
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "placeholders differ between locales:\n{}",
+                mismatches.join("\n")
+            ))
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Locales {
     values: HashMap<String, Value>,
 }
 
 impl Locales {
     pub fn load(path: &str, locale: &str) -> Result<Locales> {
-        let locale_path = Path::new(path).join(format!("{}.yml", locale));
+        let locale_path = Locales::path(path, locale);
         let locales_file = File::open(locale_path)
             .with_context(|| format!("open locale file {}/{}.yml", path, locale))?;
         let values: HashMap<String, Value> = from_reader(locales_file)
@@ -82,7 +148,30 @@ impl Locales {
         Ok(Locales { values })
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = StringValuePathPair> + '_ {
+    /// Parse the values of a locale file from its content.
+    #[cfg(test)]
+    pub fn from_yaml(yaml: &str) -> Result<Locales> {
+        let values: HashMap<String, Value> = serde_yaml::from_str(yaml)?;
+        Ok(Locales { values })
+    }
+
+    /// The path of the file of the given locale.
+    pub fn path(path: &str, locale: &str) -> PathBuf {
+        Path::new(path).join(format!("{}.yml", locale))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = LocaleValuePathPair> + '_ {
+        ValueIter::new(&self.values)
+            .map(|pair| LocaleValuePathPair {
+                value: pair.value.into_locale_value(),
+                path: pair.path,
+            })
+            .sorted()
+    }
+
+    /// Iterate over the values as they are written in the locale file,
+    /// meaning that their format (e.g. markdown) isn't applied yet.
+    pub fn sources(&self) -> impl Iterator<Item = TypedValuePathPair> + '_ {
         ValueIter::new(&self.values).sorted()
     }
 }
@@ -96,14 +185,27 @@ struct ValuePathPairRef<'a> {
     path: Vec<String>,
 }
 
-#[derive(Eq, Clone)]
-pub struct StringValuePathPair {
-    pub value: String,
+/// A value found in a locale file, together with the path of keys
+/// used to reach it. Pairs are compared and ordered by their path only.
+#[derive(Clone)]
+pub struct PathPair<T> {
+    pub value: T,
     pub path: Vec<String>,
 }
 
-impl PartialEq for StringValuePathPair {
-    fn eq(&self, other: &StringValuePathPair) -> bool {
+/// A pair containing the rust code used to generate a locale value.
+pub type StringValuePathPair = PathPair<String>;
+
+/// A pair containing a value as defined in a locale file.
+pub type LocaleValuePathPair = PathPair<LocaleValue>;
+
+/// A pair containing a value as written in a locale file.
+pub type TypedValuePathPair = PathPair<TypedValue>;
+
+impl<T> Eq for PathPair<T> {}
+
+impl<T> PartialEq for PathPair<T> {
+    fn eq(&self, other: &PathPair<T>) -> bool {
         if self.path.len() != other.path.len() {
             return false;
         }
@@ -116,8 +218,8 @@ impl PartialEq for StringValuePathPair {
     }
 }
 
-impl Ord for StringValuePathPair {
-    fn cmp(&self, other: &StringValuePathPair) -> Ordering {
+impl<T> Ord for PathPair<T> {
+    fn cmp(&self, other: &PathPair<T>) -> Ordering {
         for i in 0..self.path.len() {
             let c = self.path[i].cmp(&other.path[i]);
             if i >= other.path.len() {
@@ -131,8 +233,8 @@ impl Ord for StringValuePathPair {
     }
 }
 
-impl PartialOrd for StringValuePathPair {
-    fn partial_cmp(&self, other: &StringValuePathPair) -> Option<Ordering> {
+impl<T> PartialOrd for PathPair<T> {
+    fn partial_cmp(&self, other: &PathPair<T>) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
@@ -151,37 +253,37 @@ impl<'a> ValueIter<'a> {
 }
 
 impl<'a> Iterator for ValueIter<'a> {
-    type Item = StringValuePathPair;
+    type Item = TypedValuePathPair;
 
-    fn next(&mut self) -> Option<StringValuePathPair> {
+    fn next(&mut self) -> Option<TypedValuePathPair> {
         loop {
             match self.stack.pop() {
                 None => return None,
                 Some(pair_ref) => match pair_ref.value {
                     Value::Null => continue,
                     Value::Bool(b) => {
-                        return Some(StringValuePathPair {
-                            value: (if *b { "true" } else { "false" }).to_owned(),
+                        return Some(TypedValuePathPair {
+                            value: TypedValue::text((if *b { "true" } else { "false" }).to_owned()),
                             path: pair_ref.path,
                         })
                     }
                     Value::Number(n) => {
-                        return Some(StringValuePathPair {
-                            value: format!("{}", n),
+                        return Some(TypedValuePathPair {
+                            value: TypedValue::text(format!("{}", n)),
                             path: pair_ref.path,
                         })
                     }
                     Value::String(s) => {
-                        return Some(StringValuePathPair {
-                            value: s.clone(),
+                        return Some(TypedValuePathPair {
+                            value: TypedValue::text(s.clone()),
                             path: pair_ref.path,
                         })
                     }
                     Value::Sequence(_) => continue,
                     Value::Mapping(m) => match TypedValue::try_from(pair_ref.value) {
                         Ok(tv) => {
-                            return Some(StringValuePathPair {
-                                value: tv.to_string(),
+                            return Some(TypedValuePathPair {
+                                value: tv,
                                 path: pair_ref.path,
                             })
                         }
@@ -201,29 +303,197 @@ impl<'a> Iterator for ValueIter<'a> {
     }
 }
 
-#[derive(Debug, Deserialize)]
-enum ValueFormat {
+/// A value as it is to be rendered for a locale,
+/// with its format (e.g. markdown) already applied.
+///
+/// Plain-text values can contain `{name}` placeholders,
+/// which are to be interpolated at runtime.
+#[derive(Debug, Clone)]
+pub enum LocaleValue {
+    /// A single plain-text string.
+    Text {
+        value: String,
+        placeholders: Vec<String>,
+    },
+    /// A single string of HTML, rendered from a markdown value,
+    /// which is trusted as it originates from the locale files.
+    Html { value: String },
+    /// A string with a variant for each plural category,
+    /// of which one is selected at runtime using a count.
+    /// The `{count}` placeholder is always available
+    /// and thus not part of the listed placeholders.
+    Plural {
+        value: PluralValue,
+        placeholders: Vec<String>,
+        /// True in case the variants are HTML rendered from markdown.
+        html: bool,
+    },
+}
+
+impl LocaleValue {
+    fn text_with_placeholders(value: String) -> LocaleValue {
+        let placeholders = find_placeholders(&value);
+        LocaleValue::Text {
+            value,
+            placeholders,
+        }
+    }
+
+    /// The names of the placeholders found in this value,
+    /// in order of first appearance.
+    pub fn placeholders(&self) -> &[String] {
+        match self {
+            LocaleValue::Text { placeholders, .. } => placeholders,
+            LocaleValue::Html { .. } => &[],
+            LocaleValue::Plural { placeholders, .. } => placeholders,
+        }
+    }
+
+    /// All text variants of this value.
+    pub fn variants(&self) -> Box<dyn Iterator<Item = &String> + '_> {
+        match self {
+            LocaleValue::Text { value, .. } | LocaleValue::Html { value } => {
+                Box::new(std::iter::once(value))
+            }
+            LocaleValue::Plural { value, .. } => Box::new(value.variants()),
+        }
+    }
+
+    /// Returns true if both values are of the same kind,
+    /// meaning they can be used in place of one another.
+    pub fn same_kind(&self, other: &LocaleValue) -> bool {
+        match (self, other) {
+            (LocaleValue::Text { .. }, LocaleValue::Text { .. })
+            | (LocaleValue::Html { .. }, LocaleValue::Html { .. }) => true,
+            (
+                LocaleValue::Plural { html, .. },
+                LocaleValue::Plural {
+                    html: other_html, ..
+                },
+            ) => html == other_html,
+            _ => false,
+        }
+    }
+}
+
+/// The given placeholders together with their type, e.g. `{count}: i64, {user}: &str`.
+fn typed_placeholders(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| format!("{{{}}}: {}", name, placeholder_type(name)))
+        .join(", ")
+}
+
+/// Find all `{name}` placeholders in the given value, in order of first appearance,
+/// where a name is a snake_case identifier (e.g. `count` or `user`).
+fn find_placeholders(value: &str) -> Vec<String> {
+    let mut placeholders: Vec<String> = Vec::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('{') {
+        rest = &rest[start + 1..];
+        if let Some(end) = rest.find('}') {
+            let name = &rest[..end];
+            let is_identifier = name.starts_with(|c: char| c.is_ascii_lowercase())
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+            if is_identifier && !placeholders.iter().any(|p| p == name) {
+                placeholders.push(name.to_owned());
+            }
+        }
+    }
+    placeholders
+}
+
+/// The rust type of a placeholder, defined by its name: `count` and names ending with `_count`
+/// are integers (`i64`), while any other placeholder is a string (`&str`).
+pub fn placeholder_type(name: &str) -> &'static str {
+    if name == "count" || name.ends_with("_count") {
+        "i64"
+    } else {
+        "&str"
+    }
+}
+
+/// The plural variants of a value, keyed by their CLDR plural category.
+/// Only the `other` category is required, as it is the variant
+/// used for any category that isn't defined.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PluralValue {
+    pub zero: Option<String>,
+    pub one: Option<String>,
+    pub two: Option<String>,
+    pub few: Option<String>,
+    pub many: Option<String>,
+    pub other: String,
+}
+
+impl PluralValue {
+    /// The plural categories defined by this value,
+    /// together with their variant.
+    pub fn categories(&self) -> impl Iterator<Item = (&'static str, &String)> {
+        [
+            ("zero", self.zero.as_ref()),
+            ("one", self.one.as_ref()),
+            ("two", self.two.as_ref()),
+            ("few", self.few.as_ref()),
+            ("many", self.many.as_ref()),
+            ("other", Some(&self.other)),
+        ]
+        .into_iter()
+        .filter_map(|(category, variant)| variant.map(|variant| (category, variant)))
+    }
+
+    /// The variant of the given plural category, if defined.
+    pub fn get(&self, category: &str) -> Option<&String> {
+        self.categories()
+            .find(|(c, _)| *c == category)
+            .map(|(_, variant)| variant)
+    }
+
+    fn variants(&self) -> impl Iterator<Item = &String> {
+        self.zero
+            .iter()
+            .chain(self.one.iter())
+            .chain(self.two.iter())
+            .chain(self.few.iter())
+            .chain(self.many.iter())
+            .chain(std::iter::once(&self.other))
+    }
+
+    fn map(self, f: impl Fn(String) -> String) -> PluralValue {
+        PluralValue {
+            zero: self.zero.map(&f),
+            one: self.one.map(&f),
+            two: self.two.map(&f),
+            few: self.few.map(&f),
+            many: self.many.map(&f),
+            other: f(self.other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum ValueFormat {
     #[serde(rename = "txt")]
     Text,
     #[serde(rename = "md")]
     Markdown,
 }
 
-#[derive(Debug, Deserialize)]
-struct TypedValue {
-    value: String,
-    format: Option<ValueFormat>,
+/// The markdown extensions supported for markdown values.
+pub fn markdown_options() -> Options {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options
 }
 
-impl ToString for TypedValue {
-    fn to_string(&self) -> String {
-        let format = self.format.as_ref().unwrap_or(&ValueFormat::Text);
-        match format {
-            ValueFormat::Text => self.value.clone(),
+impl ValueFormat {
+    fn apply(&self, value: String) -> String {
+        match self {
+            ValueFormat::Text => value,
             ValueFormat::Markdown => {
-                let mut options = Options::empty();
-                options.insert(Options::ENABLE_STRIKETHROUGH);
-                let parser = Parser::new_ext(&self.value, options);
+                let parser = Parser::new_ext(&value, markdown_options());
 
                 let mut output = String::new();
                 html::push_html(&mut output, parser);
@@ -234,12 +504,120 @@ impl ToString for TypedValue {
     }
 }
 
+/// A value as it is written in a locale file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum TypedValue {
+    Plural {
+        plural: PluralValue,
+        format: Option<ValueFormat>,
+    },
+    Single {
+        value: String,
+        format: Option<ValueFormat>,
+    },
+}
+
+impl TypedValue {
+    pub fn text(value: String) -> TypedValue {
+        TypedValue::Single {
+            value,
+            format: Some(ValueFormat::Text),
+        }
+    }
+
+    pub fn format(&self) -> ValueFormat {
+        match self {
+            TypedValue::Plural { format, .. } | TypedValue::Single { format, .. } => {
+                format.unwrap_or(ValueFormat::Text)
+            }
+        }
+    }
+
+    /// All text variants of this value.
+    pub fn variants(&self) -> Box<dyn Iterator<Item = &String> + '_> {
+        match self {
+            TypedValue::Single { value, .. } => Box::new(std::iter::once(value)),
+            TypedValue::Plural { plural, .. } => Box::new(plural.variants()),
+        }
+    }
+
+    /// Returns true if both values are of the same kind and format,
+    /// meaning they can be used in place of one another.
+    pub fn same_kind(&self, other: &TypedValue) -> bool {
+        self.format() == other.format()
+            && matches!(
+                (self, other),
+                (TypedValue::Single { .. }, TypedValue::Single { .. })
+                    | (TypedValue::Plural { .. }, TypedValue::Plural { .. })
+            )
+    }
+
+    /// Convert into the YAML value as it is to be written in a locale file,
+    /// using the short notation for plain-text values.
+    pub fn to_yaml(&self) -> Value {
+        let mut m = Mapping::new();
+        match self {
+            TypedValue::Single { value, .. } if self.format() == ValueFormat::Text => {
+                return Value::String(value.clone());
+            }
+            TypedValue::Single { value, .. } => {
+                m.insert("value".into(), value.as_str().into());
+            }
+            TypedValue::Plural { plural, .. } => {
+                let mut variants = Mapping::new();
+                for (category, variant) in plural.categories() {
+                    variants.insert(category.into(), variant.as_str().into());
+                }
+                m.insert("plural".into(), Value::Mapping(variants));
+            }
+        }
+        if self.format() == ValueFormat::Markdown {
+            m.insert("format".into(), "md".into());
+        }
+        Value::Mapping(m)
+    }
+
+    /// Convert into a locale value, placeholders are only supported
+    /// for plain-text values, as to not clash with the markdown syntax.
+    fn into_locale_value(self) -> LocaleValue {
+        match self {
+            TypedValue::Plural { plural, format } => match format.unwrap_or(ValueFormat::Text) {
+                ValueFormat::Text => {
+                    let placeholders = plural
+                        .variants()
+                        .flat_map(|variant| find_placeholders(variant))
+                        .filter(|name| name != "count")
+                        .unique()
+                        .collect();
+                    LocaleValue::Plural {
+                        value: plural,
+                        placeholders,
+                        html: false,
+                    }
+                }
+                format => LocaleValue::Plural {
+                    value: plural.map(|value| format.apply(value)),
+                    placeholders: vec![],
+                    html: true,
+                },
+            },
+            TypedValue::Single { value, format } => match format.unwrap_or(ValueFormat::Text) {
+                ValueFormat::Text => LocaleValue::text_with_placeholders(value),
+                format => LocaleValue::Html {
+                    value: format.apply(value),
+                },
+            },
+        }
+    }
+}
+
 impl TryFrom<&Value> for TypedValue {
     type Error = Error;
 
     fn try_from(value: &Value) -> Result<Self, Self::Error> {
         if let Some(s) = value.as_str() {
-            return Ok(TypedValue {
+            return Ok(TypedValue::Single {
                 value: s.to_owned(),
                 format: Some(ValueFormat::Text),
             });
@@ -248,3 +626,106 @@ impl TryFrom<&Value> for TypedValue {
         Ok(value)
     }
 }
+
+/// Build the tree of a locale file from the given (flat) values.
+pub fn to_yaml(pairs: impl Iterator<Item = TypedValuePathPair>) -> Value {
+    let mut root = Mapping::new();
+    for pair in pairs {
+        let mut node = &mut root;
+        let (key, parents) = match pair.path.split_last() {
+            Some(split) => split,
+            None => continue,
+        };
+        for parent in parents {
+            let child = node
+                .entry(parent.as_str().into())
+                .or_insert_with(|| Value::Mapping(Mapping::new()));
+            if !child.is_mapping() {
+                *child = Value::Mapping(Mapping::new());
+            }
+            node = match child {
+                Value::Mapping(m) => m,
+                _ => unreachable!(),
+            };
+        }
+        node.insert(key.as_str().into(), pair.value.to_yaml());
+    }
+    Value::Mapping(root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFAULT: &str = r#"
+page:
+  welcome: "Welcome {user}"
+  points: "{points_count} points by {user}"
+time:
+  hours:
+    plural:
+      one: "{count} hour ago by {user}"
+      other: "{count} hours ago by {user}"
+"#;
+
+    fn check(translations: &str) -> Result<()> {
+        Storage::from_yaml(&[("en", DEFAULT), ("nl", translations)])
+            .unwrap()
+            .check_placeholders()
+    }
+
+    #[test]
+    fn same_placeholders_are_accepted() {
+        check(
+            r#"
+page:
+  welcome: "Welkom {user}"
+  points: "{user} heeft {points_count} punten"
+time:
+  hours:
+    plural:
+      one: "{count} uur geleden door {user}"
+      other: "{count} uren geleden door {user}"
+"#,
+        )
+        .unwrap();
+        // missing strings are resolved using the default locale
+        check("page: {}").unwrap();
+    }
+
+    #[test]
+    fn missing_placeholder_is_refused() {
+        let err = check(r#"page: { welcome: "Welkom" }"#).unwrap_err();
+        assert!(err.to_string().contains(
+            "nl.yml: page.welcome has placeholders [] while [{user}: &str] are expected"
+        ));
+
+        let err = check(
+            r#"
+time:
+  hours:
+    plural:
+      one: "{count} uur geleden"
+      other: "{count} uren geleden"
+"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("time.hours"));
+    }
+
+    #[test]
+    fn extra_placeholder_is_refused() {
+        let err = check(r#"page: { welcome: "Welkom {user} uit {location}" }"#).unwrap_err();
+        assert!(err.to_string().contains(
+            "page.welcome has placeholders [{location}: &str, {user}: &str] while [{user}: &str] are expected"
+        ));
+    }
+
+    #[test]
+    fn placeholder_of_another_type_is_refused() {
+        let err = check(r#"page: { points: "{points} punten door {user}" }"#).unwrap_err();
+        assert!(err.to_string().contains(
+            "page.points has placeholders [{points}: &str, {user}: &str] while [{points_count}: i64, {user}: &str] are expected"
+        ));
+    }
+}
@@ -17,13 +17,41 @@
 use anyhow::Result;
 
 mod codegen;
-mod config;
-mod locales;
+pub mod config;
+pub mod coverage;
+pub mod lint;
+pub mod locales;
+pub mod po;
 
 /// build the i18n locale structs and (Askama) templates
 /// for the project
 pub fn build(cargo_toml: &str) -> Result<()> {
     let i18n_cfg = config::load(cargo_toml)?;
     let locales_storage = locales::Storage::load(&i18n_cfg.path, &i18n_cfg.locales[..])?;
+    locales_storage.check_placeholders()?;
+
+    // the lock is only read, as the build may not touch any tracked files
+    let lock = match &i18n_cfg.lock {
+        Some(path) => coverage::SourceLock::load(path)?,
+        None => coverage::SourceLock::default(),
+    };
+    let report = coverage::Report::new(&locales_storage, &lock)?;
+    let report_path = i18n_cfg
+        .report
+        .clone()
+        .unwrap_or_else(|| format!("{}/coverage.json", i18n_cfg.out));
+    report.write(&report_path)?;
+    if let Some(threshold) = i18n_cfg.strict {
+        report.check(threshold)?;
+    }
+
     codegen::generate_all(&i18n_cfg.out, &locales_storage, &i18n_cfg.pages)
 }
+
+/// Instruct cargo to rerun the build script if the given path changes,
+/// only printed when running as part of a build script.
+pub(crate) fn rerun_if_changed(path: &str) {
+    if std::env::var_os("OUT_DIR").is_some() {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+}
@@ -0,0 +1,401 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Conversion of locales from and to the gettext PO format,
+//! such that translators can use the translation tools they are used to.
+//!
+//! Each string is identified by its key (e.g. `site.nav.header.news`) as message context,
+//! while each plural category of a plural string is its own message,
+//! identified by its key and category (e.g. `site.time.ago.hours[one]`).
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use anyhow::{anyhow, Context, Result};
+use serde_yaml::Value;
+
+use super::codegen::plural_categories;
+use super::locales::{self, Locales, PluralValue, TypedValue, TypedValuePathPair, ValueFormat};
+
+/// Export the strings of the default locale as a PO file for the given locale,
+/// using the existing translations as the translated messages.
+pub fn export<W: Write>(
+    w: &mut W,
+    locale: &str,
+    default: &Locales,
+    translations: &Locales,
+) -> Result<()> {
+    let translations: BTreeMap<Vec<String>, TypedValue> = translations
+        .sources()
+        .map(|pair| (pair.path, pair.value))
+        .collect();
+
+    writeln!(w, "msgid \"\"")?;
+    writeln!(w, "msgstr \"\"")?;
+    writeln!(w, "\"Language: {}\\n\"", locale)?;
+    writeln!(w, "\"Content-Type: text/plain; charset=UTF-8\\n\"")?;
+
+    for pair in default.sources() {
+        let key = pair.path.join(".");
+        let translation = translations
+            .get(&pair.path)
+            .filter(|value| value.same_kind(&pair.value));
+        let format = match pair.value.format() {
+            ValueFormat::Text => "txt",
+            ValueFormat::Markdown => "md",
+        };
+        match &pair.value {
+            TypedValue::Single { value, .. } => {
+                let msgstr = match translation {
+                    Some(TypedValue::Single { value, .. }) => value.as_str(),
+                    _ => "",
+                };
+                writeln!(w)?;
+                writeln!(w, "#. format: {}", format)?;
+                write_message(w, &key, value, msgstr)?;
+            }
+            TypedValue::Plural { plural, .. } => {
+                for category in plural_categories(locale) {
+                    let msgid = plural.get(category).unwrap_or(&plural.other);
+                    let msgstr = match translation {
+                        Some(TypedValue::Plural { plural, .. }) => plural.get(category),
+                        _ => None,
+                    };
+                    writeln!(w)?;
+                    writeln!(w, "#. format: {}, plural category: {}", format, category)?;
+                    write_message(
+                        w,
+                        &format!("{}[{}]", key, category),
+                        msgid,
+                        msgstr.map(|s| s.as_str()).unwrap_or_default(),
+                    )?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Import the translated messages of a PO file on top of the existing translations,
+/// returning the resulting locale file. Fuzzy and untranslated messages are ignored.
+pub fn import(po: &str, default: &Locales, translations: &Locales) -> Result<Value> {
+    let mut messages: BTreeMap<String, String> = parse(po)?
+        .into_iter()
+        .filter(|message| {
+            !message.fuzzy && !message.msgctxt.is_empty() && !message.msgstr.is_empty()
+        })
+        .map(|message| (message.msgctxt, message.msgstr))
+        .collect();
+    let mut values: BTreeMap<Vec<String>, TypedValue> = translations
+        .sources()
+        .map(|pair| (pair.path, pair.value))
+        .collect();
+
+    for pair in default.sources() {
+        let key = pair.path.join(".");
+        match &pair.value {
+            TypedValue::Single { format, .. } => {
+                if let Some(msgstr) = messages.remove(&key) {
+                    values.insert(
+                        pair.path,
+                        TypedValue::Single {
+                            value: msgstr,
+                            format: *format,
+                        },
+                    );
+                }
+            }
+            TypedValue::Plural { format, .. } => {
+                let mut plural = match values.get(&pair.path) {
+                    Some(TypedValue::Plural { plural, .. }) => Some(plural.clone()),
+                    _ => None,
+                };
+                for category in ["zero", "one", "two", "few", "many", "other"] {
+                    if let Some(msgstr) = messages.remove(&format!("{}[{}]", key, category)) {
+                        plural = Some(with_category(plural, category, msgstr));
+                    }
+                }
+                match plural {
+                    Some(plural) if plural.other.is_empty() => eprintln!(
+                        "plabayo-news_builder: plural {} is missing the 'other' category, ignoring it",
+                        key
+                    ),
+                    Some(plural) => {
+                        values.insert(
+                            pair.path,
+                            TypedValue::Plural {
+                                plural,
+                                format: *format,
+                            },
+                        );
+                    }
+                    None => (),
+                }
+            }
+        }
+    }
+
+    for msgctxt in messages.keys() {
+        eprintln!(
+            "plabayo-news_builder: unknown message {}, ignoring it",
+            msgctxt
+        );
+    }
+
+    Ok(locales::to_yaml(
+        values
+            .into_iter()
+            .map(|(path, value)| TypedValuePathPair { value, path }),
+    ))
+}
+
+fn with_category(plural: Option<PluralValue>, category: &str, value: String) -> PluralValue {
+    let mut plural = plural.unwrap_or(PluralValue {
+        zero: None,
+        one: None,
+        two: None,
+        few: None,
+        many: None,
+        other: String::new(),
+    });
+    match category {
+        "zero" => plural.zero = Some(value),
+        "one" => plural.one = Some(value),
+        "two" => plural.two = Some(value),
+        "few" => plural.few = Some(value),
+        "many" => plural.many = Some(value),
+        _ => plural.other = value,
+    }
+    plural
+}
+
+fn write_message<W: Write>(w: &mut W, msgctxt: &str, msgid: &str, msgstr: &str) -> Result<()> {
+    write_string(w, "msgctxt", msgctxt)?;
+    write_string(w, "msgid", msgid)?;
+    write_string(w, "msgstr", msgstr)
+}
+
+fn write_string<W: Write>(w: &mut W, keyword: &str, s: &str) -> Result<()> {
+    if s.trim_end_matches('\n').contains('\n') {
+        writeln!(w, "{} \"\"", keyword)?;
+        for line in s.split_inclusive('\n') {
+            writeln!(w, "\"{}\"", escape(line))?;
+        }
+    } else {
+        writeln!(w, "{} \"{}\"", keyword, escape(s))?;
+    }
+    Ok(())
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\t', "\\t")
+}
+
+fn unescape(s: &str) -> String {
+    let mut output = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => output.push('\n'),
+            Some('r') => output.push('\r'),
+            Some('t') => output.push('\t'),
+            Some(c) => output.push(c),
+            None => output.push('\\'),
+        }
+    }
+    output
+}
+
+#[derive(Debug, Default)]
+struct Message {
+    msgctxt: String,
+    msgid: String,
+    msgstr: String,
+    fuzzy: bool,
+}
+
+#[derive(Clone, Copy)]
+enum Field {
+    Context,
+    Id,
+    Str,
+}
+
+/// Parse the messages of a PO file, only supporting what is exported by [`export`].
+fn parse(po: &str) -> Result<Vec<Message>> {
+    let mut messages = Vec::new();
+    let mut message = Message::default();
+    let mut field = None;
+
+    for (n, line) in po.lines().enumerate() {
+        let line = line.trim();
+        let (next_field, rest) = if line.is_empty() {
+            if field.is_some() {
+                messages.push(std::mem::take(&mut message));
+            }
+            field = None;
+            continue;
+        } else if line.starts_with('#') {
+            // comments and flags precede the message they belong to,
+            // and thus end the previous message if not separated by a blank line
+            if matches!(field, Some(Field::Str)) {
+                messages.push(std::mem::take(&mut message));
+                field = None;
+            }
+            message.fuzzy |= line.starts_with("#,") && line.contains("fuzzy");
+            continue;
+        } else if let Some(rest) = line.strip_prefix("msgctxt ") {
+            (Field::Context, rest)
+        } else if let Some(rest) = line.strip_prefix("msgid ") {
+            (Field::Id, rest)
+        } else if let Some(rest) = line.strip_prefix("msgstr ") {
+            (Field::Str, rest)
+        } else if line.starts_with('"') {
+            match field {
+                Some(field) => (field, line),
+                None => return Err(anyhow!("line {}: string without keyword", n + 1)),
+            }
+        } else {
+            return Err(anyhow!("line {}: unsupported PO syntax: {}", n + 1, line));
+        };
+
+        if matches!(
+            (field, next_field),
+            (Some(Field::Str), Field::Context | Field::Id)
+        ) {
+            messages.push(std::mem::take(&mut message));
+        }
+
+        let s = rest
+            .trim()
+            .strip_prefix('"')
+            .and_then(|s| s.strip_suffix('"'))
+            .with_context(|| format!("line {}: expected a quoted string", n + 1))?;
+        let s = unescape(s);
+        match next_field {
+            Field::Context => message.msgctxt.push_str(&s),
+            Field::Id => message.msgid.push_str(&s),
+            Field::Str => message.msgstr.push_str(&s),
+        }
+        field = Some(next_field);
+    }
+    if field.is_some() {
+        messages.push(message);
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFAULT: &str = r#"
+page:
+  title: "Plabayo News"
+  about: "Line one\nLine two\n"
+  quote: "Say \"hi\" \\ wave\tnow"
+time:
+  hours:
+    plural:
+      one: "{count} hour ago"
+      other: "{count} hours ago"
+"#;
+
+    const TRANSLATIONS: &str = r#"
+page:
+  title: "Plabayo Nieuws"
+  about: "Regel een\nRegel twee\n"
+  quote: "Zeg \"hoi\" \\ zwaai\tnu"
+time:
+  hours:
+    plural:
+      one: "{count} uur geleden"
+      other: "{count} uren geleden"
+"#;
+
+    fn round_trip(po: &str) -> Value {
+        let default = Locales::from_yaml(DEFAULT).unwrap();
+        import(po, &default, &Locales::default()).unwrap()
+    }
+
+    fn export_nl() -> String {
+        let default = Locales::from_yaml(DEFAULT).unwrap();
+        let translations = Locales::from_yaml(TRANSLATIONS).unwrap();
+        let mut po = Vec::new();
+        export(&mut po, "nl", &default, &translations).unwrap();
+        String::from_utf8(po).unwrap()
+    }
+
+    #[test]
+    fn translations_survive_a_round_trip() {
+        let po = export_nl();
+        assert!(po.contains("msgid \"\"\n\"Line one\\n\"\n\"Line two\\n\"\n"));
+        assert!(po.contains("msgctxt \"time.hours[one]\""));
+
+        let value = round_trip(&po);
+        assert_eq!(value, serde_yaml::from_str::<Value>(TRANSLATIONS).unwrap());
+    }
+
+    #[test]
+    fn fuzzy_messages_are_ignored() {
+        let po = export_nl().replace(
+            "#. format: txt\nmsgctxt \"page.title\"",
+            "#, fuzzy\n#. format: txt\nmsgctxt \"page.title\"",
+        );
+        let value = round_trip(&po);
+        assert!(value["page"].get("title").is_none());
+        assert_eq!(
+            value["page"]["about"].as_str(),
+            Some("Regel een\nRegel twee\n")
+        );
+    }
+
+    #[test]
+    fn flags_belong_to_the_next_message_without_blank_lines() {
+        let po = r#"msgctxt "page.about"
+msgid "Line one"
+msgstr "Regel een"
+#, fuzzy
+msgctxt "page.title"
+msgid "Plabayo News"
+msgstr "Plabayo Nieuws"
+msgctxt "page.quote"
+msgid "Say"
+msgstr "Zeg"
+"#;
+        let messages = parse(po).unwrap();
+        let fuzzy: Vec<(&str, bool)> = messages
+            .iter()
+            .map(|message| (message.msgctxt.as_str(), message.fuzzy))
+            .collect();
+        assert_eq!(
+            fuzzy,
+            vec![
+                ("page.about", false),
+                ("page.title", true),
+                ("page.quote", false)
+            ]
+        );
+    }
+}
@@ -14,28 +14,1335 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::time::SystemTime;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
 
-use crate::models::{Item, ItemKind, ItemState};
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-#[derive(Clone, Copy)]
-pub struct Database {}
+use crate::models::{
+    Action, ActionID, ActionKind, ApiAccessRequest, ApiAccessRequestID, ApiAccessRequestState,
+    ApiKey, ApiKeyID, ApiKeyLimits, ApiKeyOwner, FollowedThread, Item, ItemContent, ItemID,
+    ItemKind, ItemRelation, ItemRelations, ItemRevision, ItemState, SpamDecision, SpamVerdict,
+    User, UserID, UserKind, UserPreferences, UserState,
+};
+use crate::sites;
+
+/// The storage of all Plabayo News data, kept in the memory of the process.
+/// Clones share the same storage, such that the website and the API,
+/// served by the same process, serve the same content.
+#[derive(Clone, Default)]
+pub struct Database {
+    store: Arc<RwLock<Store>>,
+}
+
+struct Store {
+    users: Vec<User>,
+    items: Vec<Item>,
+    /// The previous versions of all edited items, oldest first.
+    item_revisions: HashMap<ItemID, Vec<ItemRevision>>,
+    /// The relations (hidden, favorite, followed) each user has with items.
+    item_relations: HashMap<UserID, ItemRelations>,
+    /// Time each user last read the replies to their items.
+    replies_seen: HashMap<UserID, SystemTime>,
+    /// Time each user was last sent an email digest.
+    digests_sent: HashMap<UserID, SystemTime>,
+    /// The pending confirmation of the email address of each user.
+    email_confirmations: HashMap<UserID, EmailConfirmation>,
+    /// The users whose items are only visible to themselves.
+    shadow_banned: HashSet<UserID>,
+    actions: Vec<Action>,
+    action_subscribers: Vec<ActionSubscriber>,
+    api_keys: Vec<ApiKey>,
+    api_access_requests: Vec<ApiAccessRequest>,
+    /// Index of the stories per site they link to.
+    sites: HashMap<String, Vec<ItemID>>,
+    ranking: Ranking,
+}
+
+/// The data of the store as saved to disk,
+/// leaving out what is derived from it (e.g. indices) or only lives as long as the process.
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    users: Vec<User>,
+    items: Vec<Item>,
+    item_revisions: HashMap<ItemID, Vec<ItemRevision>>,
+    item_relations: HashMap<UserID, ItemRelations>,
+    replies_seen: HashMap<UserID, SystemTime>,
+    digests_sent: HashMap<UserID, SystemTime>,
+    email_confirmations: HashMap<UserID, EmailConfirmation>,
+    shadow_banned: HashSet<UserID>,
+    actions: Vec<Action>,
+    api_keys: Vec<ApiKey>,
+    api_access_requests: Vec<ApiAccessRequest>,
+}
+
+impl Default for Store {
+    fn default() -> Store {
+        Store::from_snapshot(Snapshot {
+            users: example_users(),
+            items: example_items(),
+            item_revisions: HashMap::new(),
+            item_relations: HashMap::new(),
+            replies_seen: HashMap::new(),
+            digests_sent: HashMap::new(),
+            email_confirmations: HashMap::new(),
+            shadow_banned: HashSet::new(),
+            actions: example_actions(),
+            api_keys: Vec::new(),
+            api_access_requests: Vec::new(),
+        })
+    }
+}
+
+/// A link sent to a user, to confirm they own the email address.
+#[derive(Clone, Serialize, Deserialize)]
+struct EmailConfirmation {
+    email: String,
+    /// Hash of the token within the link, which is only known by the user.
+    hash: String,
+    time: SystemTime,
+}
+
+/// Time within which a user has to confirm their email address.
+const EMAIL_CONFIRMATION_TTL: Duration = Duration::from_secs(2 * 24 * 60 * 60);
+
+/// Parameters of the ranked listings.
+#[derive(Debug, Clone, Copy)]
+pub struct Ranking {
+    /// Accounts younger than this are considered fresh,
+    /// keeping their submissions out of the ranked listings.
+    pub fresh_account_age: Duration,
+}
+
+impl Default for Ranking {
+    fn default() -> Ranking {
+        Ranking {
+            fresh_account_age: DEFAULT_FRESH_ACCOUNT_AGE,
+        }
+    }
+}
+
+/// The default age until which accounts are considered fresh, see [`Ranking`].
+pub const DEFAULT_FRESH_ACCOUNT_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Prefix of all API keys, making them easy to recognize (e.g. by secret scanners).
+const API_KEY_PREFIX: &str = "pn_";
+
+impl Database {
+    pub fn new() -> Database {
+        Database::default()
+    }
+
+    /// Open the database saved at the given path, or one with the example data
+    /// in case nothing is saved there yet. All changes are kept in memory,
+    /// until saved (again) using [`Database::save`].
+    pub fn open(path: &Path) -> io::Result<Database> {
+        let content = match fs::read(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Database::new()),
+            Err(err) => return Err(err),
+        };
+        let snapshot: Snapshot = serde_json::from_slice(&content)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(Database {
+            store: Arc::new(RwLock::new(Store::from_snapshot(snapshot))),
+        })
+    }
+
+    /// Save all data to the given path, replacing what was saved there before.
+    /// The data is first written to a temporary file next to it,
+    /// such that the previous data is kept intact when failing halfway.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let content = serde_json::to_vec(&self.store.read().unwrap().snapshot())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        fs::write(&temp, content)?;
+        fs::rename(&temp, path)
+    }
+
+    /// Set the parameters of the ranked listings.
+    pub fn set_ranking(&self, ranking: Ranking) {
+        self.store.write().unwrap().ranking = ranking;
+    }
+
+    /// The item with the given ID, unless it is hidden from the viewer
+    /// (none for anonymous visitors and consumers of the API).
+    pub async fn get_item(&self, id: ItemID, viewer: Option<UserID>) -> Option<Item> {
+        let store = self.store.read().unwrap();
+        store
+            .items
+            .iter()
+            .find(|item| item.id == id && store.is_visible_to(item, viewer))
+            .cloned()
+    }
+
+    pub async fn get_user(&self, id: UserID) -> Option<User> {
+        let store = self.store.read().unwrap();
+        store.users.iter().find(|user| user.id == id).cloned()
+    }
+
+    /// All stories and questions, ranked by their votes,
+    /// except those in the penalty box.
+    pub async fn get_news_ranked(&self, viewer: Option<UserID>) -> Vec<Item> {
+        let items = self.alive_items(viewer);
+        let store = self.store.read().unwrap();
+        let mut items: Vec<Item> = items
+            .into_iter()
+            .filter(|item| !matches!(item.kind, ItemKind::Comment) && !store.is_penalized(item))
+            .collect();
+        items.sort_by_key(|item| Reverse(item.votes));
+        items
+    }
+
+    /// All stories and questions, newest first,
+    /// except those in the penalty box.
+    pub async fn get_news_newest(&self, viewer: Option<UserID>) -> Vec<Item> {
+        let items = self.alive_items(viewer);
+        let store = self.store.read().unwrap();
+        let mut items: Vec<Item> = items
+            .into_iter()
+            .filter(|item| !matches!(item.kind, ItemKind::Comment) && !store.is_penalized(item))
+            .collect();
+        items.sort_by_key(|item| Reverse(item.time));
+        items
+    }
+
+    /// A page of the stories and questions, newest first. Those in the penalty box
+    /// (flagged or submitted by fresh accounts) are only included when asked for.
+    pub async fn get_news_newest_page(
+        &self,
+        offset: usize,
+        limit: usize,
+        include_penalized: bool,
+        viewer: Option<UserID>,
+    ) -> Vec<Item> {
+        let items = self.alive_items(viewer);
+        let store = self.store.read().unwrap();
+        let mut items: Vec<Item> = items
+            .into_iter()
+            .filter(|item| {
+                !matches!(item.kind, ItemKind::Comment)
+                    && (include_penalized || !store.is_penalized(item))
+            })
+            .collect();
+        items.sort_by_key(|item| Reverse(item.time));
+        items.into_iter().skip(offset).take(limit).collect()
+    }
+
+    /// All questions, ranked by their votes,
+    /// except those in the penalty box.
+    pub async fn get_ask_ranked(&self, viewer: Option<UserID>) -> Vec<Item> {
+        let items = self.alive_items(viewer);
+        let store = self.store.read().unwrap();
+        let mut items: Vec<Item> = items
+            .into_iter()
+            .filter(|item| matches!(item.kind, ItemKind::Question) && !store.is_penalized(item))
+            .collect();
+        items.sort_by_key(|item| Reverse(item.votes));
+        items
+    }
+
+    /// All stories and questions submitted within the given time range
+    /// (start inclusive, end exclusive), ranked by their votes,
+    /// except those in the penalty box.
+    pub async fn get_news_ranked_between(
+        &self,
+        start: SystemTime,
+        end: SystemTime,
+        viewer: Option<UserID>,
+    ) -> Vec<Item> {
+        let items = self.alive_items(viewer);
+        let store = self.store.read().unwrap();
+        let mut items: Vec<Item> = items
+            .into_iter()
+            .filter(|item| {
+                !matches!(item.kind, ItemKind::Comment)
+                    && item.time >= start
+                    && item.time < end
+                    && !store.is_penalized(item)
+            })
+            .collect();
+        items.sort_by_key(|item| Reverse(item.votes));
+        items
+    }
+
+    /// All comments, newest first.
+    pub async fn get_comments_newest(&self, viewer: Option<UserID>) -> Vec<Item> {
+        let mut items: Vec<Item> = self
+            .alive_items(viewer)
+            .into_iter()
+            .filter(|item| matches!(item.kind, ItemKind::Comment))
+            .collect();
+        items.sort_by_key(|item| Reverse(item.time));
+        items
+    }
+
+    /// All stories and questions submitted by the given user, newest first.
+    pub async fn get_user_submissions(&self, id: UserID, viewer: Option<UserID>) -> Vec<Item> {
+        let mut items: Vec<Item> = self
+            .alive_items(viewer)
+            .into_iter()
+            .filter(|item| item.by == id && !matches!(item.kind, ItemKind::Comment))
+            .collect();
+        items.sort_by_key(|item| Reverse(item.time));
+        items
+    }
+
+    /// All stories linking to the given site, newest first.
+    /// The site is a registrable domain (e.g. `bbc.co.uk`), with subdomains resolving to it.
+    pub async fn get_news_from_site(&self, site: &str, viewer: Option<UserID>) -> Vec<Item> {
+        let ids = match sites::site_of_host(site) {
+            Some(site) => {
+                let store = self.store.read().unwrap();
+                store.sites.get(&site).cloned().unwrap_or_default()
+            }
+            None => return Vec::new(),
+        };
+        let mut items: Vec<Item> = self
+            .alive_items(viewer)
+            .into_iter()
+            .filter(|item| ids.contains(&item.id))
+            .collect();
+        items.sort_by_key(|item| Reverse(item.time));
+        items
+    }
+}
+
+impl Database {
+    /// Replace the content of an item, keeping its previous content as a revision.
+    /// Returns the edited item, or `None` in case no such (undeleted) item exists.
+    pub async fn edit_item(&self, id: ItemID, by: UserID, content: ItemContent) -> Option<Item> {
+        let mut store = self.store.write().unwrap();
+        let now = SystemTime::now();
+        let item = store
+            .items
+            .iter_mut()
+            .find(|item| item.id == id && !matches!(item.state, ItemState::Deleted))?;
+
+        let previous = ItemContent {
+            title: std::mem::replace(&mut item.title, content.title),
+            text: std::mem::replace(&mut item.text, content.text),
+            url: std::mem::replace(&mut item.url, content.url),
+        };
+        item.mod_time = now;
+        let item = item.clone();
+
+        let previous_site = previous.url.as_deref().and_then(sites::site_of_url);
+        let site = item.url.as_deref().and_then(sites::site_of_url);
+        if previous_site != site {
+            if let Some(ids) = previous_site.and_then(|site| store.sites.get_mut(&site)) {
+                ids.retain(|other| *other != id);
+            }
+            if let Some(site) = site {
+                store.sites.entry(site).or_default().push(id);
+            }
+        }
+
+        store
+            .item_revisions
+            .entry(id)
+            .or_default()
+            .push(ItemRevision {
+                item: id,
+                time: now,
+                by,
+                content: previous,
+            });
+        store.record_action(ActionKind::ItemModified(id));
+        Some(item)
+    }
+
+    /// Delete an item, returning false in case no such (undeleted) item exists.
+    pub async fn delete_item(&self, id: ItemID) -> bool {
+        let mut store = self.store.write().unwrap();
+        match store
+            .items
+            .iter_mut()
+            .find(|item| item.id == id && !matches!(item.state, ItemState::Deleted))
+        {
+            Some(item) => {
+                item.state = ItemState::Deleted;
+                item.mod_time = SystemTime::now();
+            }
+            None => return false,
+        }
+        store.record_action(ActionKind::ItemModified(id));
+        true
+    }
+
+    /// All previous versions of the given item, most recent first.
+    pub async fn get_item_revisions(&self, id: ItemID) -> Vec<ItemRevision> {
+        let store = self.store.read().unwrap();
+        store
+            .item_revisions
+            .get(&id)
+            .map(|revisions| revisions.iter().rev().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// All items which are alive, and thus to be listed,
+    /// except those hidden from the viewer.
+    fn alive_items(&self, viewer: Option<UserID>) -> Vec<Item> {
+        let store = self.store.read().unwrap();
+        store
+            .items
+            .iter()
+            .filter(|item| {
+                matches!(item.state, ItemState::Alive) && store.is_visible_to(item, viewer)
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+impl Database {
+    /// Add (or remove) a relation between a user and an item,
+    /// returning false in case no such item exists.
+    pub async fn set_item_relation(
+        &self,
+        user: UserID,
+        item: ItemID,
+        relation: ItemRelation,
+        enabled: bool,
+    ) -> bool {
+        let mut store = self.store.write().unwrap();
+        if !store.items.iter().any(|other| other.id == item) {
+            return false;
+        }
+        let relations = store.item_relations.entry(user).or_default();
+        match (relation, enabled) {
+            (ItemRelation::Hidden, true) => {
+                relations.hidden.insert(item);
+            }
+            (ItemRelation::Hidden, false) => {
+                relations.hidden.remove(&item);
+            }
+            (ItemRelation::Favorite, true) => {
+                if !relations.favorites.contains(&item) {
+                    relations.favorites.push(item);
+                }
+            }
+            (ItemRelation::Favorite, false) => relations.favorites.retain(|other| *other != item),
+            (ItemRelation::Followed, true) => {
+                relations.followed.entry(item).or_insert(FollowedThread {
+                    seen_time: SystemTime::now(),
+                    new_replies: 0,
+                });
+            }
+            (ItemRelation::Followed, false) => {
+                relations.followed.remove(&item);
+            }
+        }
+        true
+    }
+
+    /// Mark all replies within a thread followed by the user as seen.
+    pub async fn mark_thread_seen(&self, user: UserID, item: ItemID) {
+        let mut store = self.store.write().unwrap();
+        if let Some(thread) = store
+            .item_relations
+            .get_mut(&user)
+            .and_then(|relations| relations.followed.get_mut(&item))
+        {
+            thread.seen_time = SystemTime::now();
+        }
+    }
+
+    /// Allow (or disallow) other users to see the favorites of the user.
+    pub async fn set_public_favorites(&self, user: UserID, public: bool) {
+        let mut store = self.store.write().unwrap();
+        store
+            .item_relations
+            .entry(user)
+            .or_default()
+            .public_favorites = public;
+    }
+
+    /// All relations the user has with items,
+    /// with the replies within followed threads counted.
+    pub async fn get_item_relations(&self, user: UserID) -> ItemRelations {
+        let store = self.store.read().unwrap();
+        let mut relations = store.item_relations.get(&user).cloned().unwrap_or_default();
+        for (item, thread) in relations.followed.iter_mut() {
+            thread.new_replies = store.count_replies_since(*item, thread.seen_time);
+        }
+        relations
+    }
+
+    /// The favorite items of the user, most recently added first.
+    pub async fn get_favorites(&self, user: UserID, viewer: Option<UserID>) -> Vec<Item> {
+        let favorites = match self.store.read().unwrap().item_relations.get(&user) {
+            Some(relations) => relations.favorites.clone(),
+            None => return Vec::new(),
+        };
+        let items = self.alive_items(viewer);
+        favorites
+            .iter()
+            .rev()
+            .filter_map(|id| items.iter().find(|item| item.id == *id).cloned())
+            .collect()
+    }
+}
+
+impl Database {
+    /// All replies made by others (who are not shadow banned) to the items of the given user,
+    /// newest first.
+    pub async fn get_replies(&self, user: UserID) -> Vec<Item> {
+        let store = self.store.read().unwrap();
+        let mut items: Vec<Item> = store.replies_to(user).cloned().collect();
+        items.sort_by_key(|item| Reverse(item.time));
+        items
+    }
+
+    /// Time the user last read the replies to their items, if ever.
+    pub async fn get_replies_seen_time(&self, user: UserID) -> Option<SystemTime> {
+        let store = self.store.read().unwrap();
+        store.replies_seen.get(&user).copied()
+    }
+
+    /// Amount of replies to the items of the user made since they last read them.
+    pub async fn count_unread_replies(&self, user: UserID) -> usize {
+        let store = self.store.read().unwrap();
+        let seen_time = store.replies_seen.get(&user).copied();
+        store
+            .replies_to(user)
+            .filter(|item| !matches!(seen_time, Some(seen_time) if item.time <= seen_time))
+            .count()
+    }
+
+    /// Mark all replies to the items of the user as read.
+    pub async fn mark_replies_seen(&self, user: UserID) {
+        let mut store = self.store.write().unwrap();
+        store.replies_seen.insert(user, SystemTime::now());
+    }
+
+    /// Replace the preferences of a user, returning false in case no such user exists.
+    /// Set the preferences of the user. Their email address stays verified for as long as
+    /// it is unchanged, otherwise it has to be confirmed (again), see [`Database::confirm_email`].
+    pub async fn set_user_preferences(
+        &self,
+        user: UserID,
+        mut preferences: UserPreferences,
+    ) -> bool {
+        let mut store = self.store.write().unwrap();
+        match store.users.iter_mut().find(|other| other.id == user) {
+            Some(user) => {
+                preferences.email_verified = matches!(
+                    &user.preferences,
+                    Some(current) if current.email_verified && current.email == preferences.email
+                );
+                user.preferences = Some(preferences);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Create the (secret) token with which the user can confirm to own the given email address,
+    /// replacing any confirmation still pending for them.
+    pub async fn create_email_confirmation(&self, user: UserID, email: &str) -> String {
+        let mut token = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut token);
+        let token = to_hex(&token);
+        let mut store = self.store.write().unwrap();
+        store.email_confirmations.insert(
+            user,
+            EmailConfirmation {
+                email: email.to_owned(),
+                hash: hash_secret(&token),
+                time: SystemTime::now(),
+            },
+        );
+        token
+    }
+
+    /// Verify the email address of the user, if the token is the one created for it
+    /// less than two days ago and the user did not change their address since.
+    pub async fn confirm_email(&self, user: UserID, token: &str, now: SystemTime) -> bool {
+        let mut store = self.store.write().unwrap();
+        let confirmation = match store.email_confirmations.get(&user) {
+            Some(confirmation) if confirmation.hash == hash_secret(token) => confirmation.clone(),
+            _ => return false,
+        };
+        if now.duration_since(confirmation.time).unwrap_or_default() >= EMAIL_CONFIRMATION_TTL {
+            return false;
+        }
+        let preferences = match store
+            .users
+            .iter_mut()
+            .find(|other| other.id == user)
+            .and_then(|user| user.preferences.as_mut())
+        {
+            Some(preferences) if preferences.email.as_ref() == Some(&confirmation.email) => {
+                preferences
+            }
+            _ => return false,
+        };
+        preferences.email_verified = true;
+        store.email_confirmations.remove(&user);
+        true
+    }
+
+    /// All users due for an email digest at the given time, each with
+    /// the time since which replies are to be included in their digest.
+    pub async fn get_due_digests(&self, now: SystemTime) -> Vec<(User, SystemTime)> {
+        let store = self.store.read().unwrap();
+        store
+            .users
+            .iter()
+            .filter_map(|user| {
+                let preferences = user.preferences.as_ref()?;
+                preferences.email.as_ref()?;
+                if !preferences.email_verified {
+                    return None;
+                }
+                let period = preferences.digest.period()?;
+                let since = match store.digests_sent.get(&user.id) {
+                    Some(sent) if now.duration_since(*sent).ok()? < period => return None,
+                    Some(sent) => *sent,
+                    None => now.checked_sub(period)?,
+                };
+                Some((user.clone(), since))
+            })
+            .collect()
+    }
+
+    /// Register that the user was sent an email digest at the given time.
+    pub async fn mark_digest_sent(&self, user: UserID, time: SystemTime) {
+        let mut store = self.store.write().unwrap();
+        store.digests_sent.insert(user, time);
+    }
+}
 
 impl Database {
-    pub async fn get_news_ranked(&self) -> Vec<Item> {
-        vec![Item {
+    /// The items submitted (by anyone, including shadow banned users) since the given time,
+    /// newest first.
+    pub async fn get_items_since(&self, since: SystemTime) -> Vec<Item> {
+        let store = self.store.read().unwrap();
+        let mut items: Vec<Item> = store
+            .items
+            .iter()
+            .filter(|item| matches!(item.state, ItemState::Alive) && item.time >= since)
+            .cloned()
+            .collect();
+        items.sort_by_key(|item| Reverse(item.time));
+        items
+    }
+
+    /// The users, other than the given one, who used any of the given IPs.
+    pub async fn get_users_sharing_ips(&self, user: UserID, ips: &[String]) -> Vec<User> {
+        let store = self.store.read().unwrap();
+        store
+            .users
+            .iter()
+            .filter(|other| other.id != user && other.ips.iter().any(|ip| ips.contains(ip)))
+            .cloned()
+            .collect()
+    }
+
+    /// The users whose items are only visible to themselves.
+    pub async fn get_shadow_banned_users(&self) -> HashSet<UserID> {
+        let store = self.store.read().unwrap();
+        store.shadow_banned.clone()
+    }
+
+    /// Shadow ban (or pardon) the user, hiding (or revealing)
+    /// all of their items to anyone but themselves.
+    pub async fn set_shadow_banned(&self, user: UserID, banned: bool) {
+        let mut store = self.store.write().unwrap();
+        if banned {
+            store.shadow_banned.insert(user);
+        } else {
+            store.shadow_banned.remove(&user);
+        }
+    }
+
+    /// Log a decision of the spam prevention, shadow banning the user if so decided.
+    pub async fn record_spam_decision(&self, decision: SpamDecision) -> ActionID {
+        let mut store = self.store.write().unwrap();
+        if decision.verdict == SpamVerdict::ShadowBanned {
+            store.shadow_banned.insert(decision.user);
+        }
+        store.record_action(ActionKind::SpamDecision(decision))
+    }
+}
+
+impl Database {
+    /// Log an action, notifying all subscribers of the action log.
+    pub async fn record_action(&self, kind: ActionKind) -> ActionID {
+        let mut store = self.store.write().unwrap();
+        store.record_action(kind)
+    }
+
+    /// The ID of the last logged action, which increases with every write
+    /// of an item or user, and thus serves as the version of the public data.
+    pub async fn data_version(&self) -> ActionID {
+        let store = self.store.read().unwrap();
+        store.actions.len() as ActionID
+    }
+
+    /// Up to `limit` public actions logged after the action with the given ID,
+    /// in the order they happened. Use 0 to start from the first action.
+    pub async fn get_actions(&self, after: ActionID, limit: usize) -> Vec<Action> {
+        let store = self.store.read().unwrap();
+        store
+            .actions
+            .iter()
+            .skip(after as usize)
+            .filter(|action| store.is_public(action))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// Subscribe to the public actions of the log, returning those logged after the action
+    /// with the given ID as well as a receiver of all public actions logged from now on,
+    /// such that no action is missed or received twice.
+    pub async fn subscribe_actions(
+        &self,
+        after: ActionID,
+    ) -> (Vec<Action>, UnboundedReceiver<Action>) {
+        self.subscribe(after, true)
+    }
+
+    /// Subscribe to all actions of the log, including the private ones (such as
+    /// spam decisions), for internal use only, e.g. to invalidate caches.
+    pub async fn subscribe_all_actions(
+        &self,
+        after: ActionID,
+    ) -> (Vec<Action>, UnboundedReceiver<Action>) {
+        self.subscribe(after, false)
+    }
+
+    fn subscribe(
+        &self,
+        after: ActionID,
+        public_only: bool,
+    ) -> (Vec<Action>, UnboundedReceiver<Action>) {
+        let mut store = self.store.write().unwrap();
+        let (sender, rx) = mpsc::unbounded();
+        store.action_subscribers.push(ActionSubscriber {
+            sender,
+            public_only,
+        });
+        let actions = store
+            .actions
+            .iter()
+            .skip(after as usize)
+            .filter(|action| !public_only || store.is_public(action))
+            .cloned()
+            .collect();
+        (actions, rx)
+    }
+}
+
+impl Database {
+    /// Request access to the API, to be approved or rejected by an admin.
+    pub async fn create_api_access_request(
+        &self,
+        owner: ApiKeyOwner,
+        motivation: String,
+    ) -> ApiAccessRequestID {
+        let mut store = self.store.write().unwrap();
+        let id = store.api_access_requests.len() as ApiAccessRequestID + 1;
+        store.api_access_requests.push(ApiAccessRequest {
+            id,
+            owner,
+            motivation,
+            time: SystemTime::now(),
+            state: ApiAccessRequestState::Pending,
+        });
+        id
+    }
+
+    /// All API access requests, most recent first.
+    pub async fn get_api_access_requests(&self) -> Vec<ApiAccessRequest> {
+        let store = self.store.read().unwrap();
+        store.api_access_requests.iter().rev().cloned().collect()
+    }
+
+    /// Approve a pending API access request, issuing a key to the requester.
+    /// Returns the (secret) key, which is not stored and thus can only be shown once.
+    pub async fn approve_api_access_request(
+        &self,
+        id: ApiAccessRequestID,
+        limits: ApiKeyLimits,
+    ) -> Option<String> {
+        let mut store = self.store.write().unwrap();
+        let owner = match store.api_access_requests.iter().find(|r| r.id == id) {
+            Some(request) if request.state == ApiAccessRequestState::Pending => {
+                request.owner.clone()
+            }
+            _ => return None,
+        };
+        let (key_id, key) = store.issue_api_key(owner, limits);
+        if let Some(request) = store.api_access_requests.iter_mut().find(|r| r.id == id) {
+            request.state = ApiAccessRequestState::Approved(key_id);
+        }
+        Some(key)
+    }
+
+    /// Reject a pending API access request,
+    /// returning false if no such request is pending.
+    pub async fn reject_api_access_request(&self, id: ApiAccessRequestID) -> bool {
+        let mut store = self.store.write().unwrap();
+        match store
+            .api_access_requests
+            .iter_mut()
+            .find(|r| r.id == id && r.state == ApiAccessRequestState::Pending)
+        {
+            Some(request) => {
+                request.state = ApiAccessRequestState::Rejected;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Issue a new API key, returning the (secret) key,
+    /// which is not stored and thus can only be shown once.
+    pub async fn issue_api_key(&self, owner: ApiKeyOwner, limits: ApiKeyLimits) -> String {
+        let mut store = self.store.write().unwrap();
+        store.issue_api_key(owner, limits).1
+    }
+
+    /// All API keys, including the revoked ones, most recent first.
+    pub async fn get_api_keys(&self) -> Vec<ApiKey> {
+        let store = self.store.read().unwrap();
+        store.api_keys.iter().rev().cloned().collect()
+    }
+
+    /// Revoke an API key, returning false if no such (unrevoked) key exists.
+    pub async fn revoke_api_key(&self, id: ApiKeyID) -> bool {
+        let mut store = self.store.write().unwrap();
+        match store
+            .api_keys
+            .iter_mut()
+            .find(|key| key.id == id && !key.is_revoked())
+        {
+            Some(key) => {
+                key.revoke_time = Some(SystemTime::now());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Get the API key matching the given (secret) key, if it exists and isn't revoked.
+    pub async fn verify_api_key(&self, key: &str) -> Option<ApiKey> {
+        let prefix = key_prefix(key)?;
+        let hash = hash_secret(key);
+        let store = self.store.read().unwrap();
+        store
+            .api_keys
+            .iter()
+            .find(|api_key| api_key.prefix == prefix && api_key.hash == hash)
+            .filter(|api_key| !api_key.is_revoked())
+            .cloned()
+    }
+}
+
+/// A receiver of the actions logged from now on.
+struct ActionSubscriber {
+    sender: UnboundedSender<Action>,
+    /// Only send the public actions, see [`Store::is_public`].
+    public_only: bool,
+}
+
+impl Store {
+    fn from_snapshot(snapshot: Snapshot) -> Store {
+        let mut sites: HashMap<String, Vec<ItemID>> = HashMap::new();
+        for item in snapshot.items.iter() {
+            if let Some(site) = item.url.as_deref().and_then(sites::site_of_url) {
+                sites.entry(site).or_default().push(item.id);
+            }
+        }
+        Store {
+            users: snapshot.users,
+            items: snapshot.items,
+            item_revisions: snapshot.item_revisions,
+            item_relations: snapshot.item_relations,
+            replies_seen: snapshot.replies_seen,
+            digests_sent: snapshot.digests_sent,
+            email_confirmations: snapshot.email_confirmations,
+            shadow_banned: snapshot.shadow_banned,
+            actions: snapshot.actions,
+            action_subscribers: Vec::new(),
+            api_keys: snapshot.api_keys,
+            api_access_requests: snapshot.api_access_requests,
+            sites,
+            ranking: Ranking::default(),
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            users: self.users.clone(),
+            items: self.items.clone(),
+            item_revisions: self.item_revisions.clone(),
+            item_relations: self.item_relations.clone(),
+            replies_seen: self.replies_seen.clone(),
+            digests_sent: self.digests_sent.clone(),
+            email_confirmations: self.email_confirmations.clone(),
+            shadow_banned: self.shadow_banned.clone(),
+            actions: self.actions.clone(),
+            api_keys: self.api_keys.clone(),
+            api_access_requests: self.api_access_requests.clone(),
+        }
+    }
+
+    /// Returns true if the item can be seen by the viewer, which is not the case
+    /// for the items of shadow banned users, except for those users themselves.
+    fn is_visible_to(&self, item: &Item, viewer: Option<UserID>) -> bool {
+        !self.shadow_banned.contains(&item.by) || viewer == Some(item.by)
+    }
+
+    /// Returns true if the action changed a public profile or an item visible to all,
+    /// such that it can be shared with the consumers of the action log.
+    fn is_public(&self, action: &Action) -> bool {
+        match (action.item(), action.user()) {
+            (Some(id), _) => self
+                .items
+                .iter()
+                .any(|item| item.id == id && self.is_visible_to(item, None)),
+            (None, Some(_)) => true,
+            // e.g. spam decisions, which would reveal shadow bans
+            (None, None) => false,
+        }
+    }
+
+    /// Returns true if the item is kept out of the ranked listings, as it is flagged
+    /// (auto-flagged due to negative votes) or submitted by a fresh account.
+    fn is_penalized(&self, item: &Item) -> bool {
+        if item.votes < 0 {
+            return true;
+        }
+        match self.users.iter().find(|user| user.id == item.by) {
+            Some(user) => user
+                .create_time
+                .elapsed()
+                .map(|age| age < self.ranking.fresh_account_age)
+                .unwrap_or(true),
+            None => false,
+        }
+    }
+
+    /// All (alive) comments made by others directly on the items of the given user.
+    fn replies_to(&self, user: UserID) -> impl Iterator<Item = &Item> {
+        let own: HashSet<ItemID> = self
+            .items
+            .iter()
+            .filter(|item| item.by == user)
+            .map(|item| item.id)
+            .collect();
+        self.items.iter().filter(move |item| {
+            matches!(item.kind, ItemKind::Comment)
+                && matches!(item.state, ItemState::Alive)
+                && item.by != user
+                && !self.shadow_banned.contains(&item.by)
+                && matches!(item.parent, Some(parent) if own.contains(&parent))
+        })
+    }
+
+    /// Amount of (alive) comments made after the given time,
+    /// anywhere within the thread of the given item.
+    fn count_replies_since(&self, item: ItemID, since: SystemTime) -> usize {
+        let parents: HashMap<ItemID, ItemID> = self
+            .items
+            .iter()
+            .filter_map(|other| Some((other.id, other.parent?)))
+            .collect();
+        self.items
+            .iter()
+            .filter(|reply| {
+                matches!(reply.kind, ItemKind::Comment)
+                    && matches!(reply.state, ItemState::Alive)
+                    && !self.shadow_banned.contains(&reply.by)
+                    && reply.time > since
+            })
+            .filter(|reply| {
+                // walk up the thread, guarding against cycles
+                let mut visited = HashSet::new();
+                let mut parent = reply.parent;
+                while let Some(id) = parent {
+                    if id == item {
+                        return true;
+                    }
+                    if !visited.insert(id) {
+                        break;
+                    }
+                    parent = parents.get(&id).copied();
+                }
+                false
+            })
+            .count()
+    }
+
+    fn record_action(&mut self, kind: ActionKind) -> ActionID {
+        let action = Action {
+            id: self.actions.len() as ActionID + 1,
+            time: SystemTime::now(),
+            kind,
+        };
+        let public = self.is_public(&action);
+        self.action_subscribers.retain(|subscriber| {
+            (subscriber.public_only && !public)
+                || subscriber.sender.unbounded_send(action.clone()).is_ok()
+        });
+        self.actions.push(action);
+        self.actions.len() as ActionID
+    }
+
+    fn issue_api_key(&mut self, owner: ApiKeyOwner, limits: ApiKeyLimits) -> (ApiKeyID, String) {
+        let mut rng = rand::thread_rng();
+        let mut prefix = [0u8; 4];
+        let mut secret = [0u8; 24];
+        rng.fill_bytes(&mut prefix);
+        rng.fill_bytes(&mut secret);
+
+        let prefix = format!("{}{}", API_KEY_PREFIX, to_hex(&prefix));
+        let key = format!("{}_{}", prefix, to_hex(&secret));
+
+        let id = self.api_keys.len() as ApiKeyID + 1;
+        self.api_keys.push(ApiKey {
+            id,
+            prefix,
+            hash: hash_secret(&key),
+            owner,
+            limits,
+            create_time: SystemTime::now(),
+            revoke_time: None,
+        });
+        (id, key)
+    }
+}
+
+/// The public part of an API key, e.g. `pn_1a2b3c4d` for `pn_1a2b3c4d_<secret>`.
+fn key_prefix(key: &str) -> Option<&str> {
+    if !key.starts_with(API_KEY_PREFIX) {
+        return None;
+    }
+    key.rfind('_')
+        .filter(|index| *index > API_KEY_PREFIX.len())
+        .map(|index| &key[..index])
+}
+
+/// The hash of a secret (e.g. an API key) as stored, such that the secret itself is not.
+fn hash_secret(secret: &str) -> String {
+    to_hex(&Sha256::digest(secret.as_bytes()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// TODO: replace the example data with actual storage
+
+/// The action log matching the example data, derived from their creation and modification times.
+fn example_actions() -> Vec<Action> {
+    let mut actions: Vec<(SystemTime, ActionKind)> = example_users()
+        .into_iter()
+        .map(|user| (user.create_time, ActionKind::UserCreated(user.id)))
+        .collect();
+    for item in example_items() {
+        actions.push((item.time, ActionKind::ItemCreated(item.id)));
+        if item.mod_time > item.time {
+            actions.push((item.mod_time, ActionKind::ItemModified(item.id)));
+        }
+    }
+    actions.sort_by_key(|(time, _)| *time);
+    actions
+        .into_iter()
+        .enumerate()
+        .map(|(index, (time, kind))| Action {
+            id: index as ActionID + 1,
+            time,
+            kind,
+        })
+        .collect()
+}
+
+fn example_items() -> Vec<Item> {
+    let now = SystemTime::now();
+    vec![
+        Item {
             id: 1,
             state: ItemState::Alive,
             kind: ItemKind::Story,
             by: 100,
-            time: SystemTime::now(),
-            mod_time: SystemTime::now(),
+            time: now - Duration::from_secs(2 * 60 * 60),
+            mod_time: now - Duration::from_secs(2 * 60 * 60),
             votes: 42,
             text: None,
             parent: None,
-            kids: vec![],
+            kids: vec![3],
             url: Some("https://www.example.org/".to_owned()),
             title: Some("an example news article".to_owned()),
-        }]
+        },
+        Item {
+            id: 2,
+            state: ItemState::Alive,
+            kind: ItemKind::Question,
+            by: 101,
+            time: now - Duration::from_secs(30 * 60),
+            mod_time: now - Duration::from_secs(30 * 60),
+            votes: 7,
+            text: Some("What is your favourite _example_?".to_owned()),
+            parent: None,
+            kids: vec![],
+            url: None,
+            title: Some("Ask PN: an example question".to_owned()),
+        },
+        Item {
+            id: 4,
+            state: ItemState::Alive,
+            kind: ItemKind::Story,
+            by: 100,
+            time: now - Duration::from_secs(10 * 60),
+            mod_time: now - Duration::from_secs(10 * 60),
+            votes: -2,
+            text: None,
+            parent: None,
+            kids: vec![],
+            url: Some("https://news.example.com/clickbait".to_owned()),
+            title: Some("an example of a flagged story".to_owned()),
+        },
+        Item {
+            id: 3,
+            state: ItemState::Alive,
+            kind: ItemKind::Comment,
+            by: 101,
+            time: now - Duration::from_secs(60 * 60),
+            mod_time: now - Duration::from_secs(60 * 60),
+            votes: 3,
+            text: Some("A fine example indeed.".to_owned()),
+            parent: Some(1),
+            kids: vec![],
+            url: None,
+            title: None,
+        },
+    ]
+}
+
+fn example_users() -> Vec<User> {
+    let now = SystemTime::now();
+    vec![
+        User {
+            id: 1,
+            state: UserState::Public,
+            kind: UserKind::Admin,
+            username: Some("admin".to_owned()),
+            name: None,
+            locale: None,
+            location: None,
+            create_time: now - Duration::from_secs(365 * 24 * 60 * 60),
+            last_login_time: now,
+            karma: 1,
+            about: None,
+            items: vec![],
+            ips: vec![],
+            authentications: vec![],
+            preferences: None,
+        },
+        User {
+            id: 100,
+            state: UserState::Public,
+            kind: UserKind::Member,
+            username: Some("example".to_owned()),
+            name: None,
+            locale: None,
+            location: None,
+            create_time: now - Duration::from_secs(365 * 24 * 60 * 60),
+            last_login_time: now,
+            karma: 42,
+            about: Some("Just an _example_ user.".to_owned()),
+            items: vec![1, 4],
+            ips: vec![],
+            authentications: vec![],
+            preferences: None,
+        },
+        User {
+            id: 101,
+            state: UserState::Hidden,
+            kind: UserKind::Member,
+            username: None,
+            name: None,
+            locale: None,
+            location: None,
+            create_time: now - Duration::from_secs(30 * 24 * 60 * 60),
+            last_login_time: now,
+            karma: 10,
+            about: None,
+            items: vec![2, 3],
+            ips: vec![],
+            authentications: vec![],
+            preferences: None,
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::DigestFrequency;
+    use futures::executor::block_on;
+    use futures::StreamExt;
+
+    #[test]
+    fn writes_reach_subscribers_of_all_handles() {
+        block_on(async {
+            let db = Database::new();
+            let writer = db.clone();
+            let (_, mut actions) = db.subscribe_actions(ActionID::MAX).await;
+
+            let content = ItemContent {
+                title: Some("Edited".to_owned()),
+                text: None,
+                url: Some("https://plabayo.tech".to_owned()),
+            };
+            assert!(writer.edit_item(1, 100, content).await.is_some());
+
+            let action = actions.next().await.unwrap();
+            assert_eq!(action.item(), Some(1));
+            assert_eq!(
+                db.get_item(1, None).await.unwrap().title.as_deref(),
+                Some("Edited")
+            );
+            assert_eq!(db.data_version().await, action.id);
+        })
+    }
+
+    #[test]
+    fn shadow_banned_items_are_only_visible_to_their_author() {
+        block_on(async {
+            let db = Database::new();
+            db.set_shadow_banned(100, true).await;
+
+            assert!(db.get_item(1, None).await.is_none());
+            assert!(db.get_item(1, Some(101)).await.is_none());
+            assert!(db.get_item(1, Some(100)).await.is_some());
+            assert!(db
+                .get_news_newest(None)
+                .await
+                .iter()
+                .all(|item| item.id != 1));
+            assert!(db
+                .get_news_newest(Some(100))
+                .await
+                .iter()
+                .any(|item| item.id == 1));
+
+            // their actions are kept out of the public action log
+            let after = db.data_version().await;
+            let (_, mut all_actions) = db.subscribe_all_actions(after).await;
+            let content = ItemContent {
+                title: Some("Edited".to_owned()),
+                text: None,
+                url: Some("https://plabayo.tech".to_owned()),
+            };
+            assert!(db.edit_item(1, 100, content).await.is_some());
+            assert_eq!(all_actions.next().await.unwrap().item(), Some(1));
+            assert!(db.get_actions(after, 10).await.is_empty());
+            assert!(db.subscribe_actions(after).await.0.is_empty());
+        })
+    }
+
+    #[test]
+    fn data_is_saved_and_opened() {
+        block_on(async {
+            let path =
+                std::env::temp_dir().join(format!("plabayo-news-{}.json", std::process::id()));
+            let db = Database::open(&path).unwrap();
+            db.set_shadow_banned(100, true).await;
+            db.save(&path).unwrap();
+
+            let reopened = Database::open(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert!(reopened.get_item(1, None).await.is_none());
+            assert_eq!(reopened.data_version().await, db.data_version().await);
+            assert_eq!(
+                reopened.get_user(100).await.map(|user| user.create_time),
+                db.get_user(100).await.map(|user| user.create_time)
+            );
+        })
+    }
+
+    #[test]
+    fn fresh_accounts_are_penalized() {
+        block_on(async {
+            let db = Database::new();
+            let ranked = db.get_news_ranked(None).await.len();
+            db.set_ranking(Ranking {
+                fresh_account_age: Duration::from_secs(100 * 365 * 24 * 60 * 60),
+            });
+            assert!(db.get_news_ranked(None).await.len() < ranked);
+        })
+    }
+
+    #[test]
+    fn email_is_verified_until_changed() {
+        block_on(async {
+            let db = Database::new();
+            let preferences = |email: &str| UserPreferences {
+                email: Some(email.to_owned()),
+                digest: DigestFrequency::Daily,
+                ..UserPreferences::default()
+            };
+            let verified = |db: &Database| {
+                let db = db.clone();
+                async move {
+                    db.get_user(100)
+                        .await
+                        .unwrap()
+                        .preferences
+                        .unwrap()
+                        .email_verified
+                }
+            };
+            let now = SystemTime::now();
+            let due = |db: &Database| {
+                let db = db.clone();
+                async move { db.get_due_digests(now).await.len() }
+            };
+
+            db.set_user_preferences(100, preferences("a@example.com"))
+                .await;
+            let token = db.create_email_confirmation(100, "a@example.com").await;
+            assert_eq!(due(&db).await, 0);
+            assert!(!db.confirm_email(100, "guess", now).await);
+            assert!(!db.confirm_email(101, &token, now).await);
+            assert!(
+                !db.confirm_email(100, &token, now + 2 * EMAIL_CONFIRMATION_TTL)
+                    .await
+            );
+            assert!(db.confirm_email(100, &token, now).await);
+            assert!(verified(&db).await);
+            assert_eq!(due(&db).await, 1);
+            // a token can only be used once
+            assert!(!db.confirm_email(100, &token, now).await);
+
+            // verified for as long as the address is unchanged
+            db.set_user_preferences(100, preferences("a@example.com"))
+                .await;
+            assert!(verified(&db).await);
+            let token = db.create_email_confirmation(100, "b@example.com").await;
+            db.set_user_preferences(100, preferences("b@example.com"))
+                .await;
+            assert!(!verified(&db).await);
+            assert_eq!(due(&db).await, 0);
+            // the address changed again, before it was confirmed
+            db.set_user_preferences(100, preferences("c@example.com"))
+                .await;
+            assert!(!db.confirm_email(100, &token, now).await);
+        })
     }
 }
@@ -16,8 +16,9 @@
 
 mod database;
 pub mod models;
+pub mod sites;
 
-pub use database::Database;
+pub use database::{Database, Ranking, DEFAULT_FRESH_ACCOUNT_AGE};
 
 #[cfg(test)]
 mod tests {
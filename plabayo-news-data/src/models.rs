@@ -14,11 +14,16 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::time::SystemTime;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
 
 /// API Representation of an Item,
 /// containing the info and data of any Post, Question and comment
 /// as stored for Plabayo News and shown on the website.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Item {
     /// The item's unique id.
     pub id: ItemID,
@@ -39,8 +44,9 @@ pub struct Item {
     /// items with negative votes get auto-flagged.
     /// TODO: how to implement flagging?!?!
     pub votes: i64,
-    /// The HTML-formatted text in case of a comment or question,
-    /// not defined in the case of a post.
+    /// The markdown-formatted text in case of a comment or question,
+    /// as written by the user, not defined in the case of a post.
+    /// It is to be rendered as sanitized HTML before it is shown on the website.
     pub text: Option<String>,
     /// An optional ID of the parent item,
     /// which could be a parent comment or the story in case of a root comment,
@@ -55,7 +61,43 @@ pub struct Item {
     pub title: Option<String>,
 }
 
+impl Item {
+    /// Returns true if the given user is allowed to edit or delete this item,
+    /// which is its author within the edit window following its submission,
+    /// and moderators (or admins) at any time. Deleted items can no longer be edited.
+    pub fn is_editable_by(&self, user: &User, edit_window: Duration, now: SystemTime) -> bool {
+        if matches!(self.state, ItemState::Deleted) {
+            return false;
+        }
+        if matches!(user.kind, UserKind::Moderator | UserKind::Admin) {
+            return true;
+        }
+        user.id == self.by && matches!(now.duration_since(self.time), Ok(age) if age < edit_window)
+    }
+}
+
+/// The content of an item that can be edited by its author.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ItemContent {
+    pub title: Option<String>,
+    pub text: Option<String>,
+    pub url: Option<String>,
+}
+
+/// A previous version of the content of an item,
+/// kept each time the item is edited.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ItemRevision {
+    pub item: ItemID,
+    /// Time this version got replaced by an edit.
+    pub time: SystemTime,
+    /// The user that made the edit replacing this version.
+    pub by: UserID,
+    pub content: ItemContent,
+}
+
 /// The possible kinds an Item can be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ItemKind {
     Story,
     Question,
@@ -64,6 +106,7 @@ pub enum ItemKind {
 
 /// The possible states an item can be in,
 /// each item is in exactly one of these states at all times.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ItemState {
     Alive,
     Deleted,
@@ -80,12 +123,15 @@ pub type ItemID = u64;
 /// as well as allow the possibility for a user to stay private if desired.
 pub type UserID = u64;
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct User {
     /// The user's unique ID, auto generated by the system.
     pub id: UserID,
     /// Indicates if the user is public, hidden,
     /// deleted or Locked.
     pub state: UserState,
+    /// The kind of user, defining its privileges.
+    pub kind: UserKind,
     /// An optional username of the user's choosing,
     /// only rules are that it isn't taken yet and follows the site's guidelines.
     pub username: Option<String>,
@@ -105,7 +151,8 @@ pub struct User {
     pub last_login_time: SystemTime,
     /// Karma points collected by the user (can also be negative).
     pub karma: i64,
-    /// The user's optional self-description, HTML Formatted.
+    /// The user's optional self-description, markdown formatted as written by the user,
+    /// and to be rendered as sanitized HTML before it is shown on the website.
     pub about: Option<String>,
     /// The posts, questions and comments submitted by the user,
     /// in the order of creation.
@@ -118,7 +165,9 @@ pub struct User {
     /// to identify itself and proof their authority, as part
     /// of their login procedure. A user requires at least one
     /// form of UserAuthentication.
-    pub authentications: Vec<Box<dyn UserAuthentication>>,
+    // not (yet) stored, as these are not implemented by any form of authentication so far
+    #[serde(skip)]
+    pub authentications: Vec<Arc<dyn UserAuthentication>>,
     /// Optional preferences that can be configured by the user.
     pub preferences: Option<UserPreferences>,
 }
@@ -137,6 +186,7 @@ impl User {
 
 /// The possible states a User can be in,
 /// each user is in exactly one of these states at all times.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum UserState {
     /// Default state of a User. It indicates the user is active,
     /// and allows other users to check this user's profile page/info.
@@ -156,6 +206,7 @@ pub enum UserState {
 }
 
 /// The possible kinds a user can be. The user is only on of these.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum UserKind {
     /// Authorizes the User as a (regular) member,
     /// allowing the user to submit items and store
@@ -173,9 +224,10 @@ pub enum UserKind {
 
 /// A UserAuthentication is used to identify and authenticate the user,
 /// the authorization is defined by what kind of user it is.
-pub trait UserAuthentication {}
+pub trait UserAuthentication: Send + Sync {}
 
 /// Bundles the optional preferences a user can configure.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserPreferences {
     /// Defines the language/locale used for the user,
     /// despite what its browser might define, which is the default.
@@ -184,10 +236,50 @@ pub struct UserPreferences {
     /// Color schema to be used by the user. By default it is defined
     /// by the info provided by the browser (client).
     pub color_schema: ColorSchema,
+    /// Optional email address of the user, only used to send the email digest to.
+    pub email: Option<String>,
+    /// Whether the user confirmed to own the email address,
+    /// as no digests are sent to an address until it is confirmed.
+    pub email_verified: bool,
+    /// How often the user receives an email digest of the replies to their items.
+    pub digest: DigestFrequency,
+}
+
+impl Default for UserPreferences {
+    fn default() -> UserPreferences {
+        UserPreferences {
+            language: UserLanguage::Auto,
+            color_schema: ColorSchema::Auto,
+            email: None,
+            email_verified: false,
+            digest: DigestFrequency::Never,
+        }
+    }
+}
+
+/// How often a user receives an email digest of the replies to their items,
+/// an opt-in feature which is disabled by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DigestFrequency {
+    Never,
+    Daily,
+    Weekly,
+}
+
+impl DigestFrequency {
+    /// The time between two digests, not defined if no digests are to be sent.
+    pub fn period(&self) -> Option<Duration> {
+        match self {
+            DigestFrequency::Never => None,
+            DigestFrequency::Daily => Some(Duration::from_secs(24 * 60 * 60)),
+            DigestFrequency::Weekly => Some(Duration::from_secs(7 * 24 * 60 * 60)),
+        }
+    }
 }
 
 /// Languages (locales) that can be used by the user for the localization
 /// of the website. As such only languages already supported by us can be chosen (found here).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum UserLanguage {
     Auto,
     /// automatically detect the language using the browser's client
@@ -197,6 +289,7 @@ pub enum UserLanguage {
 }
 
 /// The colorSchema for the website.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ColorSchema {
     /// Automatically choose the color schema based on the input of the browser client,
     /// this is default behavior.
@@ -207,7 +300,212 @@ pub enum ColorSchema {
     Dark,
 }
 
+/// The relations a user can add to (and remove from) any item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ItemRelation {
+    /// The item is removed from all listings shown to the user.
+    Hidden,
+    /// The item is added to the favorites of the user.
+    Favorite,
+    /// New replies in the thread of the item are surfaced to the user.
+    Followed,
+}
+
+/// All relations a user has with items.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ItemRelations {
+    pub hidden: HashSet<ItemID>,
+    /// The favorite items of the user, most recently added last.
+    pub favorites: Vec<ItemID>,
+    /// Whether or not the favorites of the user can be seen by others.
+    pub public_favorites: bool,
+    pub followed: HashMap<ItemID, FollowedThread>,
+}
+
+/// A thread followed by a user.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct FollowedThread {
+    /// Time the user last caught up with the replies of the thread.
+    pub seen_time: SystemTime,
+    /// Amount of replies in the thread made after the `seen_time`.
+    pub new_replies: usize,
+}
+
 /// Used to keep a log of actions happening on the website,
 /// to keep track of how karma has been affected, post votes,
 /// user and item state.
-pub struct Action {} // TODO
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Action {
+    /// The action's unique ID, increasing in the order the actions happened,
+    /// such that it can be used as a cursor within the log.
+    pub id: ActionID,
+    /// Time the action happened.
+    pub time: SystemTime,
+    /// What happened, and to which item or user.
+    pub kind: ActionKind,
+}
+
+impl Action {
+    /// The ID of the item changed by this action, if any.
+    pub fn item(&self) -> Option<ItemID> {
+        match self.kind {
+            ActionKind::ItemCreated(id) | ActionKind::ItemModified(id) => Some(id),
+            ActionKind::UserCreated(_)
+            | ActionKind::UserModified(_)
+            | ActionKind::SpamDecision(_) => None,
+        }
+    }
+
+    /// The ID of the user (profile) changed by this action, if any.
+    pub fn user(&self) -> Option<UserID> {
+        match self.kind {
+            ActionKind::UserCreated(id) | ActionKind::UserModified(id) => Some(id),
+            // spam decisions are kept private, as they would reveal shadow bans
+            ActionKind::ItemCreated(_)
+            | ActionKind::ItemModified(_)
+            | ActionKind::SpamDecision(_) => None,
+        }
+    }
+}
+
+/// The unique ID (identifier) of an action,
+/// starting from 1 for the first action ever logged.
+pub type ActionID = u64;
+
+/// The possible kinds of actions logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ActionKind {
+    /// An item was submitted.
+    ItemCreated(ItemID),
+    /// An item was modified, e.g. edited, voted on, (un)locked or deleted.
+    ItemModified(ItemID),
+    /// A user registered.
+    UserCreated(UserID),
+    /// A user was modified, e.g. its profile, karma or state.
+    UserModified(UserID),
+    /// Content submitted by a user was refused, or got the user shadow banned.
+    SpamDecision(SpamDecision),
+}
+
+/// A decision made by the spam prevention on content submitted by a user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpamDecision {
+    /// The user who submitted the content.
+    pub user: UserID,
+    /// The item the content was submitted for, none in case of a new item.
+    pub item: Option<ItemID>,
+    pub verdict: SpamVerdict,
+    pub reason: SpamReason,
+}
+
+/// What happens to content considered to be spam.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpamVerdict {
+    /// The content is refused, letting the user know why.
+    Rejected,
+    /// The content is accepted, but the user is shadow banned,
+    /// making all of their items visible only to themselves.
+    ShadowBanned,
+}
+
+/// Why content is considered to be spam.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpamReason {
+    /// The user submitted too many items while on probation.
+    ProbationLimit,
+    /// The user linked to a site that is not allowed while on probation.
+    ProbationLink,
+    /// The content links to a blocked site.
+    BlockedDomain,
+    /// The same text was recently submitted already.
+    DuplicateText,
+    /// The comment contains too many links.
+    TooManyLinks,
+    /// The user shares an address with a banned (or locked) user.
+    BanEvasion,
+}
+
+/// The unique ID (identifier) of an API key.
+pub type ApiKeyID = u64;
+
+/// A key giving access to the Plabayo News API,
+/// of which only a hash of the secret is stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    /// The key's unique ID, auto generated by the system.
+    pub id: ApiKeyID,
+    /// The public (non-secret) part of the key,
+    /// used to identify the key when it is used.
+    pub prefix: String,
+    /// Hash of the full key, the key itself is only shown once, when issued.
+    pub hash: String,
+    /// The user or organization the key was issued to.
+    pub owner: ApiKeyOwner,
+    /// The rate limit and quota of the key.
+    pub limits: ApiKeyLimits,
+    /// Time the key was issued.
+    pub create_time: SystemTime,
+    /// Time the key was revoked, a revoked key can no longer be used.
+    pub revoke_time: Option<SystemTime>,
+}
+
+impl ApiKey {
+    pub fn is_revoked(&self) -> bool {
+        self.revoke_time.is_some()
+    }
+}
+
+/// The owner of an API key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ApiKeyOwner {
+    User(UserID),
+    /// An organization, represented by the user requesting access on its behalf.
+    Organization {
+        name: String,
+        contact: UserID,
+    },
+}
+
+/// The usage allowed for a single API key.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ApiKeyLimits {
+    /// Maximum amount of requests within a minute.
+    pub requests_per_minute: u32,
+    /// Maximum amount of requests within a (UTC) day.
+    pub requests_per_day: u32,
+}
+
+impl Default for ApiKeyLimits {
+    fn default() -> ApiKeyLimits {
+        ApiKeyLimits {
+            requests_per_minute: 60,
+            requests_per_day: 10_000,
+        }
+    }
+}
+
+/// The unique ID (identifier) of an API access request.
+pub type ApiAccessRequestID = u64;
+
+/// A formal request for access to the Plabayo News API,
+/// which is to be approved by an admin before a key is issued.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiAccessRequest {
+    pub id: ApiAccessRequestID,
+    /// The user or organization requesting access.
+    pub owner: ApiKeyOwner,
+    /// The motivation for the data and its purpose.
+    pub motivation: String,
+    /// Time the request was made.
+    pub time: SystemTime,
+    pub state: ApiAccessRequestState,
+}
+
+/// The possible states an API access request can be in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ApiAccessRequestState {
+    Pending,
+    /// Approved, resulting in the key with the given ID.
+    Approved(ApiKeyID),
+    Rejected,
+}
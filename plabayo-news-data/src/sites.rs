@@ -0,0 +1,125 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Identification of the site a story links to, being the registrable domain
+//! of its URL according to the Public Suffix List (e.g. `bbc.co.uk` for `news.bbc.co.uk`).
+
+use url::{Host, Url};
+
+/// The site of the given URL, `None` in case it is not an absolute URL with a host.
+pub fn site_of_url(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    match url.host()? {
+        Host::Domain(domain) => site_of_host(domain),
+        // IP addresses have no (registrable) domain, and are a site of their own
+        host => Some(host.to_string()),
+    }
+}
+
+/// The site of the given host, e.g. `bbc.co.uk` for `news.bbc.co.uk`,
+/// or the host itself in case it has no known public suffix (e.g. `localhost`).
+pub fn site_of_host(host: &str) -> Option<String> {
+    let host = host.trim().trim_end_matches('.').to_lowercase();
+    if host.is_empty() {
+        return None;
+    }
+    Some(psl::domain_str(&host).unwrap_or(&host).to_owned())
+}
+
+/// Whether the given string is a valid (ASCII) host name, such as `news.bbc.co.uk`,
+/// ignoring surrounding whitespace and the trailing dot of a fully qualified name.
+pub fn is_host_name(host: &str) -> bool {
+    let host = host.trim().trim_end_matches('.');
+    !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn site_is_registrable_domain() {
+        assert_eq!(
+            site_of_url("https://news.bbc.co.uk/world").as_deref(),
+            Some("bbc.co.uk")
+        );
+        assert_eq!(
+            site_of_url("http://WWW.Example.org:8080/?q=1").as_deref(),
+            Some("example.org")
+        );
+        assert_eq!(
+            site_of_url("https://plabayo.github.io/news").as_deref(),
+            Some("plabayo.github.io")
+        );
+    }
+
+    #[test]
+    fn site_of_hosts_without_domain() {
+        assert_eq!(
+            site_of_url("http://127.0.0.1/").as_deref(),
+            Some("127.0.0.1")
+        );
+        assert_eq!(
+            site_of_url("http://localhost:8080").as_deref(),
+            Some("localhost")
+        );
+        assert_eq!(site_of_url("/relative/path"), None);
+        assert_eq!(site_of_url("mailto:pn@plabayo.tech"), None);
+    }
+
+    #[test]
+    fn site_of_host_normalizes() {
+        assert_eq!(
+            site_of_host("News.BBC.co.uk.").as_deref(),
+            Some("bbc.co.uk")
+        );
+        assert_eq!(site_of_host(" "), None);
+    }
+
+    #[test]
+    fn host_names_are_validated() {
+        for host in [
+            "spam.com",
+            "News.BBC.co.uk.",
+            "localhost",
+            "127.0.0.1",
+            "xn--bcher-kva.de",
+        ] {
+            assert!(is_host_name(host), "{}", host);
+        }
+        for host in [
+            "",
+            ".",
+            "https://spam.com",
+            "spam.com/path",
+            "spam .com",
+            "spam..com",
+            "-spam.com",
+            "spam_com.org",
+            "bücher.de",
+        ] {
+            assert!(!is_host_name(host), "{}", host);
+        }
+    }
+}
@@ -14,11 +14,353 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+//! Sending of plain text emails (e.g. the reply digests) using
+//! a local `sendmail` compatible program, such as the one provided by postfix or msmtp,
+//! or an SMTP relay.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+
+/// A plain text email.
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Email {
+    /// The email formatted as an (RFC 5322) message,
+    /// failing in case any of the headers would allow header injection.
+    pub fn to_message(&self) -> Result<String> {
+        for (name, value) in [
+            ("From", &self.from),
+            ("To", &self.to),
+            ("Subject", &self.subject),
+        ] {
+            if value.contains(['\r', '\n']) {
+                bail!("invalid {} header: contains a line break", name);
+            }
+        }
+        Ok(format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nMIME-Version: 1.0\r\n\
+             Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n{}",
+            self.from,
+            self.to,
+            encode_header(&self.subject),
+            self.body.replace("\r\n", "\n").replace('\n', "\r\n"),
+        ))
+    }
+}
+
+/// The header value as is if it is ASCII, or otherwise encoded as (RFC 2047) encoded words,
+/// each short enough to keep the lines of the header within the limit of 78 characters.
+fn encode_header(value: &str) -> String {
+    if value.is_ascii() {
+        return value.to_owned();
+    }
+    // 39 bytes are encoded as 52 base64 characters, leaving room for the name of the header
+    const MAX_CHUNK: usize = 39;
+    let mut words = Vec::new();
+    let mut rest = value;
+    while !rest.is_empty() {
+        let mut end = rest.len().min(MAX_CHUNK);
+        // never split a character over two words
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        words.push(format!("=?UTF-8?B?{}?=", base64(&rest.as_bytes()[..end])));
+        rest = &rest[end..];
+    }
+    words.join("\r\n ")
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &byte)| n | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// A way to deliver emails.
+pub trait Transport {
+    fn send(&self, email: &Email) -> Result<()>;
+}
+
+/// Delivers emails by piping them into a `sendmail` compatible program,
+/// which reads the recipients from the message itself.
+#[derive(Debug, Clone)]
+pub struct Sendmail {
+    program: PathBuf,
+}
+
+impl Sendmail {
+    pub fn new(program: impl Into<PathBuf>) -> Sendmail {
+        Sendmail {
+            program: program.into(),
+        }
+    }
+}
+
+impl Default for Sendmail {
+    fn default() -> Sendmail {
+        Sendmail::new("/usr/sbin/sendmail")
+    }
+}
+
+impl Transport for Sendmail {
+    fn send(&self, email: &Email) -> Result<()> {
+        let message = email.to_message()?;
+        let mut child = Command::new(&self.program)
+            .args(["-t", "-i"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .with_context(|| format!("spawn sendmail program {}", self.program.display()))?;
+        child
+            .stdin
+            .take()
+            .context("open stdin of sendmail program")?
+            .write_all(message.as_bytes())
+            .context("write message to sendmail program")?;
+        let status = child.wait().context("wait for sendmail program")?;
+        if !status.success() {
+            bail!("sendmail program failed with {}", status);
+        }
+        Ok(())
+    }
+}
+
+/// Delivers emails to an SMTP relay, such as a local postfix, trusted to deliver them further:
+/// the emails are submitted without authentication nor TLS.
+#[derive(Debug, Clone)]
+pub struct Smtp {
+    /// Address of the relay, e.g. `localhost:25`.
+    server: String,
+    /// Name of this host, as sent in the greeting.
+    hello: String,
+    timeout: Duration,
+}
+
+impl Smtp {
+    pub fn new(server: impl Into<String>, hello: impl Into<String>) -> Smtp {
+        Smtp {
+            server: server.into(),
+            hello: hello.into(),
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Transport for Smtp {
+    fn send(&self, email: &Email) -> Result<()> {
+        // also guards the commands below against injection
+        let message = email.to_message()?;
+        let stream = TcpStream::connect(&self.server)
+            .with_context(|| format!("connect to SMTP server {}", self.server))?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let mut session = SmtpSession {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+
+        session.reply(&[220])?;
+        session.command(&format!("HELO {}", self.hello), &[250])?;
+        session.command(&format!("MAIL FROM:<{}>", address(&email.from)), &[250])?;
+        session.command(&format!("RCPT TO:<{}>", address(&email.to)), &[250, 251])?;
+        session.command("DATA", &[354])?;
+        // lines starting with a dot are escaped, as a dot on its own line ends the message
+        let mut data = message.replace("\r\n.", "\r\n..");
+        if data.starts_with('.') {
+            data.insert(0, '.');
+        }
+        data.push_str("\r\n.");
+        session.command(&data, &[250])?;
+        session.command("QUIT", &[221])
+    }
+}
+
+struct SmtpSession {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl SmtpSession {
+    fn command(&mut self, command: &str, expected: &[u16]) -> Result<()> {
+        write!(self.writer, "{}\r\n", command).context("write SMTP command")?;
+        self.reply(expected)
+    }
+
+    /// Read the (possibly multiline) reply, failing unless its code is one of those expected.
+    fn reply(&mut self, expected: &[u16]) -> Result<()> {
+        loop {
+            let mut line = String::new();
+            if self
+                .reader
+                .read_line(&mut line)
+                .context("read SMTP reply")?
+                == 0
+            {
+                bail!("SMTP server closed the connection");
+            }
+            let code: u16 = match line.get(..3).map(str::parse) {
+                Some(Ok(code)) => code,
+                _ => bail!("invalid SMTP reply: {}", line.trim_end()),
+            };
+            // all but the last line of a reply have a dash after their code
+            if line[3..].starts_with('-') {
+                continue;
+            }
+            if !expected.contains(&code) {
+                bail!("unexpected SMTP reply: {}", line.trim_end());
+            }
+            return Ok(());
+        }
+    }
+}
+
+/// The address of a mailbox such as `Plabayo News <news@plabayo.tech>`.
+fn address(mailbox: &str) -> &str {
+    match mailbox.rsplit_once('<') {
+        Some((_, address)) => address.trim_end().trim_end_matches('>'),
+        None => mailbox.trim(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    fn email() -> Email {
+        Email {
+            from: "Plabayo News <news@example.com>".to_owned(),
+            to: "user@example.com".to_owned(),
+            subject: "Replies".to_owned(),
+            body: "hello\nworld".to_owned(),
+        }
+    }
+
+    #[test]
+    fn message_has_headers_and_crlf_body() {
+        let message = email().to_message().unwrap();
+        assert!(message.starts_with("From: Plabayo News <news@example.com>\r\n"));
+        assert!(message.contains("\r\nTo: user@example.com\r\n"));
+        assert!(message.ends_with("\r\n\r\nhello\r\nworld"));
+    }
+
+    #[test]
+    fn non_ascii_subject_is_encoded() {
+        let reply = Email {
+            subject: "1 nieuwe reactie op « Plabayo News » — bekijk ze nu allemaal".to_owned(),
+            ..email()
+        };
+        let message = reply.to_message().unwrap();
+        let subject = message
+            .split("\r\n")
+            .skip_while(|line| !line.starts_with("Subject: "))
+            .take_while(|line| line.starts_with("Subject: ") || line.starts_with(' '))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            subject,
+            [
+                "Subject: =?UTF-8?B?MSBuaWV1d2UgcmVhY3RpZSBvcCDCqyBQbGFiYXlvIE5ld3Mgwrsg?=",
+                " =?UTF-8?B?4oCUIGJla2lqayB6ZSBudSBhbGxlbWFhbA==?=",
+            ]
+        );
+        assert!(subject.iter().all(|line| line.len() <= 78));
+        assert!(email()
+            .to_message()
+            .unwrap()
+            .contains("\r\nSubject: Replies\r\n"));
+    }
+
     #[test]
-    fn it_works() {
-        let result = 2 + 2;
-        assert_eq!(result, 4);
+    fn base64_is_padded() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64("€".as_bytes()), "4oKs");
+    }
+
+    #[test]
+    fn message_rejects_header_injection() {
+        let email = Email {
+            to: "user@example.com\r\nBcc: victim@example.com".to_owned(),
+            ..email()
+        };
+        assert!(email.to_message().is_err());
+    }
+
+    #[test]
+    fn smtp_submits_escaped_message() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        let relay = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut received = Vec::new();
+            writer.write_all(b"220-relay\r\n220 ready\r\n").unwrap();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                let line = line.trim_end_matches("\r\n").to_owned();
+                let reply: &[u8] = if in_data {
+                    if line != "." {
+                        received.push(line);
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 queued\r\n"
+                } else if line == "DATA" {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                } else if line == "QUIT" {
+                    b"221 bye\r\n"
+                } else {
+                    b"250 ok\r\n"
+                };
+                received.push(line);
+                writer.write_all(reply).unwrap();
+            }
+            received
+        });
+
+        let email = Email {
+            body: ".hidden\n.".to_owned(),
+            ..email()
+        };
+        Smtp::new(server, "localhost").send(&email).unwrap();
+        let received = relay.join().unwrap();
+        assert_eq!(received[0], "HELO localhost");
+        assert_eq!(received[1], "MAIL FROM:<news@example.com>");
+        assert_eq!(received[2], "RCPT TO:<user@example.com>");
+        assert_eq!(received[3], "DATA");
+        assert!(received.contains(&"..hidden".to_owned()));
+        assert!(received.contains(&"..".to_owned()));
+        assert_eq!(received.last().map(String::as_str), Some("QUIT"));
     }
 }
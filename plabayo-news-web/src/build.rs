@@ -14,8 +14,13 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
 use actix_web_static_files::resource_dir;
-use anyhow::Result;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
 use vergen::{vergen, Config};
 
 use plabayo_news_builder::i18n;
@@ -32,6 +37,78 @@ fn main() -> Result<()> {
     // and make the setup of the news web server easier.
     resource_dir("./site/assets").build()?;
 
+    // Hash the same resources, used as their (strong) ETag
+    // and to serve them at fingerprinted paths.
+    build_asset_hashes("./site/assets")?;
+
     // All good.
     Ok(())
 }
+
+/// Amount of (hex) characters of the content hash used to fingerprint an asset name.
+const ASSET_FINGERPRINT_LEN: usize = 16;
+
+/// Generate the lookup functions of the content hashes of all bundled assets:
+///
+/// - `hashes()`: maps each asset to the (hex encoded) sha256 hash of its content;
+/// - `fingerprinted_name(name)`: the name of an asset with (a prefix of) its hash
+///   inserted before its extension, e.g. `style.<hash>.css`;
+/// - `original_name(fingerprinted)`: the inverse of `fingerprinted_name`.
+fn build_asset_hashes(dir: &str) -> Result<()> {
+    let mut assets = Vec::new();
+    collect_assets(Path::new(dir), "", &mut assets)?;
+    assets.sort();
+
+    let mut hashes = Vec::with_capacity(assets.len());
+    for (name, data) in assets {
+        let mut hash = String::new();
+        for b in Sha256::digest(&data).iter() {
+            write!(hash, "{:02x}", b)?;
+        }
+        let fingerprint = &hash[..ASSET_FINGERPRINT_LEN];
+        let fingerprinted = match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() && !stem.ends_with('/') => {
+                format!("{}.{}.{}", stem, fingerprint, ext)
+            }
+            _ => format!("{}.{}", name, fingerprint),
+        };
+        hashes.push((name, hash, fingerprinted));
+    }
+
+    let mut code = String::from(
+        "pub fn hashes() -> ::std::collections::HashMap<&'static str, &'static str> {\n    let mut r = ::std::collections::HashMap::new();\n",
+    );
+    for (name, hash, _) in &hashes {
+        writeln!(code, "    r.insert({:?}, {:?});", name, hash)?;
+    }
+    code.push_str("    r\n}\n\npub fn fingerprinted_name(name: &str) -> Option<&'static str> {\n    match name {\n");
+    for (name, _, fingerprinted) in &hashes {
+        writeln!(code, "        {:?} => Some({:?}),", name, fingerprinted)?;
+    }
+    code.push_str("        _ => None,\n    }\n}\n\npub fn original_name(fingerprinted: &str) -> Option<&'static str> {\n    match fingerprinted {\n");
+    for (name, _, fingerprinted) in &hashes {
+        writeln!(code, "        {:?} => Some({:?}),", fingerprinted, name)?;
+    }
+    code.push_str("        _ => None,\n    }\n}\n");
+
+    let out_dir = std::env::var("OUT_DIR").context("read OUT_DIR env variable")?;
+    let path = Path::new(&out_dir).join("asset_hashes.rs");
+    fs::write(&path, code).with_context(|| format!("write asset hashes to {}", path.display()))
+}
+
+/// Read all files found (recursively) in the given directory,
+/// named by their path relative to the root asset directory.
+fn collect_assets(dir: &Path, prefix: &str, assets: &mut Vec<(String, Vec<u8>)>) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("read dir {}", dir.display()))? {
+        let entry = entry?;
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        let path = entry.path();
+        if path.is_dir() {
+            collect_assets(&path, &format!("{}/", name), assets)?;
+        } else {
+            let data = fs::read(&path).with_context(|| format!("read {}", path.display()))?;
+            assets.push((name, data));
+        }
+    }
+    Ok(())
+}
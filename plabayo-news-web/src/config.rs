@@ -0,0 +1,600 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Configuration of the web server, layered from (lowest to highest priority)
+//! the defaults, an optional TOML file, `PLABAYO_NEWS_*` environment variables
+//! and finally the command line flags.
+//!
+//! Environment variables are named after the keys of the file, using `__`
+//! to separate the keys of a section, e.g. `PLABAYO_NEWS_MAIL__FROM`
+//! for the `from` key in the `[mail]` section.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Error, Result};
+use serde::{Deserialize, Serialize};
+use toml::value::{Table, Value};
+
+use plabayo_news_data::{sites, Ranking, DEFAULT_FRESH_ACCOUNT_AGE};
+use plabayo_news_sendmail::Smtp;
+
+use crate::site::csrf::CsrfSecret;
+use crate::site::rate_limit::Limits;
+use crate::site::spam::SpamPolicy;
+use crate::site::state::DEFAULT_ORIGIN;
+
+/// Prefix of all environment variables configuring the server.
+pub const ENV_PREFIX: &str = "PLABAYO_NEWS_";
+
+/// Environment variable defining the path of the configuration file.
+pub const ENV_CONFIG_FILE: &str = "PLABAYO_NEWS_CONFIG";
+
+/// Shown instead of secrets when printing the configuration.
+const REDACTED: &str = "<redacted>";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Interface to bind to.
+    pub interface: String,
+    /// Public origin of the site, used for its absolute links,
+    /// such as those within emails and the locale alternates of its pages,
+    /// and the only origin from which its forms can be submitted.
+    pub origin: String,
+    /// Addresses of the reverse proxies trusted to forward the address of the client
+    /// (using the `X-Forwarded-For` header), which is otherwise never looked at.
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Amount of worker threads, one per CPU core by default.
+    pub workers: Option<usize>,
+    /// Enable debugging features such as the logger.
+    pub debug: bool,
+    pub log_format: LogFormat,
+    /// Time (in minutes) within which authors can still edit and delete their own items.
+    pub edit_window: u64,
+    /// Hex encoded (32 bytes) secret from which the CSRF tokens are derived,
+    /// generated at startup if not defined, invalidating the forms of a previous run.
+    pub csrf_secret: Option<String>,
+    /// URI to which browsers report violations of the Content-Security-Policy.
+    pub csp_report_uri: Option<String>,
+    pub database: DatabaseConfig,
+    pub api: ApiConfig,
+    pub mail: MailConfig,
+    pub rate_limits: Limits,
+    pub ranking: RankingConfig,
+    pub spam: SpamConfig,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            interface: "127.0.0.1:8080".to_owned(),
+            origin: DEFAULT_ORIGIN.to_owned(),
+            trusted_proxies: Vec::new(),
+            workers: None,
+            debug: false,
+            log_format: LogFormat::Text,
+            edit_window: 120,
+            csrf_secret: None,
+            csp_report_uri: None,
+            database: DatabaseConfig::default(),
+            api: ApiConfig::default(),
+            mail: MailConfig::default(),
+            rate_limits: Limits::default(),
+            ranking: RankingConfig::default(),
+            spam: SpamConfig::default(),
+        }
+    }
+}
+
+/// Settings of the database, kept in memory and saved to disk periodically.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// File the database is saved to (and opened from at startup),
+    /// the (example) data is lost on exit if not defined.
+    pub path: Option<PathBuf>,
+    /// Time (in seconds) between two saves of the database, which is saved on exit as well.
+    pub save_interval: u64,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> DatabaseConfig {
+        DatabaseConfig {
+            path: None,
+            save_interval: 60,
+        }
+    }
+}
+
+/// Settings of the JSON API, served by the same process as the website,
+/// as both need to share the (in-memory) database.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    /// Serve the API next to the website.
+    pub enabled: bool,
+    /// Interface to bind the API to.
+    pub interface: String,
+}
+
+impl Default for ApiConfig {
+    fn default() -> ApiConfig {
+        ApiConfig {
+            enabled: true,
+            interface: "127.0.0.1:8081".to_owned(),
+        }
+    }
+}
+
+/// Settings of the emails (digests) sent by the site.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailConfig {
+    /// Sendmail compatible program used to send the email digests,
+    /// no digests are sent if not defined.
+    pub sendmail: Option<PathBuf>,
+    /// Sender of the emails sent.
+    pub from: String,
+    /// SMTP relay used to send the email digests instead of a sendmail program.
+    pub smtp: Option<SmtpConfig>,
+}
+
+impl Default for MailConfig {
+    fn default() -> MailConfig {
+        MailConfig {
+            sendmail: None,
+            from: "Plabayo News <news@plabayo.tech>".to_owned(),
+            smtp: None,
+        }
+    }
+}
+
+/// An SMTP relay trusted to deliver the emails sent,
+/// as they are submitted without authentication nor TLS, see [`Smtp`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SmtpConfig {
+    /// Address of the relay.
+    pub server: String,
+    /// Name of this host, as sent to the relay.
+    pub hello: String,
+}
+
+impl Default for SmtpConfig {
+    fn default() -> SmtpConfig {
+        SmtpConfig {
+            server: "localhost:25".to_owned(),
+            hello: "localhost".to_owned(),
+        }
+    }
+}
+
+impl SmtpConfig {
+    pub fn transport(&self) -> Smtp {
+        Smtp::new(&self.server, &self.hello)
+    }
+}
+
+/// Settings of the ranked listings, see [`Ranking`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RankingConfig {
+    /// Hours after its creation during which an account is considered fresh,
+    /// keeping its submissions out of the ranked listings.
+    pub fresh_account_hours: u64,
+}
+
+impl Default for RankingConfig {
+    fn default() -> RankingConfig {
+        RankingConfig {
+            fresh_account_hours: DEFAULT_FRESH_ACCOUNT_AGE.as_secs() / (60 * 60),
+        }
+    }
+}
+
+impl RankingConfig {
+    pub fn ranking(&self) -> Ranking {
+        Ranking {
+            fresh_account_age: Duration::from_secs(self.fresh_account_hours * 60 * 60),
+        }
+    }
+}
+
+/// Settings of the spam prevention, see [`SpamPolicy`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpamConfig {
+    /// Days after its creation during which an account is on probation.
+    pub probation_days: u64,
+    pub probation_karma: i64,
+    pub probation_max_items: usize,
+    pub max_comment_links: usize,
+    pub blocked_domains: Vec<String>,
+    pub allowed_domains: Vec<String>,
+}
+
+impl Default for SpamConfig {
+    fn default() -> SpamConfig {
+        let policy = SpamPolicy::default();
+        SpamConfig {
+            probation_days: policy.probation.as_secs() / (24 * 60 * 60),
+            probation_karma: policy.probation_karma,
+            probation_max_items: policy.probation_max_items,
+            max_comment_links: policy.max_comment_links,
+            blocked_domains: Vec::new(),
+            allowed_domains: Vec::new(),
+        }
+    }
+}
+
+impl SpamConfig {
+    pub fn policy(&self) -> SpamPolicy {
+        let sites = |domains: &[String]| {
+            domains
+                .iter()
+                .filter_map(|domain| sites::site_of_host(domain))
+                .collect()
+        };
+        SpamPolicy {
+            probation: Duration::from_secs(self.probation_days * 24 * 60 * 60),
+            probation_karma: self.probation_karma,
+            probation_max_items: self.probation_max_items,
+            max_comment_links: self.max_comment_links,
+            blocked_domains: sites(&self.blocked_domains),
+            allowed_domains: sites(&self.allowed_domains),
+            ..SpamPolicy::default()
+        }
+    }
+}
+
+/// Format of the lines logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines, as formatted by `env_logger`.
+    Text,
+    /// A JSON object per line, for log aggregators.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<LogFormat> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(anyhow!("unknown log format '{}', expected text or json", s)),
+        }
+    }
+}
+
+impl Config {
+    /// Load the configuration from the defaults, the given file (if any)
+    /// and the given environment variables, the latter taking precedence.
+    pub fn load(
+        file: Option<&Path>,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Config> {
+        let mut value = Value::try_from(Config::default()).context("serialize default config")?;
+        let schema = Value::try_from(Config::schema()).context("serialize config schema")?;
+        if let Some(file) = file {
+            let content = std::fs::read_to_string(file)
+                .with_context(|| format!("read config file {}", file.display()))?;
+            let table: Table = toml::from_str(&content)
+                .with_context(|| format!("parse config file {}", file.display()))?;
+            merge(&mut value, Value::Table(table));
+        }
+        for (name, raw) in vars {
+            let key = match name.strip_prefix(ENV_PREFIX) {
+                Some(key) if name != ENV_CONFIG_FILE => key.to_lowercase(),
+                _ => continue,
+            };
+            set_env_value(&mut value, &schema, &key, &raw)
+                .with_context(|| format!("apply environment variable {}", name))?;
+        }
+        value.try_into().context("invalid config")
+    }
+
+    /// The default configuration with all of its optional settings defined,
+    /// such that the type of every setting can be looked up by its key.
+    fn schema() -> Config {
+        Config {
+            workers: Some(1),
+            csrf_secret: Some(String::new()),
+            csp_report_uri: Some(String::new()),
+            database: DatabaseConfig {
+                path: Some(PathBuf::new()),
+                ..DatabaseConfig::default()
+            },
+            mail: MailConfig {
+                sendmail: Some(PathBuf::new()),
+                smtp: Some(SmtpConfig::default()),
+                ..MailConfig::default()
+            },
+            ..Config::default()
+        }
+    }
+
+    /// Validate the settings that cannot be expressed by their type alone,
+    /// such that a misconfiguration is reported at startup.
+    pub fn validate(&self) -> Result<()> {
+        validate_interface(&self.interface).context("invalid interface")?;
+        if self.api.enabled {
+            validate_interface(&self.api.interface).context("invalid api.interface")?;
+            if self.api.interface == self.interface {
+                bail!("api.interface cannot be the interface of the website");
+            }
+        }
+        if self.workers == Some(0) {
+            bail!("workers must be at least 1");
+        }
+        if self.edit_window == 0 {
+            bail!("edit_window must be at least 1 minute");
+        }
+        if let Some(secret) = &self.csrf_secret {
+            CsrfSecret::from_hex(secret).context("invalid csrf_secret")?;
+        }
+        if self.database.save_interval == 0 {
+            bail!("database.save_interval must be at least 1 second");
+        }
+        if let Some(sendmail) = &self.mail.sendmail {
+            if !sendmail.is_file() {
+                bail!("mail.sendmail program {} not found", sendmail.display());
+            }
+            if self.mail.smtp.is_some() {
+                bail!("mail.sendmail and mail.smtp cannot be used both");
+            }
+        }
+        if let Some(smtp) = &self.mail.smtp {
+            validate_interface(&smtp.server).context("invalid mail.smtp.server")?;
+            if smtp.hello.is_empty() || smtp.hello.contains(char::is_whitespace) {
+                bail!("mail.smtp.hello '{}' is not a host name", smtp.hello);
+            }
+        }
+        if !self.origin.starts_with("https://") && !self.origin.starts_with("http://") {
+            bail!("origin '{}' is not an http(s) origin", self.origin);
+        }
+        for limit in [
+            self.rate_limits.page_views,
+            self.rate_limits.submissions,
+            self.rate_limits.comments,
+            self.rate_limits.votes,
+            self.rate_limits.logins,
+        ] {
+            if limit.burst == 0 || limit.period.is_zero() {
+                bail!("rate limit {} allows no requests at all", limit);
+            }
+        }
+        for domain in self
+            .spam
+            .blocked_domains
+            .iter()
+            .chain(&self.spam.allowed_domains)
+        {
+            if !sites::is_host_name(domain) {
+                bail!("spam domain '{}' is not a valid host name", domain);
+            }
+        }
+        Ok(())
+    }
+
+    /// The secret from which the CSRF tokens are derived,
+    /// generated if not configured.
+    pub fn csrf_secret(&self) -> Result<CsrfSecret> {
+        match &self.csrf_secret {
+            Some(secret) => CsrfSecret::from_hex(secret),
+            None => Ok(CsrfSecret::generate()),
+        }
+    }
+}
+
+/// Prints the configuration as TOML, with its secrets redacted.
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let config = Config {
+            csrf_secret: self.csrf_secret.as_ref().map(|_| REDACTED.to_owned()),
+            ..self.clone()
+        };
+        let s = toml::to_string(&config).map_err(|_| fmt::Error)?;
+        f.write_str(&s)
+    }
+}
+
+fn validate_interface(interface: &str) -> Result<()> {
+    match interface.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(()),
+        _ => bail!("'{}' is not of the form <host>:<port>", interface),
+    }
+}
+
+/// Merge the overriding value into the base value, table by table.
+fn merge(base: &mut Value, overriding: Value) {
+    match (base, overriding) {
+        (Value::Table(base), Value::Table(overriding)) => {
+            for (key, value) in overriding {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overriding) => *base = overriding,
+    }
+}
+
+/// Set the value of the (`__` separated) key to the raw value of an environment variable,
+/// interpreted according to the type of the setting in the schema.
+fn set_env_value(value: &mut Value, schema: &Value, key: &str, raw: &str) -> Result<()> {
+    let mut keys = key.split("__").peekable();
+    let mut table = value
+        .as_table_mut()
+        .ok_or_else(|| anyhow!("config is not a table"))?;
+    let mut schema = schema.as_table();
+    while let Some(key) = keys.next() {
+        let setting = schema.and_then(|schema| schema.get(key));
+        if keys.peek().is_none() {
+            let parsed =
+                env_value(setting, raw).with_context(|| format!("invalid value for '{}'", key))?;
+            table.insert(key.to_owned(), parsed);
+            break;
+        }
+        schema = setting.and_then(Value::as_table);
+        table = table
+            .entry(key.to_owned())
+            .or_insert_with(|| Value::Table(Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("'{}' is not a section", key))?;
+    }
+    Ok(())
+}
+
+/// The raw value of an environment variable as a value of the same type as the given setting.
+/// Strings are taken as is, quotes and `#` included, as are the items of a (comma separated) list.
+fn env_value(setting: Option<&Value>, raw: &str) -> Result<Value> {
+    Ok(match setting {
+        Some(Value::Integer(_)) => Value::Integer(raw.trim().parse()?),
+        Some(Value::Float(_)) => Value::Float(raw.trim().parse()?),
+        Some(Value::Boolean(_)) => Value::Boolean(raw.trim().parse()?),
+        Some(Value::Array(_)) => Value::Array(
+            raw.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| Value::String(item.to_owned()))
+                .collect(),
+        ),
+        Some(Value::Table(_)) => bail!("a section cannot be set as a whole"),
+        // settings unknown to the schema are rejected when deserializing the config
+        Some(Value::String(_)) | Some(Value::Datetime(_)) | None => Value::String(raw.to_owned()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::site::rate_limit::Limit;
+
+    #[test]
+    fn config_is_layered_and_validated() {
+        let file = std::env::temp_dir().join(format!("plabayo-news-{}.toml", std::process::id()));
+        std::fs::write(
+            &file,
+            "workers = 2\n[mail]\nfrom = \"news@example.org\"\n[rate_limits]\nvotes = \"5/h\"\n",
+        )
+        .unwrap();
+        let vars = [
+            ("PLABAYO_NEWS_INTERFACE", "0.0.0.0:8888"),
+            ("PLABAYO_NEWS_WORKERS", "4"),
+            ("PLABAYO_NEWS_SPAM__BLOCKED_DOMAINS", "spam.com, scam.org"),
+            (
+                "PLABAYO_NEWS_DATABASE__PATH",
+                "/var/lib/plabayo-news/db.json",
+            ),
+            ("PLABAYO_NEWS_MAIL__SMTP__SERVER", "mail.example.org:25"),
+            ("PLABAYO_NEWS_RANKING__FRESH_ACCOUNT_HOURS", "48"),
+            ("HOME", "/root"),
+        ]
+        .map(|(name, value)| (name.to_owned(), value.to_owned()));
+        let config = Config::load(Some(&file), vars).unwrap();
+        std::fs::remove_file(&file).unwrap();
+
+        assert_eq!(config.interface, "0.0.0.0:8888");
+        assert_eq!(config.workers, Some(4));
+        assert_eq!(config.mail.from, "news@example.org");
+        assert_eq!(config.origin, Config::default().origin);
+        assert_eq!(config.rate_limits.votes, Limit::per_hour(5));
+        assert_eq!(config.spam.blocked_domains, ["spam.com", "scam.org"]);
+        assert_eq!(
+            config.database.path.as_deref(),
+            Some(Path::new("/var/lib/plabayo-news/db.json"))
+        );
+        let smtp = config.mail.smtp.as_ref().unwrap();
+        assert_eq!(smtp.server, "mail.example.org:25");
+        assert_eq!(smtp.hello, SmtpConfig::default().hello);
+        assert_eq!(
+            config.ranking.ranking().fresh_account_age,
+            Duration::from_secs(48 * 60 * 60)
+        );
+        config.validate().unwrap();
+
+        let unknown = [("PLABAYO_NEWS_INTERFACES".to_owned(), "x".to_owned())];
+        assert!(Config::load(None, unknown).is_err());
+        let invalid = [("PLABAYO_NEWS_WORKERS".to_owned(), "four".to_owned())];
+        assert!(Config::load(None, invalid).is_err());
+        let invalid = Config {
+            interface: "localhost".to_owned(),
+            ..Config::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn env_strings_are_taken_as_is() {
+        let secret = format!("1e{}", "0".repeat(62));
+        let vars = [
+            (
+                "PLABAYO_NEWS_MAIL__FROM",
+                "\"Plabayo # News\" <news@example.org>",
+            ),
+            ("PLABAYO_NEWS_CSRF_SECRET", &secret),
+            ("PLABAYO_NEWS_CSP_REPORT_URI", "/csp-report#'x'"),
+        ]
+        .map(|(name, value)| (name.to_owned(), value.to_owned()));
+        let config = Config::load(None, vars).unwrap();
+
+        assert_eq!(config.mail.from, "\"Plabayo # News\" <news@example.org>");
+        assert_eq!(config.csrf_secret.as_deref(), Some(secret.as_str()));
+        assert_eq!(config.csp_report_uri.as_deref(), Some("/csp-report#'x'"));
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn spam_domains_are_host_names() {
+        let config = |domain: &str| Config {
+            spam: SpamConfig {
+                blocked_domains: vec![domain.to_owned()],
+                ..SpamConfig::default()
+            },
+            ..Config::default()
+        };
+        assert!(config("spam.com").validate().is_ok());
+        assert!(config("https://spam.com").validate().is_err());
+        assert!(config("spam com").validate().is_err());
+    }
+
+    #[test]
+    fn secrets_are_not_printed() {
+        let config = Config {
+            csrf_secret: Some("00".repeat(32)),
+            mail: MailConfig {
+                smtp: Some(SmtpConfig::default()),
+                ..MailConfig::default()
+            },
+            ..Config::default()
+        };
+        let printed = config.to_string();
+        assert!(printed.contains(REDACTED));
+        assert!(!printed.contains(&"00".repeat(32)));
+        let parsed: Config = toml::from_str(&Config::default().to_string()).unwrap();
+        assert_eq!(parsed.interface, Config::default().interface);
+    }
+}
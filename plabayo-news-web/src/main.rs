@@ -14,63 +14,327 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
 use actix_web::{middleware, web, App, HttpServer};
 use anyhow::{Context, Result};
 use structopt::StructOpt;
 
+use plabayo_news_data::Database;
+use plabayo_news_sendmail::{Sendmail, Transport};
+use plabayo_news_web::config::{self, Config, LogFormat};
+use plabayo_news_web::site::cache::{self, ResponseCache};
+use plabayo_news_web::site::digest::DigestMailer;
 use plabayo_news_web::site::middleware as pn_middleware;
+use plabayo_news_web::site::rate_limit::{self, Limit, MemoryStore, RateLimiter};
 use plabayo_news_web::site::state::AppState;
-use plabayo_news_web::site::{assets, pages};
+use plabayo_news_web::site::{assets, csp, feeds, pages};
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "plabayo-news-web")]
 struct Opt {
+    /// TOML file to read the configuration from, overridden by
+    /// `PLABAYO_NEWS_*` environment variables and the flags below
+    #[structopt(short, long, parse(from_os_str), env = config::ENV_CONFIG_FILE)]
+    config: Option<PathBuf>,
+
+    /// print the effective configuration (as TOML) and exit
+    #[structopt(long)]
+    print_config: bool,
+
     /// enable debugging features such as the logger
     #[structopt(short, long)]
     debug: bool,
 
-    /// interface to bind to
-    #[structopt(short, long, default_value = "127.0.0.1:8080")]
-    interface: String,
+    /// interface to bind to, `127.0.0.1:8080` by default
+    #[structopt(short, long)]
+    interface: Option<String>,
+
+    /// amount of worker threads, one per CPU core by default
+    #[structopt(long)]
+    workers: Option<usize>,
+
+    /// format of the logged lines, `text` (default) or `json`
+    #[structopt(long)]
+    log_format: Option<LogFormat>,
+
+    /// time (in minutes) within which authors can still edit and delete their own items,
+    /// `120` by default
+    #[structopt(long)]
+    edit_window: Option<u64>,
+
+    /// file the database is saved to (and opened from at startup),
+    /// the (example) data is lost on exit if not defined
+    #[structopt(long, parse(from_os_str))]
+    database: Option<PathBuf>,
+
+    /// interface to bind the JSON API to, `127.0.0.1:8081` by default
+    #[structopt(long)]
+    api_interface: Option<String>,
+
+    /// do not serve the JSON API
+    #[structopt(long)]
+    no_api: bool,
+
+    /// sendmail compatible program used to send the email digests,
+    /// no digests are sent if not defined
+    #[structopt(long, parse(from_os_str))]
+    sendmail: Option<PathBuf>,
+
+    /// SMTP relay (e.g. `localhost:25`) used to send the email digests
+    /// instead of a sendmail program, which is trusted to deliver them further
+    #[structopt(long)]
+    smtp: Option<String>,
+
+    /// sender of the emails sent, `Plabayo News <news@plabayo.tech>` by default
+    #[structopt(long)]
+    mail_from: Option<String>,
+
+    /// public origin of the site, used for its absolute links (e.g. within emails)
+    /// and as the only origin forms can be submitted from,
+    /// `https://news.plabayo.tech` by default (use e.g. `http://127.0.0.1:8080` to develop locally)
+    #[structopt(long)]
+    origin: Option<String>,
+
+    /// URI to which browsers report violations of the Content-Security-Policy,
+    /// e.g. `/csp-report` to log them, none are reported if not defined
+    #[structopt(long)]
+    csp_report_uri: Option<String>,
+
+    /// addresses of the reverse proxies trusted to forward the address of the client
+    /// (using `X-Forwarded-For`), e.g. `127.0.0.1`, none by default
+    #[structopt(long, use_delimiter = true)]
+    trusted_proxies: Option<Vec<IpAddr>>,
+
+    /// max page views per client, e.g. `300/m` (default),
+    /// replenished over the period (`s`, `m`, `h` or `d`)
+    #[structopt(long)]
+    rate_limit_page_views: Option<Limit>,
+
+    /// max form submissions per client, e.g. `30/m` (default)
+    #[structopt(long)]
+    rate_limit_submissions: Option<Limit>,
+
+    /// max comments per client, e.g. `10/m` (default)
+    #[structopt(long)]
+    rate_limit_comments: Option<Limit>,
+
+    /// max votes per client, e.g. `60/m` (default)
+    #[structopt(long)]
+    rate_limit_votes: Option<Limit>,
+
+    /// max login attempts per client, e.g. `10/h` (default)
+    #[structopt(long)]
+    rate_limit_logins: Option<Limit>,
+
+    /// sites no one can link to, e.g. `spam.com,scam.org`
+    #[structopt(long, use_delimiter = true)]
+    blocked_domains: Option<Vec<String>>,
+
+    /// sites new accounts can link to while on probation, none by default
+    #[structopt(long, use_delimiter = true)]
+    allowed_domains: Option<Vec<String>>,
+}
+
+impl Opt {
+    /// Override the configuration with the flags given.
+    fn apply(self, config: &mut Config) {
+        config.debug |= self.debug;
+        set(&mut config.interface, self.interface);
+        config.workers = self.workers.or(config.workers);
+        set(&mut config.log_format, self.log_format);
+        set(&mut config.edit_window, self.edit_window);
+        config.database.path = self.database.or_else(|| config.database.path.take());
+        set(&mut config.api.interface, self.api_interface);
+        config.api.enabled &= !self.no_api;
+        config.mail.sendmail = self.sendmail.or_else(|| config.mail.sendmail.take());
+        if let Some(server) = self.smtp {
+            config.mail.smtp.get_or_insert_with(Default::default).server = server;
+        }
+        set(&mut config.mail.from, self.mail_from);
+        set(&mut config.origin, self.origin);
+        config.csp_report_uri = self.csp_report_uri.or_else(|| config.csp_report_uri.take());
+        set(&mut config.trusted_proxies, self.trusted_proxies);
+        let limits = &mut config.rate_limits;
+        set(&mut limits.page_views, self.rate_limit_page_views);
+        set(&mut limits.submissions, self.rate_limit_submissions);
+        set(&mut limits.comments, self.rate_limit_comments);
+        set(&mut limits.votes, self.rate_limit_votes);
+        set(&mut limits.logins, self.rate_limit_logins);
+        set(&mut config.spam.blocked_domains, self.blocked_domains);
+        set(&mut config.spam.allowed_domains, self.allowed_domains);
+    }
+}
+
+/// Replace the setting with the given value, if any.
+fn set<T>(setting: &mut T, value: Option<T>) {
+    if let Some(value) = value {
+        *setting = value;
+    }
 }
 
 #[actix_web::main]
 async fn main() -> Result<()> {
     let opt = Opt::from_args();
 
-    if opt.debug {
-        std::env::set_var("RUST_LOG", "actix_web=info");
+    let mut config = Config::load(opt.config.as_deref(), std::env::vars())?;
+    let print_config = opt.print_config;
+    opt.apply(&mut config);
+    config.validate().context("validate config")?;
+    if print_config {
+        print!("{}", config);
+        return Ok(());
+    }
+
+    if config.debug {
+        std::env::set_var("RUST_LOG", "actix_web=info,plabayo_news_web=info");
     } else {
-        std::env::set_var("RUST_LOG", "actix_web=error");
+        std::env::set_var("RUST_LOG", "actix_web=error,plabayo_news_web=error");
+    }
+    init_logger(config.log_format);
+
+    let db = match &config.database.path {
+        Some(path) => {
+            Database::open(path).with_context(|| format!("open database {}", path.display()))?
+        }
+        None => Database::new(),
+    };
+    db.set_ranking(config.ranking.ranking());
+
+    // save the database periodically, as well as on exit (see below)
+    if let Some(path) = config.database.path.clone() {
+        let db = db.clone();
+        let interval = Duration::from_secs(config.database.save_interval);
+        actix_web::rt::spawn(async move {
+            loop {
+                actix_web::rt::time::delay_for(interval).await;
+                let (db, path) = (db.clone(), path.clone());
+                if let Err(err) = web::block(move || db.save(&path)).await {
+                    log::error!("save database: {}", err);
+                }
+            }
+        });
+    }
+
+    // send the emails, of which the digests in the background
+    let transport: Option<Arc<dyn Transport + Send + Sync>> =
+        match (&config.mail.smtp, &config.mail.sendmail) {
+            (Some(smtp), _) => Some(Arc::new(smtp.transport())),
+            (None, Some(sendmail)) => Some(Arc::new(Sendmail::new(sendmail))),
+            (None, None) => None,
+        };
+    let mailer = transport.map(|transport| {
+        Arc::new(DigestMailer::new(
+            db.clone(),
+            transport,
+            config.mail.from.clone(),
+            config.origin.clone(),
+        ))
+    });
+    if let Some(mailer) = &mailer {
+        actix_web::rt::spawn(mailer.clone().run());
     }
-    env_logger::init();
 
     // create app state used by all routes
-    let state = web::Data::new(AppState::new());
+    let state = web::Data::new(AppState {
+        db,
+        edit_window: Duration::from_secs(config.edit_window * 60),
+        csrf_secret: config.csrf_secret()?,
+        spam_policy: config.spam.policy(),
+        origin: config.origin.trim_end_matches('/').to_owned(),
+        mailer,
+    });
+
+    // cache the pages served to anonymous visitors,
+    // for as long as no items are written
+    let response_cache = Arc::new(ResponseCache::new(cache::DEFAULT_CAPACITY));
+    {
+        let response_cache = response_cache.clone();
+        let db = state.db.clone();
+        actix_web::rt::spawn(async move { response_cache.invalidate_on_item_writes(db).await });
+    }
+
+    // limit the requests of each client, keeping the budgets in memory
+    let rate_limiter = Arc::new(RateLimiter::new(
+        config.rate_limits,
+        Box::new(MemoryStore::new(rate_limit::DEFAULT_CAPACITY)),
+    ));
 
     // start http server
-    HttpServer::new(move || {
+    let db = state.db.clone();
+    let csp_report_uri = config.csp_report_uri.clone();
+    let trusted_proxies = config.trusted_proxies.clone();
+    let mut server = HttpServer::new(move || {
         App::new()
             .app_data(state.clone())
-            .wrap(middleware::Logger::default())
+            .wrap(pn_middleware::Cache::new(response_cache.clone()))
+            // wraps the cache, such that cached pages are served
+            // with the nonce generated for the request as well
+            .wrap(pn_middleware::SecurityHeaders::new(
+                csp_report_uri.as_deref(),
+            ))
+            .wrap(pn_middleware::RateLimit::new(
+                rate_limiter.clone(),
+                &trusted_proxies,
+            ))
             .wrap(middleware::Compress::default())
-            .wrap(pn_middleware::Cache::default())
-            .wrap(pn_middleware::SiteInfo::default())
+            .wrap(middleware::Logger::default())
+            .wrap(pn_middleware::SiteInfo)
+            .wrap(pn_middleware::LocalePath)
             .wrap(middleware::NormalizePath::new(
                 middleware::normalize::TrailingSlash::Trim,
             ))
             .service(assets::factory())
+            .service(feeds::factory())
+            .service(csp::factory())
             .service(pages::factory())
-    })
-    .bind(&opt.interface)
-    .with_context(|| {
-        format!(
-            "bind Plabayo News HTTPServer to interface: {}",
-            opt.interface
-        )
-    })?
-    .run()
-    .await?;
+    });
+    if let Some(workers) = config.workers {
+        server = server.workers(workers);
+    }
+    let site = server
+        .bind(&config.interface)
+        .with_context(|| {
+            format!(
+                "bind Plabayo News HTTPServer to interface: {}",
+                config.interface
+            )
+        })?
+        .run();
+
+    // serve the API from the same process, as it has to share the (in-memory) database
+    if config.api.enabled {
+        let api = plabayo_news_api::server(db.clone(), &config.api.interface)?;
+        futures::future::try_join(site, api).await?;
+    } else {
+        site.await?;
+    }
+
+    if let Some(path) = &config.database.path {
+        db.save(path)
+            .with_context(|| format!("save database {}", path.display()))?;
+    }
 
     Ok(())
 }
+
+/// Initialize the logger, filtered using the `RUST_LOG` environment variable.
+fn init_logger(format: LogFormat) {
+    let mut builder = env_logger::Builder::from_default_env();
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let line = serde_json::json!({
+                "time": chrono::Utc::now().to_rfc3339(),
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{}", line)
+        });
+    }
+    builder.init();
+}
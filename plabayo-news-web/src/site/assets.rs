@@ -14,16 +14,84 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use actix_web_static_files;
+use std::collections::HashMap;
+
+use actix_web::dev::HttpServiceFactory;
+use actix_web::http::header::{self, EntityTag, IntoHeaderValue};
+use actix_web::{web, HttpResponse};
+use lazy_static::lazy_static;
+use actix_web_static_files::Resource;
 
 // include generated (assets) resource files
 include!(concat!(env!("OUT_DIR"), "/generated.rs"));
 
-use actix_web::dev::HttpServiceFactory;
+// include the generated content hashes of these resource files,
+// used as their (strong) ETag and to fingerprint their names
+include!(concat!(env!("OUT_DIR"), "/asset_hashes.rs"));
 
 pub const ROOT: &str = "assets";
 
+lazy_static! {
+    static ref RESOURCES: HashMap<&'static str, Resource> = generate();
+    static ref HASHES: HashMap<&'static str, &'static str> = hashes();
+}
+
+/// Max age of assets served at their fingerprinted path,
+/// which can be cached forever as their content never changes.
+pub const FINGERPRINTED_MAX_AGE_SEC: u32 = 365 * 24 * 60 * 60;
+
+/// The URL of the asset with the given name, fingerprinted with its content hash,
+/// such that it only needs to be fetched again when it actually changed.
+pub fn url(name: &str) -> String {
+    format!("/{}/{}", ROOT, fingerprinted_name(name).unwrap_or(name))
+}
+
+/// Whether or not the path is the fingerprinted path of an asset.
+pub fn is_fingerprinted_path(path: &str) -> bool {
+    path.strip_prefix(&format!("/{}/", ROOT))
+        .and_then(original_name)
+        .is_some()
+}
+
 pub fn factory() -> impl HttpServiceFactory + 'static {
-    let generated = generate();
-    actix_web_static_files::ResourceFiles::new(format!("/{}", ROOT).as_str(), generated)
+    web::resource(format!("/{}/{{path:.*}}", ROOT).as_str())
+        .route(web::get().to(serve_asset))
+        .route(web::head().to(serve_asset))
+}
+
+/// Serve an asset from memory, by its (fingerprinted) name, along with its ETag,
+/// such that clients can revalidate it using a conditional request.
+async fn serve_asset(path: web::Path<(String,)>) -> HttpResponse {
+    let path = path.into_inner().0;
+    let name = original_name(&path).unwrap_or(&path);
+    let resource = match RESOURCES.get(name) {
+        Some(resource) => resource,
+        None => return HttpResponse::NotFound().finish(),
+    };
+
+    let mut builder = HttpResponse::Ok();
+    builder.content_type(resource.mime_type);
+    if let Some(value) = HASHES
+        .get(name)
+        .and_then(|hash| IntoHeaderValue::try_into(EntityTag::strong(hash.to_string())).ok())
+    {
+        builder.header(header::ETAG, value);
+    }
+    builder.body(resource.data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn asset_urls_are_fingerprinted() {
+        let style = url("style.css");
+        assert!(style.starts_with("/assets/style."));
+        assert!(style.ends_with(".css"));
+        assert_ne!(style, "/assets/style.css");
+        assert!(is_fingerprinted_path(&style));
+        assert!(!is_fingerprinted_path("/assets/style.css"));
+        assert_eq!(url("unknown.js"), "/assets/unknown.js");
+    }
 }
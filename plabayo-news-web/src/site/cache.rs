@@ -0,0 +1,271 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! In-process cache of the responses served to anonymous visitors,
+//! invalidated as soon as any item is written.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::dev::{Body, MessageBody, ResponseBody, ServiceResponse};
+use actix_web::http::{HeaderName, HeaderValue, StatusCode};
+use actix_web::web::{Bytes, BytesMut, Query};
+use actix_web::{Error, HttpResponse};
+use cached::{Cached, SizedCache};
+use futures::future::poll_fn;
+use futures::StreamExt;
+
+use plabayo_news_data::models::{ActionID, ActionKind, SpamDecision, SpamVerdict};
+use plabayo_news_data::Database;
+
+use crate::site::extractors::Session;
+use crate::site::feeds;
+use crate::site::l18n::locales::Locale;
+
+/// Default amount of responses kept in the cache,
+/// the least recently used ones are evicted first.
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// Time (in seconds) feeds are kept in the cache.
+const FEED_TTL_SEC: u64 = 60;
+
+/// Time (in seconds) responses are kept in the cache per route,
+/// responses of routes not listed here are never cached.
+const ROUTE_TTL_SEC: &[(&str, u64)] = &[
+    ("/", 30),
+    ("/news", 30),
+    ("/newest", 10),
+    ("/ask", 30),
+    ("/from", 30),
+    ("/past", 300),
+    ("/comments", 10),
+    ("/item", 10),
+    ("/faq", 3600),
+    ("/guidelines", 3600),
+    ("/security", 3600),
+    ("/contribute", 3600),
+];
+
+pub struct ResponseCache {
+    entries: Mutex<SizedCache<CacheKey, CachedResponse>>,
+}
+
+/// Identifies a cached response, as responses of the same route
+/// differ per query, negotiated locale and locale path prefix (used for all links).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    path: String,
+    query: String,
+    locale: Locale,
+    path_locale: Option<Locale>,
+}
+
+impl CacheKey {
+    pub fn new(path: &str, query: &str, session: &Session) -> CacheKey {
+        CacheKey {
+            path: path.to_owned(),
+            query: normalize_query(query),
+            locale: session.locale(),
+            path_locale: session.path_locale(),
+        }
+    }
+
+    pub fn locale(&self) -> Locale {
+        self.locale
+    }
+}
+
+#[derive(Clone)]
+pub struct CachedResponse {
+    expires: Instant,
+    status: StatusCode,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    body: Bytes,
+    /// The Content-Security-Policy nonce the body was rendered with, if any.
+    nonce: Option<String>,
+}
+
+impl CachedResponse {
+    /// Read the complete body of the response, such that it can be cached,
+    /// returning the response with the read body in place.
+    pub async fn collect<B: MessageBody + 'static>(
+        mut res: ServiceResponse<B>,
+        ttl: Duration,
+        nonce: Option<String>,
+    ) -> Result<(ServiceResponse<Body>, CachedResponse), Error> {
+        let mut body = Box::pin(res.take_body());
+        let mut bytes = BytesMut::new();
+        while let Some(chunk) = poll_fn(|cx| body.as_mut().poll_next(cx)).await {
+            bytes.extend_from_slice(&chunk?);
+        }
+        let cached = CachedResponse {
+            expires: Instant::now() + ttl,
+            status: res.status(),
+            headers: res
+                .headers()
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+            body: bytes.freeze(),
+            nonce,
+        };
+        let body = cached.body.clone();
+        Ok((
+            res.map_body(|_, _| ResponseBody::Other(Body::Bytes(body))),
+            cached,
+        ))
+    }
+
+    /// The cached response, with the nonce it was rendered with replaced by the given one,
+    /// such that its inline scripts and styles are allowed by the policy of the current request.
+    pub fn to_response(&self, nonce: Option<&str>) -> HttpResponse {
+        let mut builder = HttpResponse::build(self.status);
+        for (name, value) in &self.headers {
+            builder.header(name.clone(), value.clone());
+        }
+        let body = match (self.nonce.as_deref(), nonce) {
+            (Some(from), Some(to)) => replace_nonce(&self.body, from, to),
+            _ => self.body.clone(),
+        };
+        builder.body(body)
+    }
+}
+
+impl ResponseCache {
+    pub fn new(capacity: usize) -> ResponseCache {
+        ResponseCache {
+            entries: Mutex::new(SizedCache::with_size(capacity)),
+        }
+    }
+
+    /// The time responses of the given path can be cached, if at all.
+    pub fn ttl(path: &str) -> Option<Duration> {
+        if feeds::is_feed_path(path) {
+            return Some(Duration::from_secs(FEED_TTL_SEC));
+        }
+        ROUTE_TTL_SEC
+            .iter()
+            .find(|(route, _)| route.eq_ignore_ascii_case(path))
+            .map(|(_, ttl)| Duration::from_secs(*ttl))
+    }
+
+    /// The cached response for the given key, unless it expired.
+    pub fn get(&self, key: &CacheKey) -> Option<CachedResponse> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.cache_get(key) {
+            Some(response) if response.expires > Instant::now() => Some(response.clone()),
+            Some(_) => {
+                entries.cache_remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, key: CacheKey, response: CachedResponse) {
+        self.entries.lock().unwrap().cache_set(key, response);
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().cache_clear();
+    }
+
+    /// Clear the cache each time an item is created or modified,
+    /// for as long as the database exists.
+    pub async fn invalidate_on_item_writes(&self, db: Database) {
+        let (_, mut actions) = db.subscribe_all_actions(ActionID::MAX).await;
+        while let Some(action) = actions.next().await {
+            // shadow banning a user hides all of their items from anonymous visitors
+            let shadow_ban = matches!(
+                action.kind,
+                ActionKind::SpamDecision(SpamDecision {
+                    verdict: SpamVerdict::ShadowBanned,
+                    ..
+                })
+            );
+            if action.item().is_some() || shadow_ban {
+                self.clear();
+            }
+        }
+    }
+}
+
+/// Replace all occurrences of the nonce within the body.
+fn replace_nonce(body: &Bytes, from: &str, to: &str) -> Bytes {
+    if from.is_empty() || from == to {
+        return body.clone();
+    }
+    let mut replaced = BytesMut::with_capacity(body.len());
+    let mut rest = &body[..];
+    while let Some(pos) = rest
+        .windows(from.len())
+        .position(|window| window == from.as_bytes())
+    {
+        replaced.extend_from_slice(&rest[..pos]);
+        replaced.extend_from_slice(to.as_bytes());
+        rest = &rest[pos + from.len()..];
+    }
+    replaced.extend_from_slice(rest);
+    replaced.freeze()
+}
+
+/// The query sorted by its keys, such that the order
+/// in which parameters are given does not matter.
+fn normalize_query(query: &str) -> String {
+    match Query::<BTreeMap<String, String>>::from_query(query) {
+        Ok(params) => params
+            .iter()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join("&"),
+        Err(_) => query.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_is_normalized() {
+        assert_eq!(normalize_query("site=a.com&p=2"), "p=2&site=a.com");
+        assert_eq!(normalize_query("p=2&site=a.com&q="), "p=2&site=a.com");
+        assert_eq!(normalize_query(""), "");
+    }
+
+    #[test]
+    fn only_listed_routes_are_cached() {
+        assert_eq!(ResponseCache::ttl("/"), Some(Duration::from_secs(30)));
+        assert_eq!(
+            ResponseCache::ttl("/newest/rss"),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(ResponseCache::ttl("/threads"), None);
+        assert_eq!(ResponseCache::ttl("/edit"), None);
+    }
+
+    #[test]
+    fn cached_pages_get_the_nonce_of_the_request() {
+        let body = Bytes::from_static(b"<script nonce=\"aaaa\"></script><style nonce=\"aaaa\">");
+        assert_eq!(
+            replace_nonce(&body, "aaaa", "bbbb"),
+            Bytes::from_static(b"<script nonce=\"bbbb\"></script><style nonce=\"bbbb\">")
+        );
+        assert_eq!(replace_nonce(&body, "", "bbbb"), body);
+    }
+}
@@ -0,0 +1,164 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Conditional requests, answering requests for representations
+//! the client already has with a bodiless `304 Not Modified` response.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::http::header::{
+    self, EntityTag, Header, HttpDate, IfModifiedSince, IfNoneMatch, IntoHeaderValue,
+};
+use actix_web::http::{HeaderMap, Method};
+use actix_web::{HttpMessage, HttpResponse};
+
+use plabayo_news_data::models::ActionID;
+
+use crate::site::l18n::locales::Locale;
+use crate::site::SITE_INFO;
+
+/// The validators a client sent along with its request.
+#[derive(Debug, Default)]
+pub struct Preconditions {
+    if_none_match: Option<IfNoneMatch>,
+    if_modified_since: Option<HttpDate>,
+}
+
+impl Preconditions {
+    /// The preconditions of the request, which only apply to GET and HEAD requests.
+    pub fn of<T: HttpMessage>(method: &Method, req: &T) -> Preconditions {
+        if method != Method::GET && method != Method::HEAD {
+            return Preconditions::default();
+        }
+        Preconditions {
+            // a missing header parses as an empty list of tags
+            if_none_match: IfNoneMatch::parse(req)
+                .ok()
+                .filter(|inm| !matches!(inm, IfNoneMatch::Items(tags) if tags.is_empty())),
+            if_modified_since: IfModifiedSince::parse(req).ok().map(|ims| ims.0),
+        }
+    }
+
+    /// Whether or not the client already has the representation
+    /// with the given validators, in which case it can be answered with a 304.
+    ///
+    /// As `If-None-Match` is more accurate than `If-Modified-Since`,
+    /// the latter is ignored when both are given.
+    pub fn is_not_modified(
+        &self,
+        etag: Option<&EntityTag>,
+        last_modified: Option<SystemTime>,
+    ) -> bool {
+        match (&self.if_none_match, &self.if_modified_since) {
+            (Some(IfNoneMatch::Any), _) => etag.is_some(),
+            (Some(IfNoneMatch::Items(tags)), _) => match etag {
+                Some(etag) => tags.iter().any(|tag| tag.weak_eq(etag)),
+                None => false,
+            },
+            (None, Some(since)) => match last_modified {
+                // HTTP dates have a precision of seconds
+                Some(last_modified) => unix_sec(last_modified) <= unix_sec((*since).into()),
+                None => false,
+            },
+            (None, None) => false,
+        }
+    }
+}
+
+/// The validators of a response, found in its `ETag` and `Last-Modified` headers.
+pub fn validators(headers: &HeaderMap) -> (Option<EntityTag>, Option<SystemTime>) {
+    let etag = headers
+        .get(header::ETAG)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<EntityTag>().ok());
+    let last_modified = headers
+        .get(header::LAST_MODIFIED)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<HttpDate>().ok())
+        .map(SystemTime::from);
+    (etag, last_modified)
+}
+
+/// Weak validator of a rendered page, which stays the same for as long
+/// as the site is not redeployed and no items or users are written.
+pub fn page_etag(data_version: ActionID, locale: Locale) -> EntityTag {
+    EntityTag::weak(format!(
+        "{:x}-{}-{}",
+        SITE_INFO.version, data_version, locale
+    ))
+}
+
+/// A bodiless response, letting the client know its representation
+/// (identified by the given validators) is still fresh.
+pub fn not_modified(etag: Option<&EntityTag>, last_modified: Option<SystemTime>) -> HttpResponse {
+    let mut builder = HttpResponse::NotModified();
+    if let Some(value) = etag.and_then(|etag| IntoHeaderValue::try_into(etag.clone()).ok()) {
+        builder.header(header::ETAG, value);
+    }
+    if let Some(value) =
+        last_modified.and_then(|time| IntoHeaderValue::try_into(HttpDate::from(time)).ok())
+    {
+        builder.header(header::LAST_MODIFIED, value);
+    }
+    builder.finish()
+}
+
+fn unix_sec(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    #[test]
+    fn if_none_match_uses_weak_comparison() {
+        let req = TestRequest::default()
+            .header("if-none-match", r#"W/"1-en", "2-en""#)
+            .to_http_request();
+        let preconditions = Preconditions::of(&Method::GET, &req);
+        assert!(preconditions.is_not_modified(Some(&EntityTag::weak("2-en".to_owned())), None));
+        assert!(preconditions.is_not_modified(Some(&EntityTag::strong("1-en".to_owned())), None));
+        assert!(!preconditions.is_not_modified(Some(&EntityTag::weak("3-en".to_owned())), None));
+        assert!(!preconditions.is_not_modified(None, Some(UNIX_EPOCH)));
+    }
+
+    #[test]
+    fn if_modified_since_is_ignored_with_if_none_match() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let since = HttpDate::from(time).to_string();
+        let req = TestRequest::default()
+            .header("if-modified-since", since.clone())
+            .to_http_request();
+        let preconditions = Preconditions::of(&Method::GET, &req);
+        assert!(preconditions.is_not_modified(None, Some(time + Duration::from_millis(500))));
+        assert!(!preconditions.is_not_modified(None, Some(time + Duration::from_secs(1))));
+        assert!(!Preconditions::of(&Method::POST, &req).is_not_modified(None, Some(time)));
+
+        let req = TestRequest::default()
+            .header("if-modified-since", since)
+            .header("if-none-match", r#"W/"1-en""#)
+            .to_http_request();
+        let preconditions = Preconditions::of(&Method::GET, &req);
+        assert!(!preconditions.is_not_modified(None, Some(time)));
+    }
+}
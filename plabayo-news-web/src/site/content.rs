@@ -0,0 +1,308 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Rendering of user-supplied markdown content (e.g. comments and profiles)
+//! as HTML which is safe to be included as-is in the pages of the website.
+//! Any other user content is plain text, which is escaped by the page templates.
+//!
+//! Markdown is rendered by walking the events of the markdown parser,
+//! only ever producing the elements of a strict allowlist:
+//! `p`, `br`, `hr`, `em`, `strong`, `del`, `code`, `pre`, `blockquote`, `ul`, `ol`, `li` and `a`.
+//! Raw HTML is escaped and shown as text, headings are rendered as strong paragraphs,
+//! images as links to the image and all links use `rel="nofollow ugc"`.
+
+use pulldown_cmark::{Event, LinkType, Options, Parser, Tag};
+
+use crate::site::l18n::locales::Safe;
+
+/// The `rel` attribute value used for all links found in user content.
+pub const LINK_REL: &str = "nofollow ugc";
+
+/// Render the user-supplied markdown as sanitized HTML,
+/// turning bare URLs into links as well.
+pub fn markdown_to_html(input: &str) -> Safe<String> {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+
+    let mut output = String::with_capacity(input.len() * 3 / 2);
+    let mut text = String::new();
+    // for each open link or image, whether it is rendered as a link,
+    // which is not the case for links with a destination that is not allowed
+    let mut links: Vec<bool> = Vec::new();
+    let mut in_code_block = false;
+
+    for event in Parser::new_ext(input, options) {
+        // consecutive text events are merged,
+        // as the parser can split a single URL over multiple events
+        if let Event::Text(t) = &event {
+            text.push_str(t);
+            continue;
+        }
+        if !text.is_empty() {
+            if links.is_empty() && !in_code_block {
+                push_linkified(&mut output, &text);
+            } else {
+                output.push_str(&escape_html(&text));
+            }
+            text.clear();
+        }
+
+        match event {
+            Event::Start(tag) => match tag {
+                Tag::Paragraph => output.push_str("<p>"),
+                Tag::Heading(..) => output.push_str("<p><strong>"),
+                Tag::BlockQuote => output.push_str("<blockquote>"),
+                Tag::CodeBlock(_) => {
+                    in_code_block = true;
+                    output.push_str("<pre><code>");
+                }
+                Tag::List(Some(start)) if start != 1 => {
+                    output.push_str(&format!("<ol start=\"{}\">", start))
+                }
+                Tag::List(Some(_)) => output.push_str("<ol>"),
+                Tag::List(None) => output.push_str("<ul>"),
+                Tag::Item => output.push_str("<li>"),
+                Tag::Emphasis => output.push_str("<em>"),
+                Tag::Strong => output.push_str("<strong>"),
+                Tag::Strikethrough => output.push_str("<del>"),
+                Tag::Link(link_type, dest, _) | Tag::Image(link_type, dest, _) => {
+                    let url = if link_type == LinkType::Email {
+                        sanitize_url(&format!("mailto:{}", dest))
+                    } else {
+                        sanitize_url(&dest)
+                    };
+                    match url {
+                        Some(url) => {
+                            push_link_start(&mut output, &url);
+                            links.push(true);
+                        }
+                        None => links.push(false),
+                    }
+                }
+                // not enabled by the parser options, and thus not expected
+                Tag::Table(_)
+                | Tag::TableHead
+                | Tag::TableRow
+                | Tag::TableCell
+                | Tag::FootnoteDefinition(_) => (),
+            },
+            Event::End(tag) => match tag {
+                Tag::Paragraph => output.push_str("</p>"),
+                Tag::Heading(..) => output.push_str("</strong></p>"),
+                Tag::BlockQuote => output.push_str("</blockquote>"),
+                Tag::CodeBlock(_) => {
+                    in_code_block = false;
+                    output.push_str("</code></pre>");
+                }
+                Tag::List(Some(_)) => output.push_str("</ol>"),
+                Tag::List(None) => output.push_str("</ul>"),
+                Tag::Item => output.push_str("</li>"),
+                Tag::Emphasis => output.push_str("</em>"),
+                Tag::Strong => output.push_str("</strong>"),
+                Tag::Strikethrough => output.push_str("</del>"),
+                Tag::Link(..) | Tag::Image(..) => {
+                    if links.pop() == Some(true) {
+                        output.push_str("</a>");
+                    }
+                }
+                Tag::Table(_)
+                | Tag::TableHead
+                | Tag::TableRow
+                | Tag::TableCell
+                | Tag::FootnoteDefinition(_) => (),
+            },
+            Event::Code(code) => {
+                output.push_str("<code>");
+                output.push_str(&escape_html(&code));
+                output.push_str("</code>");
+            }
+            Event::Html(html) => output.push_str(&escape_html(&html)),
+            Event::SoftBreak => output.push('\n'),
+            Event::HardBreak => output.push_str("<br>"),
+            Event::Rule => output.push_str("<hr>"),
+            Event::Text(_) | Event::FootnoteReference(_) | Event::TaskListMarker(_) => (),
+        }
+    }
+    if !text.is_empty() {
+        push_linkified(&mut output, &text);
+    }
+
+    Safe::new(output)
+}
+
+/// Escape plain text, such that it can be used as HTML content or attribute value.
+pub fn escape_html(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&#x27;"),
+            c => output.push(c),
+        }
+    }
+    output
+}
+
+/// Returns the given URL if it is safe to link to,
+/// which are absolute http(s) and mailto URLs, as well as paths of this website.
+pub fn sanitize_url(url: &str) -> Option<String> {
+    let url = url.trim();
+    let lower = url.to_ascii_lowercase();
+    let allowed = ["http://", "https://", "mailto:"]
+        .iter()
+        .any(|scheme| lower.starts_with(scheme) && lower.len() > scheme.len())
+        || (url.starts_with('/') && !url.starts_with("//"));
+    if allowed && !url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        Some(url.to_owned())
+    } else {
+        None
+    }
+}
+
+fn push_link_start(output: &mut String, url: &str) {
+    output.push_str("<a href=\"");
+    output.push_str(&escape_html(url));
+    output.push_str("\" rel=\"");
+    output.push_str(LINK_REL);
+    output.push_str("\">");
+}
+
+/// Push the escaped text, turning all bare http(s) URLs found in it into links.
+fn push_linkified(output: &mut String, text: &str) {
+    let mut rest = text;
+    while let Some((start, url)) = next_url(rest) {
+        let (before, candidate) = rest.split_at(start);
+        output.push_str(&escape_html(before));
+        match sanitize_url(url) {
+            Some(href) => {
+                push_link_start(output, &href);
+                output.push_str(&escape_html(url));
+                output.push_str("</a>");
+            }
+            None => output.push_str(&escape_html(url)),
+        }
+        rest = &candidate[url.len()..];
+    }
+    output.push_str(&escape_html(rest));
+}
+
+/// All bare http(s) URLs found in the text, as they would be linkified.
+pub fn bare_urls(text: &str) -> Vec<&str> {
+    let mut urls = Vec::new();
+    let mut rest = text;
+    while let Some((start, url)) = next_url(rest) {
+        urls.push(url);
+        rest = &rest[start + url.len()..];
+    }
+    urls
+}
+
+/// The start and the URL itself of the first bare http(s) URL in the text.
+fn next_url(text: &str) -> Option<(usize, &str)> {
+    let start = find_url_start(text)?;
+    let candidate = &text[start..];
+    let end = candidate
+        .find(|c: char| c.is_whitespace() || c == '<' || c == '>' || c == '"')
+        .unwrap_or(candidate.len());
+    // trailing punctuation is most likely part of the sentence rather than the URL
+    let url = candidate[..end].trim_end_matches(|c: char| ".,:;!?'\")".contains(c));
+    Some((start, url))
+}
+
+fn find_url_start(text: &str) -> Option<usize> {
+    let lower = text.to_ascii_lowercase();
+    let mut offset = 0;
+    while let Some(index) = lower[offset..].find("http") {
+        let start = offset + index;
+        let candidate = &lower[start..];
+        let at_word_start =
+            !matches!(lower[..start].chars().last(), Some(c) if c.is_alphanumeric());
+        if at_word_start && (candidate.starts_with("http://") || candidate.starts_with("https://"))
+        {
+            return Some(start);
+        }
+        offset = start + 4;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(input: &str) -> String {
+        markdown_to_html(input).to_string()
+    }
+
+    #[test]
+    fn unsafe_links_are_dropped() {
+        assert_eq!(render("[click](javascript:alert(1))"), "<p>click</p>");
+        assert_eq!(
+            render("[click](data:text/html;base64,PHNjcmlwdD4=)"),
+            "<p>click</p>"
+        );
+        assert_eq!(render("![img](JavaScript:alert(1))"), "<p>img</p>");
+        assert_eq!(sanitize_url(" javascript:alert(1)"), None);
+        assert_eq!(sanitize_url("//evil.com"), None);
+        assert_eq!(sanitize_url("/item?id=1").as_deref(), Some("/item?id=1"));
+    }
+
+    #[test]
+    fn raw_html_is_escaped() {
+        assert_eq!(
+            render("<script>alert(1)</script>"),
+            "&lt;script&gt;alert(1)&lt;/script&gt;"
+        );
+        assert_eq!(
+            render("a <b onclick=\"x\">b</b>"),
+            "<p>a &lt;b onclick=&quot;x&quot;&gt;b&lt;/b&gt;</p>"
+        );
+        assert_eq!(render("`<script>`"), "<p><code>&lt;script&gt;</code></p>");
+    }
+
+    #[test]
+    fn links_are_nofollow_ugc() {
+        assert_eq!(
+            render("[site](https://plabayo.tech)"),
+            "<p><a href=\"https://plabayo.tech\" rel=\"nofollow ugc\">site</a></p>"
+        );
+        assert_eq!(
+            render("see https://plabayo.tech/news?p=2&q=1 now"),
+            "<p>see <a href=\"https://plabayo.tech/news?p=2&amp;q=1\" rel=\"nofollow ugc\">\
+             https://plabayo.tech/news?p=2&amp;q=1</a> now</p>"
+        );
+    }
+
+    #[test]
+    fn trailing_punctuation_stays_outside_autolinks() {
+        assert_eq!(
+            render("Read https://plabayo.tech."),
+            "<p>Read <a href=\"https://plabayo.tech\" rel=\"nofollow ugc\">https://plabayo.tech</a>.</p>"
+        );
+        assert_eq!(
+            render("(see https://plabayo.tech/a), or not!"),
+            "<p>(see <a href=\"https://plabayo.tech/a\" rel=\"nofollow ugc\">https://plabayo.tech/a</a>), or not!</p>"
+        );
+        // URLs within words are not linked
+        assert_eq!(
+            render("xhttps://plabayo.tech"),
+            "<p>xhttps://plabayo.tech</p>"
+        );
+    }
+}
@@ -0,0 +1,38 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Endpoint receiving the Content-Security-Policy violations reported by browsers,
+//! which are logged such that a too strict (or attacked) policy gets noticed.
+
+use actix_web::dev::HttpServiceFactory;
+use actix_web::web::{self, Bytes};
+use actix_web::HttpResponse;
+
+/// Path of the endpoint, to be used as the `report-uri` of the policy.
+pub const REPORT_PATH: &str = "/csp-report";
+
+/// Max amount of bytes of a report that is logged.
+const MAX_LOGGED_REPORT_LEN: usize = 4096;
+
+pub fn factory() -> impl HttpServiceFactory + 'static {
+    web::resource(REPORT_PATH).route(web::post().to(serve_report))
+}
+
+async fn serve_report(body: Bytes) -> HttpResponse {
+    let report = String::from_utf8_lossy(&body[..body.len().min(MAX_LOGGED_REPORT_LEN)]);
+    log::warn!("content security policy violation: {}", report);
+    HttpResponse::NoContent().finish()
+}
@@ -0,0 +1,98 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Tokens protecting the forms of the site against cross-site request forgery,
+//! tied to the user of the session submitting them.
+
+use std::fmt::Write;
+
+use anyhow::{anyhow, Result};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use plabayo_news_data::models::UserID;
+
+/// Name of the (hidden) form field containing the token.
+pub const FORM_FIELD: &str = "csrf";
+
+/// Secret used to derive the tokens, generated at startup unless configured,
+/// such that tokens of a previous run are no longer accepted.
+#[derive(Clone)]
+pub struct CsrfSecret([u8; 32]);
+
+impl CsrfSecret {
+    pub fn generate() -> CsrfSecret {
+        let mut secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        CsrfSecret(secret)
+    }
+
+    /// The secret encoded as the given 64 hex characters,
+    /// such that tokens remain valid across restarts.
+    pub fn from_hex(hex: &str) -> Result<CsrfSecret> {
+        let hex = hex.trim();
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(anyhow!("secret is not 64 hex characters"));
+        }
+        let mut secret = [0u8; 32];
+        for (i, byte) in secret.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+                .map_err(|_| anyhow!("secret is not 64 hex characters"))?;
+        }
+        Ok(CsrfSecret(secret))
+    }
+
+    /// The token to be embedded in all forms submitted by the given user.
+    pub fn token(&self, user: UserID) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.0);
+        hasher.update(user.to_be_bytes());
+        hasher
+            .finalize()
+            .iter()
+            .fold(String::with_capacity(64), |mut s, b| {
+                let _ = write!(s, "{:02x}", b);
+                s
+            })
+    }
+}
+
+/// Compare a submitted token with the expected one,
+/// in constant time so the comparison does not leak the expected token.
+pub fn tokens_match(expected: &str, submitted: &str) -> bool {
+    expected.len() == submitted.len()
+        && expected
+            .bytes()
+            .zip(submitted.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_is_tied_to_user_and_secret() {
+        let secret = CsrfSecret::generate();
+        let token = secret.token(1);
+        assert_eq!(token.len(), 64);
+        assert!(tokens_match(&token, &secret.token(1)));
+        assert!(!tokens_match(&token, &secret.token(2)));
+        assert!(!tokens_match(&token, &CsrfSecret::generate().token(1)));
+        assert!(!tokens_match(&token, ""));
+    }
+}
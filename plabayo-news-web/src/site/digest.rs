@@ -0,0 +1,248 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Email digests of the replies to the items of users,
+//! sent periodically to those who opted in for it and confirmed their email address.
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use actix_web::rt::time::delay_for;
+use actix_web::web;
+use anyhow::{anyhow, Result};
+
+use plabayo_news_data::models::{Item, User};
+use plabayo_news_data::Database;
+use plabayo_news_sendmail::{Email, Transport};
+
+use crate::site::l18n::locales::Locale;
+
+/// Time between two checks for digests which are due.
+const CHECK_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+pub struct DigestMailer {
+    db: Database,
+    transport: Arc<dyn Transport + Send + Sync>,
+    /// The sender of the digests, e.g. `Plabayo News <news@plabayo.tech>`.
+    from: String,
+    /// The public origin of the site (e.g. `https://news.plabayo.tech`),
+    /// used for the links within the digests.
+    origin: String,
+}
+
+impl DigestMailer {
+    pub fn new(
+        db: Database,
+        transport: Arc<dyn Transport + Send + Sync>,
+        from: String,
+        origin: String,
+    ) -> DigestMailer {
+        DigestMailer {
+            db,
+            transport,
+            from,
+            origin: origin.trim_end_matches('/').to_owned(),
+        }
+    }
+
+    /// Keep sending the digests as they become due.
+    pub async fn run(self: Arc<Self>) {
+        loop {
+            self.send_due().await;
+            delay_for(CHECK_INTERVAL).await;
+        }
+    }
+
+    /// Send all digests which are due, returning the amount of digests sent.
+    /// Users without new replies are skipped until their next digest is due.
+    pub async fn send_due(&self) -> usize {
+        let now = SystemTime::now();
+        let mut sent = 0;
+        for (user, since) in self.db.get_due_digests(now).await {
+            let mut replies = Vec::new();
+            for reply in self.db.get_replies(user.id).await {
+                if reply.time <= since {
+                    break;
+                }
+                let author = self.db.get_user(reply.by).await;
+                let story = match reply.parent {
+                    Some(parent) => self.db.get_item(parent, Some(user.id)).await,
+                    None => None,
+                };
+                replies.push((reply, author, story));
+            }
+            if !replies.is_empty() {
+                let email = match self.compose(&user, &replies) {
+                    Some(email) => email,
+                    None => continue,
+                };
+                let transport = self.transport.clone();
+                if let Err(err) = web::block(move || transport.send(&email)).await {
+                    log::error!("send email digest to user#{}: {}", user.id, err);
+                    continue;
+                }
+                sent += 1;
+            }
+            self.db.mark_digest_sent(user.id, now).await;
+        }
+        sent
+    }
+
+    fn compose(
+        &self,
+        user: &User,
+        replies: &[(Item, Option<User>, Option<Item>)],
+    ) -> Option<Email> {
+        let to = user.preferences.as_ref()?.email.clone()?;
+        let locale = locale_of(user);
+        let strings = &locale.strings().email.digest;
+
+        let mut body = String::new();
+        for (reply, author, parent) in replies {
+            let author = match author {
+                Some(author) => author.public_username(),
+                None => reply.by.to_string(),
+            };
+            let title = parent
+                .as_ref()
+                .and_then(|parent| parent.title.as_deref().or(parent.text.as_deref()))
+                .map(|title| title.lines().next().unwrap_or_default())
+                .unwrap_or_default();
+            body.push_str(&strings.reply(&author, title));
+            body.push_str("\n\n");
+            body.push_str(reply.text.as_deref().unwrap_or_default());
+            body.push_str(&format!(
+                "\n\n{}/{}/item?id={}\n\n---\n\n",
+                self.origin, locale, reply.id
+            ));
+        }
+        body.push_str(&strings.footer(&format!(
+            "{}/{}/threads?id={}",
+            self.origin, locale, user.id
+        )));
+
+        Some(Email {
+            from: self.from.clone(),
+            to,
+            subject: strings.subject(replies.len() as i64),
+            body,
+        })
+    }
+
+    /// Send the link with which the user confirms to own the given email address.
+    pub async fn send_confirmation(&self, user: &User, to: &str, token: &str) -> Result<()> {
+        let locale = locale_of(user);
+        let strings = &locale.strings().email.confirmation;
+        let email = Email {
+            from: self.from.clone(),
+            to: to.to_owned(),
+            subject: strings.subject.to_owned(),
+            body: strings.body(&format!(
+                "{}/{}/threads?confirm={}",
+                self.origin, locale, token
+            )),
+        };
+        let transport = self.transport.clone();
+        web::block(move || transport.send(&email))
+            .await
+            .map_err(|err| anyhow!("send email confirmation to user#{}: {}", user.id, err))
+    }
+}
+
+/// The locale of the emails sent to the user.
+fn locale_of(user: &User) -> Locale {
+    user.locale
+        .as_deref()
+        .and_then(|locale| Locale::try_from(locale).ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use plabayo_news_data::models::{DigestFrequency, UserPreferences};
+
+    use super::*;
+
+    /// Keeps the emails instead of delivering them.
+    #[derive(Default)]
+    struct Outbox(Mutex<Vec<Email>>);
+
+    impl Transport for Outbox {
+        fn send(&self, email: &Email) -> Result<()> {
+            self.0.lock().unwrap().push(email.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn due_digests_are_sent_to_confirmed_addresses_once() {
+        actix_web::rt::System::new("test").block_on(async {
+            let db = Database::new();
+            let outbox = Arc::new(Outbox::default());
+            let mailer = DigestMailer::new(
+                db.clone(),
+                outbox.clone(),
+                "news@plabayo.tech".to_owned(),
+                "https://news.plabayo.tech/".to_owned(),
+            );
+            for user in [100, 101] {
+                db.set_user_preferences(
+                    user,
+                    UserPreferences {
+                        email: Some(format!("{}@example.com", user)),
+                        digest: DigestFrequency::Daily,
+                        ..UserPreferences::default()
+                    },
+                )
+                .await;
+            }
+
+            // nothing is sent until an address is confirmed
+            assert_eq!(mailer.send_due().await, 0);
+            assert!(outbox.0.lock().unwrap().is_empty());
+
+            for user in [100, 101] {
+                let email = format!("{}@example.com", user);
+                let token = db.create_email_confirmation(user, &email).await;
+                assert!(db.confirm_email(user, &token, SystemTime::now()).await);
+            }
+
+            // only user#100 has new replies, but both digests are marked as sent
+            assert_eq!(mailer.send_due().await, 1);
+            assert!(db.get_due_digests(SystemTime::now()).await.is_empty());
+            {
+                let sent = outbox.0.lock().unwrap();
+                assert_eq!(sent.len(), 1);
+                let email = &sent[0];
+                assert_eq!(email.to, "100@example.com");
+                assert_eq!(email.from, "news@plabayo.tech");
+                assert_eq!(email.subject, "1 new reply on Plabayo News");
+                assert!(email.body.contains("\"an example news article\""));
+                assert!(email
+                    .body
+                    .contains("https://news.plabayo.tech/en/item?id=3\n"));
+                assert!(email
+                    .body
+                    .contains("https://news.plabayo.tech/en/threads?id=100"));
+            }
+
+            assert_eq!(mailer.send_due().await, 0);
+            assert_eq!(outbox.0.lock().unwrap().len(), 1);
+        });
+    }
+}
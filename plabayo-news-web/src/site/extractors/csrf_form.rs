@@ -0,0 +1,193 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::BTreeMap;
+
+use actix_web::dev::Payload;
+use actix_web::error::InternalError;
+use actix_web::http::header::{ORIGIN, REFERER};
+use actix_web::{web, Error, FromRequest, HttpRequest};
+use futures::future::LocalBoxFuture;
+
+use crate::site::extractors::Session;
+use crate::site::pages;
+
+/// A submitted form, only extracted when it was submitted using the site itself,
+/// defending all forms against cross-site request forgery:
+///
+/// - the form has to contain the token tied to the user of the session,
+///   anonymous submissions carry no authority and thus have no token;
+/// - as a second line of defense, the `Origin` (or `Referer`) of the request,
+///   when sent by the browser, has to be the site itself.
+///
+/// Rejected submissions are answered with a (localized) error page.
+pub struct CsrfForm(BTreeMap<String, String>);
+
+impl CsrfForm {
+    pub fn into_inner(self) -> BTreeMap<String, String> {
+        self.0
+    }
+}
+
+/// The reason a form submission was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsrfFailure {
+    /// The request originates from another site.
+    Origin,
+    /// The form does not contain the token of the user.
+    Token,
+}
+
+impl FromRequest for CsrfForm {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        let form = web::Form::<BTreeMap<String, String>>::from_request(&req, payload);
+        let session = Session::from_request(&req, &mut Payload::None);
+
+        Box::pin(async move {
+            let session = session.await?;
+            if !is_same_origin(&req, session.origin()) {
+                return Err(reject(&req, &session, CsrfFailure::Origin));
+            }
+            let form = form.await?.into_inner();
+            if session.user().is_some() && !session.verify_csrf(&form) {
+                return Err(reject(&req, &session, CsrfFailure::Token));
+            }
+            Ok(CsrfForm(form))
+        })
+    }
+}
+
+fn reject(req: &HttpRequest, session: &Session, failure: CsrfFailure) -> Error {
+    let reason = match failure {
+        CsrfFailure::Origin => "cross-origin form submission",
+        CsrfFailure::Token => "invalid csrf token",
+    };
+    match pages::forbidden_response(req, session, failure) {
+        Ok(response) => InternalError::from_response(reason, response).into(),
+        Err(err) => err,
+    }
+}
+
+/// Returns true if the request was sent from the site itself, as known by its configured origin,
+/// or if the browser did not tell where it was sent from,
+/// in which case the token remains the only defense.
+fn is_same_origin(req: &HttpRequest, origin: &str) -> bool {
+    let source = req
+        .headers()
+        .get(ORIGIN)
+        .or_else(|| req.headers().get(REFERER));
+    match source.map(|value| value.to_str()) {
+        None => true,
+        Some(Ok(url)) => strip_origin(url, origin).is_some(),
+        Some(Err(_)) => false,
+    }
+}
+
+/// The path (and query) of the page of this site the request was sent from, if known.
+pub fn referer_path(req: &HttpRequest, origin: &str) -> Option<String> {
+    let url = req.headers().get(REFERER)?.to_str().ok()?;
+    let path = strip_origin(url, origin)?;
+    Some(if path.starts_with('/') {
+        path.to_owned()
+    } else {
+        format!("/{}", path)
+    })
+}
+
+/// The remainder (e.g. `/item?id=1`) of an absolute URL within the given origin,
+/// none for URLs of other origins, including opaque origins such as `null`.
+fn strip_origin<'a>(url: &'a str, origin: &str) -> Option<&'a str> {
+    let (scheme, rest) = url.split_once("://")?;
+    let authority = rest.split(&['/', '?', '#'][..]).next()?;
+    let (url_origin, path) = url.split_at(scheme.len() + "://".len() + authority.len());
+    if url_origin.eq_ignore_ascii_case(origin.trim_end_matches('/')) {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    const ORIGIN_URL: &str = "https://news.plabayo.tech";
+
+    #[test]
+    fn origin_has_to_be_the_site_itself() {
+        let req = |name, value| {
+            TestRequest::default()
+                .header("host", "news.plabayo.tech")
+                .header(name, value)
+                .to_http_request()
+        };
+        let is_same_origin = |req| is_same_origin(&req, ORIGIN_URL);
+        assert!(is_same_origin(req("origin", "https://news.plabayo.tech")));
+        assert!(is_same_origin(req("origin", "https://NEWS.plabayo.tech")));
+        assert!(is_same_origin(req(
+            "referer",
+            "https://news.plabayo.tech/item?id=1"
+        )));
+        assert!(!is_same_origin(req("origin", "https://evil.example")));
+        assert!(!is_same_origin(req("origin", "http://news.plabayo.tech")));
+        assert!(!is_same_origin(req(
+            "referer",
+            "https://news.plabayo.tech.evil.example/"
+        )));
+        assert!(!is_same_origin(req("origin", "null")));
+        assert!(is_same_origin(
+            TestRequest::default()
+                .header("host", "news.plabayo.tech")
+                .to_http_request()
+        ));
+    }
+
+    #[test]
+    fn origin_is_not_taken_from_the_host_header() {
+        let req = TestRequest::default()
+            .header("host", "evil.example")
+            .header("origin", "https://evil.example")
+            .to_http_request();
+        assert!(!is_same_origin(&req, ORIGIN_URL));
+    }
+
+    #[test]
+    fn referer_path_is_only_given_for_the_site_itself() {
+        let req = |referer| {
+            TestRequest::default()
+                .header("host", "news.plabayo.tech")
+                .header("referer", referer)
+                .to_http_request()
+        };
+        let referer_path = |req| referer_path(&req, ORIGIN_URL);
+        assert_eq!(
+            referer_path(req("https://news.plabayo.tech/edit?item=1")),
+            Some("/edit?item=1".to_owned())
+        );
+        assert_eq!(
+            referer_path(req("https://news.plabayo.tech")),
+            Some("/".to_owned())
+        );
+        assert_eq!(referer_path(req("https://evil.example/edit")), None);
+    }
+}
@@ -14,6 +14,8 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+mod csrf_form;
 mod session;
 
+pub use csrf_form::{referer_path, CsrfFailure, CsrfForm};
 pub use session::Session;
@@ -15,27 +15,51 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use std::collections::BTreeMap;
-use std::time::SystemTime;
+use std::time::Duration;
 
 use actix_web::dev::Payload;
 use actix_web::http::header::ACCEPT_LANGUAGE;
-use actix_web::web::Query;
+use actix_web::web::{self, Query};
 use actix_web::{Error, FromRequest, HttpRequest};
 use anyhow::Result;
-use futures::future::{ready, Ready};
+use futures::future::LocalBoxFuture;
 
-use plabayo_news_data::models::{User, UserID, UserState};
+use plabayo_news_data::models::{ItemRelations, User, UserID};
 
+use crate::site::csrf;
 use crate::site::l18n::locales::Locale;
+use crate::site::middleware::{CspNonce, PathLocale};
+use crate::site::state::{AppState, DEFAULT_EDIT_WINDOW, DEFAULT_ORIGIN};
 
-#[derive(Default)]
 pub struct Session {
     headers: Headers,
-    user_ref: Option<UserReference>,
+    user: Option<User>,
+    item_relations: ItemRelations,
+    unread_replies: usize,
+    csrf_token: Option<String>,
+    edit_window: Duration,
+    origin: String,
+}
+
+impl Default for Session {
+    fn default() -> Session {
+        Session {
+            headers: Headers::default(),
+            user: None,
+            item_relations: ItemRelations::default(),
+            unread_replies: 0,
+            csrf_token: None,
+            edit_window: DEFAULT_EDIT_WINDOW,
+            origin: DEFAULT_ORIGIN.to_owned(),
+        }
+    }
 }
 
 impl Session {
     pub fn locale(&self) -> Locale {
+        if let Some(locale) = self.headers.path_locale {
+            return locale;
+        }
         if let Some(locale) = self
             .user()
             .as_ref()
@@ -50,22 +74,62 @@ impl Session {
         Locale::default()
     }
 
+    /// The locale explicitly requested using the path prefix (e.g. `/nl/news`), if any.
+    pub fn path_locale(&self) -> Option<Locale> {
+        self.headers.path_locale
+    }
+
     pub fn user(&self) -> Option<User> {
-        match self.user_ref.as_ref() {
-            None => None,
-            Some(user_ref) => user_ref.user().ok(), // TODO: log error perhaps!???!!
+        self.user.clone()
+    }
+
+    /// The relations (hidden, favorite, followed) the user has with items,
+    /// empty in case there is no user.
+    pub fn item_relations(&self) -> &ItemRelations {
+        &self.item_relations
+    }
+
+    /// Amount of replies to the items of the user made since they last read them.
+    pub fn unread_replies(&self) -> usize {
+        self.unread_replies
+    }
+
+    /// The token to embed in forms submitted by the user, if there is a user.
+    pub fn csrf_token(&self) -> Option<&str> {
+        self.csrf_token.as_deref()
+    }
+
+    /// Returns true if the form contains the token of the user,
+    /// and thus was submitted using the site itself.
+    pub fn verify_csrf(&self, form: &BTreeMap<String, String>) -> bool {
+        match (self.csrf_token(), form.get(csrf::FORM_FIELD)) {
+            (Some(expected), Some(submitted)) => csrf::tokens_match(expected, submitted),
+            _ => false,
         }
     }
+
+    /// The time within which authors can still edit and delete their own items.
+    pub fn edit_window(&self) -> Duration {
+        self.edit_window
+    }
+
+    /// The public origin of the site, used for its absolute links.
+    pub fn origin(&self) -> &str {
+        &self.origin
+    }
+
+    /// The nonce allowing inline scripts and styles by the Content-Security-Policy
+    /// of the response, if the policy is enforced at all.
+    pub fn csp_nonce(&self) -> Option<&str> {
+        self.headers.csp_nonce.as_deref()
+    }
 }
 
 #[derive(Default)]
 struct Headers {
     locale: Option<Locale>,
-}
-
-struct UserReference {
-    user_id: UserID,
-    last_fetch_time: SystemTime,
+    path_locale: Option<Locale>,
+    csp_nonce: Option<String>,
 }
 
 impl Headers {
@@ -88,55 +152,71 @@ impl Headers {
             })
             .next();
 
-        Headers { locale }
+        let path_locale = req
+            .extensions()
+            .get::<PathLocale>()
+            .map(|path_locale| path_locale.0);
+
+        let csp_nonce = req
+            .extensions()
+            .get::<CspNonce>()
+            .map(|nonce| nonce.0.clone());
+
+        Headers {
+            locale,
+            path_locale,
+            csp_nonce,
+        }
     }
 }
 
 impl FromRequest for Session {
     type Error = Error;
-    type Future = Ready<Result<Self, Error>>;
+    type Future = LocalBoxFuture<'static, Result<Self, Error>>;
     type Config = SessionConfig;
 
     fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
         let headers = Headers::from_request(req);
+        let app_state = req.app_data::<web::Data<AppState>>().cloned();
         // TODO: delete this hack once we have a reg/login system
         let query_opt: Option<Query<BTreeMap<String, String>>> =
             Query::from_query(req.query_string()).ok();
-        if let Some(user_id) = query_opt.as_ref().and_then(|q| q.get("id")) {
-            if let Ok(user_id_num) = user_id.parse::<UserID>() {
-                return ready(Ok(Session {
-                    headers,
-                    user_ref: Some(UserReference {
-                        user_id: user_id_num,
-                        last_fetch_time: SystemTime::UNIX_EPOCH,
-                    }),
-                }));
-            }
-        }
-        ready(Ok(Session {
-            headers,
-            user_ref: None,
-        }))
-    }
-}
-
-impl UserReference {
-    pub fn user(&self) -> Result<User> {
-        Ok(User {
-            id: self.user_id,
-            state: UserState::Public,
-            username: Some(format!("User#{}", self.user_id)),
-            name: None,
-            locale: None,
-            location: None,
-            create_time: self.last_fetch_time,
-            last_login_time: self.last_fetch_time,
-            karma: 42,
-            about: None,
-            items: vec![],
-            ips: vec![],
-            authentications: vec![],
-            preferences: None,
+        let user_id: Option<UserID> = query_opt
+            .as_ref()
+            .and_then(|q| q.get("id"))
+            .and_then(|user_id| user_id.parse().ok());
+
+        Box::pin(async move {
+            let app_state = match app_state {
+                Some(app_state) => app_state,
+                None => {
+                    return Ok(Session {
+                        headers,
+                        ..Session::default()
+                    })
+                }
+            };
+            let user = match user_id {
+                Some(user_id) => app_state.db.get_user(user_id).await,
+                None => None,
+            };
+            let (item_relations, unread_replies, csrf_token) = match &user {
+                Some(user) => (
+                    app_state.db.get_item_relations(user.id).await,
+                    app_state.db.count_unread_replies(user.id).await,
+                    Some(app_state.csrf_secret.token(user.id)),
+                ),
+                None => (ItemRelations::default(), 0, None),
+            };
+            Ok(Session {
+                headers,
+                user,
+                item_relations,
+                unread_replies,
+                csrf_token,
+                edit_window: app_state.edit_window,
+                origin: app_state.origin.clone(),
+            })
         })
     }
 }
@@ -0,0 +1,419 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! RSS 2.0 and Atom feeds of the front page, newest stories, questions,
+//! as well as the submissions of a single user or site.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use actix_web::dev::HttpServiceFactory;
+use actix_web::error::ErrorNotFound;
+use actix_web::http::header::CONTENT_LANGUAGE;
+use actix_web::{web, HttpResponse, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use plabayo_news_data::models::{Item, ItemKind, UserState};
+use plabayo_news_data::sites;
+
+use crate::site::content::{escape_html, markdown_to_html, sanitize_url};
+use crate::site::extractors::Session;
+use crate::site::state::AppState;
+
+/// Time (in seconds) feeds may be cached by readers and proxies.
+pub const FEED_MAX_CACHE_AGE_SEC: u32 = 5 * 60;
+
+/// Maximum amount of entries in a single feed.
+const MAX_ENTRIES: usize = 30;
+
+//---------------------------------------
+// Actix Web Factory
+//---------------------------------------
+
+pub fn factory() -> impl HttpServiceFactory + 'static {
+    web::resource("/{feed:(?:(?:newest|ask|user|from)/)?(?:rss|atom)}")
+        .route(web::get().to(serve_feed))
+}
+
+/// Returns true if the given path refers to a feed.
+pub fn is_feed_path(path: &str) -> bool {
+    matches!(path.rsplit('/').next(), Some("rss" | "atom"))
+}
+
+//---------------------------------------
+// Serve Definitions
+//---------------------------------------
+
+async fn serve_feed(
+    path: web::Path<(String,)>,
+    query: web::Query<BTreeMap<String, String>>,
+    session: Session,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let feed = path.into_inner().0;
+    let (list, format) = match feed.rsplit_once('/') {
+        Some((list, format)) => (list, format),
+        None => ("", feed.as_str()),
+    };
+
+    let locale = session.locale();
+    let strings = &locale.strings().site.feeds;
+    let db = &app_state.db;
+
+    // the configured origin is used for all links, as feeds are cached for all readers,
+    // the (locale independent) ids don't include the locale selected using the path
+    let origin = session.origin();
+    let root = match session.path_locale() {
+        Some(locale) => format!("{}/{}", origin, locale),
+        None => origin.to_owned(),
+    };
+
+    let (title, page, feed_query, items) = match list {
+        "" => (
+            strings.front.to_owned(),
+            "/news".to_owned(),
+            String::new(),
+            db.get_news_ranked(None).await,
+        ),
+        "newest" => (
+            strings.newest.to_owned(),
+            "/newest".to_owned(),
+            String::new(),
+            db.get_news_newest(None).await,
+        ),
+        "ask" => (
+            strings.ask.to_owned(),
+            "/ask".to_owned(),
+            String::new(),
+            db.get_ask_ranked(None).await,
+        ),
+        "user" => {
+            let user = match query.get("id").and_then(|id| id.parse().ok()) {
+                Some(id) => db.get_user(id).await,
+                None => None,
+            }
+            .filter(|user| matches!(user.state, UserState::Public))
+            .ok_or_else(|| ErrorNotFound("user not found"))?;
+            (
+                strings.user(&user.public_username()),
+                format!("/user?id={}", user.id),
+                format!("?id={}", user.id),
+                db.get_user_submissions(user.id, None).await,
+            )
+        }
+        _ => {
+            let site = query
+                .get("site")
+                .and_then(|site| sites::site_of_host(site))
+                .ok_or_else(|| ErrorNotFound("site not found"))?;
+            (
+                strings.site(&site),
+                format!("/from?site={}", site),
+                format!("?site={}", site),
+                db.get_news_from_site(&site, None).await,
+            )
+        }
+    };
+
+    let mut entries = Vec::new();
+    for item in items.into_iter().take(MAX_ENTRIES) {
+        let author = db
+            .get_user(item.by)
+            .await
+            .map(|user| user.public_username())
+            .unwrap_or_else(|| item.by.to_string());
+        let comments_label = locale.strings().page.items.comments(item.kids.len() as i64);
+        entries.push(Entry::from_data(
+            item,
+            author,
+            comments_label,
+            origin,
+            &root,
+        ));
+    }
+
+    let list_path = match list {
+        "" => String::new(),
+        list => format!("/{}", list),
+    };
+
+    let feed = Feed {
+        title,
+        description: locale.strings().site.description.to_owned(),
+        language: locale.as_str(),
+        link: format!("{}{}", root, page),
+        self_link: format!("{}{}/{}{}", root, list_path, format, feed_query),
+        id: format!("{}{}/atom{}", origin, list_path, feed_query),
+        updated: entries
+            .iter()
+            .map(|entry| entry.updated)
+            .max()
+            .unwrap_or_else(Utc::now),
+        entries,
+    };
+
+    let (content_type, body) = if format == "atom" {
+        ("application/atom+xml; charset=utf-8", feed.to_atom())
+    } else {
+        ("application/rss+xml; charset=utf-8", feed.to_rss())
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .set_header(CONTENT_LANGUAGE, locale.as_str())
+        .body(body))
+}
+
+//---------------------------------------
+// Feed Definitions
+//---------------------------------------
+
+struct Feed {
+    title: String,
+    description: String,
+    language: &'static str,
+    link: String,
+    self_link: String,
+    /// The Atom feed, without locale, used as the (fixed) id of the feed.
+    id: String,
+    updated: DateTime<Utc>,
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    title: String,
+    link: String,
+    /// The item page, without locale, unique for each entry and thus used as its id.
+    id: String,
+    /// The item page, with its comments.
+    comments: String,
+    comments_label: String,
+    author: String,
+    published: DateTime<Utc>,
+    updated: DateTime<Utc>,
+    /// The rendered (sanitized) html of the item's text, if any.
+    content: Option<String>,
+}
+
+impl Entry {
+    fn from_data(
+        item: Item,
+        author: String,
+        comments_label: String,
+        origin: &str,
+        root: &str,
+    ) -> Entry {
+        let comments = format!("{}/item?id={}", root, item.id);
+        let link = match item.kind {
+            ItemKind::Story => item.url.as_deref().and_then(sanitize_url),
+            _ => None,
+        }
+        .unwrap_or_else(|| comments.clone());
+        Entry {
+            title: item.title.unwrap_or_default(),
+            link,
+            id: format!("{}/item?id={}", origin, item.id),
+            comments,
+            comments_label,
+            author,
+            published: DateTime::<Utc>::from(item.time),
+            updated: DateTime::<Utc>::from(item.mod_time),
+            content: item
+                .text
+                .as_deref()
+                .map(|text| markdown_to_html(text).to_string()),
+        }
+    }
+
+    /// The description of the entry, its text if it has any,
+    /// and a link to the item page (with its comments) otherwise.
+    fn description(&self) -> String {
+        match &self.content {
+            Some(content) => content.clone(),
+            None => format!(
+                "<a href=\"{}\">{}</a>",
+                escape_html(&self.comments),
+                escape_html(&self.comments_label)
+            ),
+        }
+    }
+}
+
+impl Feed {
+    fn to_rss(&self) -> String {
+        let mut s = String::new();
+        let _ = write!(
+            s,
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n\
+             <channel>\n\
+             <title>{}</title>\n\
+             <link>{}</link>\n\
+             <description>{}</description>\n\
+             <language>{}</language>\n\
+             <lastBuildDate>{}</lastBuildDate>\n\
+             <atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n",
+            escape_html(&self.title),
+            escape_html(&self.link),
+            escape_html(&self.description),
+            self.language,
+            self.updated.to_rfc2822(),
+            escape_html(&self.self_link),
+        );
+        for entry in &self.entries {
+            let _ = write!(
+                s,
+                "<item>\n\
+                 <title>{}</title>\n\
+                 <link>{}</link>\n\
+                 <guid isPermaLink=\"true\">{}</guid>\n\
+                 <comments>{}</comments>\n\
+                 <pubDate>{}</pubDate>\n\
+                 <description>{}</description>\n\
+                 </item>\n",
+                escape_html(&entry.title),
+                escape_html(&entry.link),
+                escape_html(&entry.id),
+                escape_html(&entry.comments),
+                entry.published.to_rfc2822(),
+                escape_html(&entry.description()),
+            );
+        }
+        s.push_str("</channel>\n</rss>\n");
+        s
+    }
+
+    fn to_atom(&self) -> String {
+        let mut s = String::new();
+        let _ = write!(
+            s,
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <feed xmlns=\"http://www.w3.org/2005/Atom\" xml:lang=\"{}\">\n\
+             <title>{}</title>\n\
+             <subtitle>{}</subtitle>\n\
+             <id>{}</id>\n\
+             <link href=\"{}\"/>\n\
+             <link href=\"{}\" rel=\"self\"/>\n\
+             <updated>{}</updated>\n",
+            self.language,
+            escape_html(&self.title),
+            escape_html(&self.description),
+            escape_html(&self.id),
+            escape_html(&self.link),
+            escape_html(&self.self_link),
+            self.updated.to_rfc3339_opts(SecondsFormat::Secs, true),
+        );
+        for entry in &self.entries {
+            let _ = write!(
+                s,
+                "<entry>\n\
+                 <title>{}</title>\n\
+                 <id>{}</id>\n\
+                 <link href=\"{}\"/>\n\
+                 <link href=\"{}\" rel=\"replies\" type=\"text/html\"/>\n\
+                 <author><name>{}</name></author>\n\
+                 <published>{}</published>\n\
+                 <updated>{}</updated>\n\
+                 <content type=\"html\">{}</content>\n\
+                 </entry>\n",
+                escape_html(&entry.title),
+                escape_html(&entry.id),
+                escape_html(&entry.link),
+                escape_html(&entry.comments),
+                escape_html(&entry.author),
+                entry.published.to_rfc3339_opts(SecondsFormat::Secs, true),
+                entry.updated.to_rfc3339_opts(SecondsFormat::Secs, true),
+                escape_html(&entry.description()),
+            );
+        }
+        s.push_str("</feed>\n");
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+
+    use super::*;
+    use crate::site::middleware::LocalePath;
+
+    /// Serve the feed at the given URI, as requested using the given Host header.
+    fn feed(uri: &str, host: &str) -> String {
+        let (uri, host) = (uri.to_owned(), host.to_owned());
+        actix_web::rt::System::new("test").block_on(async move {
+            let mut app = test::init_service(
+                App::new()
+                    .app_data(web::Data::new(AppState::new()))
+                    .wrap(LocalePath)
+                    .service(factory()),
+            )
+            .await;
+            let req = test::TestRequest::get()
+                .uri(&uri)
+                .header("host", host.as_str())
+                .header("x-forwarded-host", host.as_str())
+                .to_request();
+            let res = test::call_service(&mut app, req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            String::from_utf8(test::read_body(res).await.to_vec()).unwrap()
+        })
+    }
+
+    #[test]
+    fn rss_feed_links_to_the_site() {
+        let rss = feed("/newest/rss", "news.plabayo.tech");
+        assert!(rss.starts_with("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<rss version=\"2.0\""));
+        assert!(rss.contains("<link>https://news.plabayo.tech/newest</link>"));
+        assert!(rss.contains(
+            "<atom:link href=\"https://news.plabayo.tech/newest/rss\" rel=\"self\" type=\"application/rss+xml\"/>"
+        ));
+        assert!(rss.contains("<guid isPermaLink=\"true\">https://news.plabayo.tech/item?id="));
+        assert!(rss.contains("<comments>https://news.plabayo.tech/item?id="));
+        assert!(rss.ends_with("</channel>\n</rss>\n"));
+    }
+
+    #[test]
+    fn atom_feed_has_a_fixed_id() {
+        let atom = feed("/nl/ask/atom", "news.plabayo.tech");
+        assert!(atom.contains("<feed xmlns=\"http://www.w3.org/2005/Atom\" xml:lang=\"nl\">"));
+        assert!(atom.contains("<id>https://news.plabayo.tech/ask/atom</id>"));
+        assert!(atom.contains("<link href=\"https://news.plabayo.tech/nl/ask\"/>"));
+        assert!(
+            atom.contains("<link href=\"https://news.plabayo.tech/nl/ask/atom\" rel=\"self\"/>")
+        );
+        assert!(atom.contains("<entry>\n<title>"));
+        assert!(atom.contains("<id>https://news.plabayo.tech/item?id="));
+        assert!(atom.ends_with("</feed>\n"));
+    }
+
+    #[test]
+    fn links_do_not_depend_on_the_host() {
+        for uri in ["/rss", "/atom", "/nl/newest/atom"] {
+            let expected = feed(uri, "news.plabayo.tech");
+            let forged = feed(uri, "evil.example");
+            assert!(!forged.contains("evil.example"));
+            assert_eq!(strip_dates(&forged), strip_dates(&expected));
+        }
+    }
+
+    /// The feed without its build date, which can differ between two requests.
+    fn strip_dates(feed: &str) -> String {
+        feed.lines()
+            .filter(|line| !line.starts_with("<lastBuildDate>") && !line.starts_with("<updated>"))
+            .collect()
+    }
+}
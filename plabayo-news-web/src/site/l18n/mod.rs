@@ -31,3 +31,70 @@
 
 pub mod locales;
 pub mod pages;
+
+#[cfg(test)]
+mod tests {
+    use super::locales::{interpolate, Locale, PluralCategory};
+
+    #[test]
+    fn plural_categories_follow_the_rules_of_the_locale() {
+        use PluralCategory::{Many, One, Other};
+
+        let counts = [0, 1, 2, 5, 21, -1, 1_000_000, 2_000_000, 1_000_001];
+        for (locale, categories) in [
+            (
+                Locale::En,
+                [Other, One, Other, Other, Other, One, Other, Other, Other],
+            ),
+            (
+                Locale::Nl,
+                [Other, One, Other, Other, Other, One, Other, Other, Other],
+            ),
+            (
+                Locale::De,
+                [Other, One, Other, Other, Other, One, Other, Other, Other],
+            ),
+            (
+                Locale::Es,
+                [Other, One, Other, Other, Other, One, Many, Many, Other],
+            ),
+            (
+                Locale::Fr,
+                [One, One, Other, Other, Other, One, Many, Many, Other],
+            ),
+        ] {
+            for (count, category) in counts.iter().zip(categories) {
+                assert_eq!(
+                    locale.plural_category(*count),
+                    category,
+                    "count {} in {}",
+                    count,
+                    locale
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn placeholders_are_interpolated() {
+        assert_eq!(
+            interpolate(
+                "{user} replied on {title}",
+                &[("user", &"ann"), ("title", &"x")]
+            ),
+            "ann replied on x"
+        );
+        assert_eq!(interpolate("{count} {count}", &[("count", &2)]), "2 2");
+        assert_eq!(
+            interpolate("no placeholders", &[("count", &2)]),
+            "no placeholders"
+        );
+        // unknown or unterminated placeholders are kept as they are
+        assert_eq!(
+            interpolate("{unknown} and {count", &[("count", &2)]),
+            "{unknown} and {count"
+        );
+        assert_eq!(interpolate("{{count}}", &[("count", &2)]), "{2}");
+        assert_eq!(interpolate("{}", &[]), "{}");
+    }
+}
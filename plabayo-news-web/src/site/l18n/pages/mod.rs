@@ -17,7 +17,11 @@
 mod generated;
 pub mod models;
 
-pub use generated::{static_response, PageItem, PageItems, PageSearch};
+pub use generated::{
+    static_response, PageAdminApi, PageApi, PageComments, PageDelete, PageEdit, PageFavorites,
+    PageForbidden, PageItem, PageItems, PageNewest, PagePast, PageRateLimited, PageSearch,
+    PageThreads,
+};
 
 use crate::site::assets;
 
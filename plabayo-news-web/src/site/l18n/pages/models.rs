@@ -14,12 +14,59 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use plabayo_news_data::models;
+use std::time::SystemTime;
+
+use chrono::{DateTime, Utc};
+
+use plabayo_news_data::{models, sites};
+
+use crate::site::content;
+use crate::site::l18n::locales::{Locale, Safe};
+use crate::site::pages::PageState;
 
 pub struct ContentItems {
     pub items: Vec<Item>,
 }
 
+pub struct ContentNewest {
+    pub items: Vec<Item>,
+    /// Show the items in the penalty box, hidden by default.
+    pub show_hidden: bool,
+    /// The (1-based) number of the next page, if there is one.
+    pub next_page: Option<usize>,
+}
+
+pub struct ContentPast {
+    pub title: String,
+    pub items: Vec<Item>,
+    /// The day before the shown day, formatted as `YYYY-MM-DD`.
+    pub prev_day: String,
+    /// The day after the shown day, formatted as `YYYY-MM-DD`,
+    /// not defined in case the shown day is today.
+    pub next_day: Option<String>,
+}
+
+pub struct ContentComments {
+    pub comments: Vec<Comment>,
+}
+
+pub struct ContentThreads {
+    pub title: String,
+    pub comments: Vec<Comment>,
+    /// The email address to send the digest to.
+    pub email: String,
+    /// How often the digest is sent: `never`, `daily` or `weekly`.
+    pub digest: String,
+    /// Whether the user confirmed to own the email address.
+    pub email_verified: bool,
+    /// Whether the link to confirm the email address was sent just now.
+    pub confirmation_sent: bool,
+    /// Whether the email address was confirmed just now.
+    pub confirmed: bool,
+    pub error: Option<String>,
+    pub saved: bool,
+}
+
 pub struct ContentItem {
     pub q: String,
 }
@@ -28,18 +75,119 @@ pub struct ContentSearch {
     pub q: String,
 }
 
+#[derive(Default)]
+pub struct ContentApi {
+    pub organization: String,
+    pub motivation: String,
+    pub error: Option<String>,
+    pub sent: bool,
+}
+
+pub struct ContentFavorites {
+    pub title: String,
+    pub items: Vec<Item>,
+    /// The favorites are those of the current user.
+    pub own: bool,
+    pub public: bool,
+}
+
+pub struct ContentEdit {
+    pub id: models::ItemID,
+    /// The title of the item, not defined for comments.
+    pub title: Option<String>,
+    /// The url of the item, only defined for stories.
+    pub url: Option<String>,
+    pub text: String,
+    pub error: Option<String>,
+    /// The previous versions of the item, only shown to moderators.
+    pub revisions: Option<Vec<Revision>>,
+}
+
+pub struct ContentDelete {
+    pub id: models::ItemID,
+    /// The title of the item, or its text in case it has no title.
+    pub summary: String,
+}
+
+pub struct ContentForbidden {
+    /// Why the request was refused.
+    pub message: String,
+    /// The link to return to the page the request was sent from.
+    pub back: String,
+}
+
+pub struct ContentRateLimited {
+    /// Seconds to wait before the request can be retried.
+    pub retry_after_sec: u64,
+}
+
+pub struct ContentAdminApi {
+    pub issued_key: Option<String>,
+    /// The limits proposed for the keys of the requests to approve.
+    pub default_limits: models::ApiKeyLimits,
+    pub requests: Vec<ApiAccessRequest>,
+    pub keys: Vec<ApiKey>,
+}
+
 pub struct Item {
     pub id: models::ItemID,
     pub hidden: bool,
     pub modified: bool,
+    /// The current user is allowed to edit and delete this item.
+    pub editable: bool,
+    /// The current user hid this item from their listings.
+    pub user_hidden: bool,
+    pub favorite: bool,
+    /// The current user follows the thread of this item.
+    pub following: bool,
+    /// Label of the replies the current user did not see yet, if any.
+    pub new_replies: Option<String>,
     pub by: String,
     pub by_id: models::UserID,
     pub rel_time: String,
     pub votes: i64,
+    pub points: String,
     pub title: String,
     pub url: Option<Url>,
-    pub text: Option<String>,
+    pub text: Option<Safe<String>>,
     pub comments: Vec<models::ItemID>, // TODO
+    pub comments_label: String,
+}
+
+pub struct Comment {
+    pub id: models::ItemID,
+    pub by: String,
+    pub by_id: models::UserID,
+    pub rel_time: String,
+    pub text: Option<Safe<String>>,
+    pub parent: Option<models::ItemID>,
+    /// The story (or question) the comment was made on.
+    pub story: Option<StoryRef>,
+    /// The comment is a reply not yet read by the current user.
+    pub unread: bool,
+}
+
+pub struct StoryRef {
+    pub id: models::ItemID,
+    pub title: String,
+}
+
+impl Comment {
+    pub fn from_data(data: models::Item, story: Option<models::Item>, page: &PageState) -> Comment {
+        Comment {
+            id: data.id,
+            by: format!("user#{}", data.by), // TODO: actually fetch user
+            by_id: data.by,
+            rel_time: format_rel_time(page.locale, data.time, page.gen_date_time),
+            text: data.text.as_deref().map(content::markdown_to_html),
+            parent: data.parent,
+            story: story.map(|story| StoryRef {
+                id: story.id,
+                title: story.title.unwrap_or_default(),
+            }),
+            unread: false,
+        }
+    }
 }
 
 pub struct Url {
@@ -48,22 +196,158 @@ pub struct Url {
 }
 
 impl Item {
-    pub fn from_data(data: models::Item) -> Item {
+    pub fn from_data(data: models::Item, page: &PageState) -> Item {
+        let strings = page.locale.strings();
+        let comments = vec![];
         Item {
             id: data.id,
             hidden: !matches!(data.state, models::ItemState::Alive),
             modified: data.time < data.mod_time,
+            editable: page.can_edit(&data),
+            user_hidden: page.relations.hidden.contains(&data.id),
+            favorite: page.relations.favorites.contains(&data.id),
+            following: page.relations.followed.contains_key(&data.id),
+            new_replies: page
+                .relations
+                .followed
+                .get(&data.id)
+                .filter(|thread| thread.new_replies > 0)
+                .map(|thread| strings.page.items.new_replies(thread.new_replies as i64)),
             by: format!("user#{}", data.by), // TODO: actually fetch user
             by_id: data.by,
-            rel_time: "4 hours ago".to_owned(), // actually calculate based on current time
+            rel_time: format_rel_time(page.locale, data.time, page.gen_date_time),
             votes: data.votes,
+            points: strings.page.items.points(data.votes),
             title: data.title.unwrap_or_default(),
-            url: data.url.map(|url| Url {
-                full: url,
-                domain: "example.org".to_owned(), // TODO
-            }),
-            text: data.text,
-            comments: vec![],
+            url: data
+                .url
+                .as_deref()
+                .and_then(content::sanitize_url)
+                .and_then(|url| {
+                    Some(Url {
+                        domain: sites::site_of_url(&url)?,
+                        full: url,
+                    })
+                }),
+            text: data.text.as_deref().map(content::markdown_to_html),
+            comments_label: strings.page.items.comments(comments.len() as i64),
+            comments,
+        }
+    }
+}
+
+pub struct Revision {
+    pub label: String,
+    pub title: Option<String>,
+    pub url: Option<String>,
+    pub text: Option<String>,
+}
+
+impl Revision {
+    pub fn from_data(data: models::ItemRevision, page: &PageState) -> Revision {
+        Revision {
+            label: page.locale.strings().page.edit.revision(
+                &format_rel_time(page.locale, data.time, page.gen_date_time),
+                &format!("user#{}", data.by), // TODO: actually fetch user
+            ),
+            title: data.content.title,
+            url: data.content.url,
+            text: data.content.text,
+        }
+    }
+}
+
+pub struct ApiAccessRequest {
+    pub id: models::ApiAccessRequestID,
+    pub owner: String,
+    pub motivation: String,
+    pub rel_time: String,
+    pub state: String,
+    pub pending: bool,
+}
+
+impl ApiAccessRequest {
+    pub fn from_data(data: models::ApiAccessRequest, page: &PageState) -> ApiAccessRequest {
+        let strings = &page.locale.strings().page.admin_api;
+        ApiAccessRequest {
+            id: data.id,
+            owner: strings.requested_by(&format_api_key_owner(&data.owner)),
+            motivation: data.motivation,
+            rel_time: format_rel_time(page.locale, data.time, page.gen_date_time),
+            state: match data.state {
+                models::ApiAccessRequestState::Pending => strings.pending,
+                models::ApiAccessRequestState::Approved(_) => strings.approved,
+                models::ApiAccessRequestState::Rejected => strings.rejected,
+            }
+            .to_owned(),
+            pending: data.state == models::ApiAccessRequestState::Pending,
         }
     }
 }
+
+pub struct ApiKey {
+    pub id: models::ApiKeyID,
+    pub prefix: String,
+    pub owner: String,
+    pub limits: String,
+    pub rel_time: String,
+    pub state: String,
+    pub revoked: bool,
+}
+
+impl ApiKey {
+    pub fn from_data(data: models::ApiKey, page: &PageState) -> ApiKey {
+        let strings = &page.locale.strings().page.admin_api;
+        let revoked = data.is_revoked();
+        ApiKey {
+            id: data.id,
+            prefix: data.prefix,
+            owner: strings.owned_by(&format_api_key_owner(&data.owner)),
+            limits: strings.limits(
+                &data.limits.requests_per_minute.to_string(),
+                &data.limits.requests_per_day.to_string(),
+            ),
+            rel_time: format_rel_time(page.locale, data.create_time, page.gen_date_time),
+            state: if revoked {
+                strings.revoked
+            } else {
+                strings.active
+            }
+            .to_owned(),
+            revoked,
+        }
+    }
+}
+
+fn format_api_key_owner(owner: &models::ApiKeyOwner) -> String {
+    // TODO: actually fetch user
+    match owner {
+        models::ApiKeyOwner::User(id) => format!("user#{}", id),
+        models::ApiKeyOwner::Organization { name, contact } => {
+            format!("{} (user#{})", name, contact)
+        }
+    }
+}
+
+/// Format the time passed since the given time,
+/// relative to the given moment (e.g. the page generation time) as "now".
+fn format_rel_time(locale: Locale, time: SystemTime, now: DateTime<Utc>) -> String {
+    const MINUTE: i64 = 60;
+    const HOUR: i64 = 60 * MINUTE;
+    const DAY: i64 = 24 * HOUR;
+    const MONTH: i64 = 30 * DAY;
+    const YEAR: i64 = 365 * DAY;
+
+    let ago = &locale.strings().site.time.ago;
+    match now
+        .signed_duration_since(DateTime::<Utc>::from(time))
+        .num_seconds()
+    {
+        s if s < MINUTE => ago.now.to_owned(),
+        s if s < HOUR => ago.minutes(s / MINUTE),
+        s if s < DAY => ago.hours(s / HOUR),
+        s if s < MONTH => ago.days(s / DAY),
+        s if s < YEAR => ago.months(s / MONTH),
+        s => ago.years(s / YEAR),
+    }
+}
@@ -14,68 +14,106 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::cell::RefCell;
 use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 
-use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
-use actix_web::http::header::{CacheControl, CacheDirective, Header, IntoHeaderValue};
-use actix_web::Error;
+use actix_web::dev::{
+    Body, MessageBody, ResponseBody, Service, ServiceRequest, ServiceResponse, Transform,
+};
+use actix_web::error::ErrorInternalServerError;
+use actix_web::http::header::{self, CacheControl, CacheDirective, Header, IntoHeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::{web, Error, FromRequest, HttpMessage};
 use futures::future::{ok, Ready};
 use futures::Future;
 
+use plabayo_news_data::models::ActionID;
+
+use crate::site::assets;
+use crate::site::cache::{CacheKey, CachedResponse, ResponseCache};
+use crate::site::conditional::{self, Preconditions};
+use crate::site::extractors::Session;
+use crate::site::feeds;
 use crate::site::l18n::pages;
+use crate::site::middleware::CspNonce;
+use crate::site::state::AppState;
 
 // There are two steps in middleware processing.
 // 1. Middleware initialization, middleware factory gets called with
 //    next service in chain as parameter.
 // 2. Middleware's call method gets called with normal request.
-#[derive(Default)]
-pub struct Cache;
+//
+// Besides the Cache-Control headers, successful responses of cacheable routes
+// are served from the in-process response cache for anonymous visitors,
+// and conditional requests are answered with a 304 when the client's copy is still fresh.
+pub struct Cache {
+    cache: Arc<ResponseCache>,
+}
+
+impl Cache {
+    pub fn new(cache: Arc<ResponseCache>) -> Cache {
+        Cache { cache }
+    }
+}
 
 // Middleware factory is `Transform` trait from actix-service crate
 // `S` - type of the next service
 // `B` - type of response's body
 impl<S, B> Transform<S> for Cache
 where
-    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
-    B: 'static,
+    B: MessageBody + Unpin + 'static,
 {
     type Request = ServiceRequest;
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<Body>;
     type Error = Error;
     type InitError = ();
     type Transform = CacheMiddleware<S>;
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        ok(CacheMiddleware { service })
+        ok(CacheMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            cache: self.cache.clone(),
+        })
     }
 }
 
 pub struct CacheMiddleware<S> {
-    service: S,
+    service: Rc<RefCell<S>>,
+    cache: Arc<ResponseCache>,
 }
 
 impl<S, B> Service for CacheMiddleware<S>
 where
-    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
-    B: 'static,
+    B: MessageBody + Unpin + 'static,
 {
     type Request = ServiceRequest;
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<Body>;
     type Error = Error;
     #[allow(clippy::type_complexity)]
     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.service.poll_ready(cx)
+        self.service.borrow_mut().poll_ready(cx)
     }
 
     fn call(&mut self, req: ServiceRequest) -> Self::Future {
         let cache_control_directive =
-            IntoHeaderValue::try_into(if let Ok(cc) = CacheControl::parse(&req) {
+            IntoHeaderValue::try_into(if assets::is_fingerprinted_path(req.path()) {
+                // the content of a fingerprinted asset never changes
+                CacheControl(vec![
+                    CacheDirective::MaxAge(assets::FINGERPRINTED_MAX_AGE_SEC),
+                    CacheDirective::Public,
+                    CacheDirective::Extension("immutable".to_owned(), None),
+                ])
+            } else if let Ok(cc) = CacheControl::parse(&req) {
                 if cc.iter().any(|dir| dir == &CacheDirective::NoCache) {
                     CacheControl(vec![CacheDirective::NoCache])
                 } else if let Some(CacheDirective::MaxAge(age)) = cc
@@ -98,10 +136,73 @@ where
             })
             .unwrap();
 
-        let fut = self.service.call(req);
+        let service = self.service.clone();
+        let cache = self.cache.clone();
 
         Box::pin(async move {
-            let mut res = fut.await?;
+            let preconditions = Preconditions::of(req.method(), &req);
+            let ttl = match *req.method() {
+                Method::GET => ResponseCache::ttl(req.path()),
+                _ => None,
+            };
+            let (req, key) = match ttl {
+                Some(_) => anonymous_cache_key(req).await?,
+                None => (req, None),
+            };
+
+            // anonymous visitors can revalidate their copy of a page
+            // without it being rendered (or even looked up) again
+            let etag = match &key {
+                Some(key) => Some(conditional::page_etag(
+                    data_version(&req).await,
+                    key.locale(),
+                )),
+                None => None,
+            };
+            if etag.is_some() && preconditions.is_not_modified(etag.as_ref(), None) {
+                let mut res = req.into_response(conditional::not_modified(etag.as_ref(), None));
+                res.headers_mut()
+                    .insert(CacheControl::name(), cache_control_directive);
+                return Ok(res);
+            }
+
+            // each request gets its own nonce, which the cached pages are updated with
+            let nonce = req
+                .extensions()
+                .get::<CspNonce>()
+                .map(|nonce| nonce.0.clone());
+            let mut res = match (key, ttl) {
+                (Some(key), Some(ttl)) => match cache.get(&key) {
+                    Some(hit) => req.into_response(hit.to_response(nonce.as_deref())),
+                    None => {
+                        let fut = service.borrow_mut().call(req);
+                        let res = fut.await?;
+                        if res.status() == StatusCode::OK {
+                            let (res, response) = CachedResponse::collect(res, ttl, nonce).await?;
+                            cache.insert(key, response);
+                            res
+                        } else {
+                            into_boxed_body(res)
+                        }
+                    }
+                },
+                _ => {
+                    let fut = service.borrow_mut().call(req);
+                    into_boxed_body(fut.await?)
+                }
+            };
+
+            if res.status() == StatusCode::OK {
+                if let Some(value) = etag.and_then(|etag| IntoHeaderValue::try_into(etag).ok()) {
+                    res.headers_mut().insert(header::ETAG, value);
+                }
+                let (etag, last_modified) = conditional::validators(res.headers());
+                if preconditions.is_not_modified(etag.as_ref(), last_modified) {
+                    res =
+                        res.into_response(conditional::not_modified(etag.as_ref(), last_modified));
+                }
+            }
+
             res.headers_mut()
                 .insert(CacheControl::name(), cache_control_directive);
             Ok(res)
@@ -109,7 +210,47 @@ where
     }
 }
 
+/// The key of the request within the response cache, not defined for logged-in users,
+/// as they get pages personalized to them, which are thus never cached.
+async fn anonymous_cache_key(
+    req: ServiceRequest,
+) -> Result<(ServiceRequest, Option<CacheKey>), Error> {
+    let (http_req, payload) = req.into_parts();
+    let session = Session::extract(&http_req).await?;
+    let key = match session.user() {
+        Some(_) => None,
+        None => Some(CacheKey::new(
+            http_req.path(),
+            http_req.query_string(),
+            &session,
+        )),
+    };
+    let req = ServiceRequest::from_parts(http_req, payload)
+        .map_err(|_| ErrorInternalServerError("request shared while resolving its session"))?;
+    Ok((req, key))
+}
+
+/// The version of the data the pages are rendered from.
+async fn data_version(req: &ServiceRequest) -> ActionID {
+    match req.app_data::<web::Data<AppState>>() {
+        Some(state) => state.db.data_version().await,
+        None => 0,
+    }
+}
+
+pub(super) fn into_boxed_body<B: MessageBody + Unpin + 'static>(
+    res: ServiceResponse<B>,
+) -> ServiceResponse<Body> {
+    res.map_body(|_, body| ResponseBody::Other(Body::Message(Box::new(body))))
+}
+
 fn get_cache_control_directive_for_path(path: &str) -> CacheControl {
+    if feeds::is_feed_path(path) {
+        return CacheControl(vec![
+            CacheDirective::MaxAge(feeds::FEED_MAX_CACHE_AGE_SEC),
+            CacheDirective::Public,
+        ]);
+    }
     CacheControl(match path.split('/').nth(1) {
         None => vec![CacheDirective::NoCache],
         Some(root) => {
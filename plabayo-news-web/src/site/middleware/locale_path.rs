@@ -0,0 +1,125 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderValue, VARY};
+use actix_web::http::uri::{PathAndQuery, Uri};
+use actix_web::web::Bytes;
+use actix_web::{Error, HttpMessage};
+use futures::future::{ok, Ready};
+use futures::Future;
+
+use crate::site::assets;
+use crate::site::l18n::locales::Locale;
+
+/// The locale found as the first segment of the request path,
+/// e.g. `/nl/news`, stored in the request extensions
+/// such that it can be picked up by the Session extractor.
+#[derive(Clone, Copy)]
+pub struct PathLocale(pub Locale);
+
+// There are two steps in middleware processing.
+// 1. Middleware initialization, middleware factory gets called with
+//    next service in chain as parameter.
+// 2. Middleware's call method gets called with normal request.
+#[derive(Default)]
+pub struct LocalePath;
+
+// Middleware factory is `Transform` trait from actix-service crate
+// `S` - type of the next service
+// `B` - type of response's body
+impl<S, B> Transform<S> for LocalePath
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = LocalePathMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(LocalePathMiddleware { service })
+    }
+}
+
+pub struct LocalePathMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for LocalePathMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        let path_locale = strip_locale_prefix(&mut req);
+        // pages served without a locale prefix are negotiated using
+        // the Accept-Language header, and thus need to be cached per language
+        let vary_on_language =
+            path_locale.is_none() && req.path().split('/').nth(1) != Some(assets::ROOT);
+
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let mut res = fut.await?;
+            if vary_on_language {
+                res.headers_mut()
+                    .append(VARY, HeaderValue::from_static("accept-language"));
+            }
+            Ok(res)
+        })
+    }
+}
+
+/// Strip the locale prefix (e.g. `/nl`) from the request path in case there is one,
+/// such that the remainder of the request can be routed as if there was no prefix.
+fn strip_locale_prefix(req: &mut ServiceRequest) -> Option<Locale> {
+    let mut segments = req.path().splitn(3, '/').skip(1);
+    let locale = Locale::try_from(segments.next()?).ok()?;
+    let path = format!("/{}", segments.next().unwrap_or(""));
+
+    let mut parts = req.head().uri.clone().into_parts();
+    let path = match parts.path_and_query.as_ref().and_then(|pq| pq.query()) {
+        Some(query) => Bytes::from(format!("{}?{}", path, query)),
+        None => Bytes::from(path),
+    };
+    parts.path_and_query = Some(PathAndQuery::from_maybe_shared(path).ok()?);
+    let uri = Uri::from_parts(parts).ok()?;
+
+    req.match_info_mut().get_mut().update(&uri);
+    req.head_mut().uri = uri;
+    req.extensions_mut().insert(PathLocale(locale));
+
+    Some(locale)
+}
@@ -15,7 +15,13 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 mod cache;
+mod locale_path;
+mod rate_limit;
+mod security_headers;
 mod site_info;
 
 pub use cache::Cache;
+pub use locale_path::{LocalePath, PathLocale};
+pub use rate_limit::RateLimit;
+pub use security_headers::{CspNonce, SecurityHeaders};
 pub use site_info::SiteInfo;
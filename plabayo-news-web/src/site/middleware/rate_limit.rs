@@ -0,0 +1,246 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::cell::RefCell;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use actix_web::dev::{Body, MessageBody, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::ErrorInternalServerError;
+use actix_web::{Error, FromRequest};
+use futures::future::{ok, Ready};
+use futures::Future;
+
+use super::cache::into_boxed_body;
+use crate::site::extractors::Session;
+use crate::site::pages;
+use crate::site::rate_limit::{RateLimiter, RequestKind};
+
+// There are two steps in middleware processing.
+// 1. Middleware initialization, middleware factory gets called with
+//    next service in chain as parameter.
+// 2. Middleware's call method gets called with normal request.
+//
+// Requests of clients exceeding their budget are refused with a 429 (Too Many Requests),
+// telling them (using the Retry-After header) when they can try again.
+pub struct RateLimit {
+    limiter: Arc<RateLimiter>,
+    trusted_proxies: Rc<[IpAddr]>,
+}
+
+impl RateLimit {
+    /// Limit the clients using the given limiter, identifying them by the address
+    /// of their connection, or the one forwarded by any of the given trusted proxies.
+    pub fn new(limiter: Arc<RateLimiter>, trusted_proxies: &[IpAddr]) -> RateLimit {
+        RateLimit {
+            limiter,
+            trusted_proxies: Rc::from(trusted_proxies),
+        }
+    }
+}
+
+// Middleware factory is `Transform` trait from actix-service crate
+// `S` - type of the next service
+// `B` - type of response's body
+impl<S, B> Transform<S> for RateLimit
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + Unpin + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RateLimitMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            limiter: self.limiter.clone(),
+            trusted_proxies: self.trusted_proxies.clone(),
+        })
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    limiter: Arc<RateLimiter>,
+    trusted_proxies: Rc<[IpAddr]>,
+}
+
+impl<S, B> Service for RateLimitMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + Unpin + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let limiter = self.limiter.clone();
+        let ip = client_ip(&req, &self.trusted_proxies);
+
+        Box::pin(async move {
+            let kind = match RequestKind::of(req.method(), req.path()) {
+                Some(kind) => kind,
+                None => {
+                    let fut = service.borrow_mut().call(req);
+                    return Ok(into_boxed_body(fut.await?));
+                }
+            };
+
+            let (http_req, payload) = req.into_parts();
+            let session = Session::extract(&http_req).await?;
+            let user = session.user().map(|user| user.id);
+
+            if let Err(retry_after) = limiter.check(kind, ip, user, Instant::now()) {
+                log::info!(
+                    "rate limited {:?} request of {:?} (user {:?})",
+                    kind,
+                    ip,
+                    user
+                );
+                let res = pages::rate_limited_response(&http_req, &session, retry_after)?;
+                return Ok(ServiceResponse::new(http_req, res));
+            }
+
+            let req = ServiceRequest::from_parts(http_req, payload).map_err(|_| {
+                ErrorInternalServerError("request shared while resolving its session")
+            })?;
+            let fut = service.borrow_mut().call(req);
+            Ok(into_boxed_body(fut.await?))
+        })
+    }
+}
+
+/// The address of the client, being the address of the connection, unless that is
+/// one of the trusted proxies, in which case the `X-Forwarded-For` header is used.
+/// The header can contain addresses made up by the client, so the last address
+/// not belonging to a trusted proxy is used, being the one that connected to them.
+fn client_ip(req: &ServiceRequest, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+    let peer = req.peer_addr()?.ip();
+    if !trusted_proxies.contains(&peer) {
+        return Some(peer);
+    }
+    let forwarded = req
+        .headers()
+        .get_all("x-forwarded-for")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|addr| addr.trim().parse::<IpAddr>().ok())
+        .collect::<Vec<_>>();
+    for addr in forwarded.into_iter().rev() {
+        match addr {
+            Some(addr) if trusted_proxies.contains(&addr) => continue,
+            Some(addr) => return Some(addr),
+            // an address that can't be trusted, as a proxy would not forward it
+            None => break,
+        }
+    }
+    Some(peer)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+
+    use super::*;
+    use crate::site::rate_limit::{Limits, MemoryStore};
+
+    const PEER: &str = "203.0.113.7:4321";
+    const PROXY: &str = "10.0.0.1";
+
+    fn request(peer: &str, forwarded_for: &str) -> TestRequest {
+        TestRequest::get()
+            .uri("/news")
+            .peer_addr(peer.parse().unwrap())
+            .header("x-forwarded-for", forwarded_for)
+    }
+
+    #[test]
+    fn spoofed_forwarded_for_is_still_limited() {
+        actix_web::rt::System::new("test").block_on(async {
+            let limiter = Arc::new(RateLimiter::new(
+                Limits {
+                    page_views: "2/m".parse().unwrap(),
+                    ..Limits::default()
+                },
+                Box::new(MemoryStore::new(16)),
+            ));
+            let mut app = test::init_service(
+                App::new()
+                    .wrap(RateLimit::new(limiter, &[PROXY.parse().unwrap()]))
+                    .route("/news", web::get().to(HttpResponse::Ok)),
+            )
+            .await;
+            for (n, forwarded_for) in ["198.51.100.1", "198.51.100.2", "198.51.100.3"]
+                .iter()
+                .enumerate()
+            {
+                let res =
+                    test::call_service(&mut app, request(PEER, forwarded_for).to_request()).await;
+                let expected = if n < 2 {
+                    StatusCode::OK
+                } else {
+                    StatusCode::TOO_MANY_REQUESTS
+                };
+                assert_eq!(res.status(), expected);
+            }
+        })
+    }
+
+    #[test]
+    fn client_is_forwarded_by_trusted_proxies_only() {
+        let proxies = [PROXY.parse().unwrap()];
+        let ip = |req: TestRequest| client_ip(&req.to_srv_request(), &proxies);
+
+        assert_eq!(
+            ip(request(PEER, "198.51.100.1")),
+            Some("203.0.113.7".parse().unwrap())
+        );
+        let proxied = format!("{}:80", PROXY);
+        assert_eq!(
+            ip(request(&proxied, "198.51.100.1")),
+            Some("198.51.100.1".parse().unwrap())
+        );
+        // addresses prepended by the client are ignored
+        assert_eq!(
+            ip(request(&proxied, "192.0.2.66, 198.51.100.1")),
+            Some("198.51.100.1".parse().unwrap())
+        );
+        assert_eq!(
+            ip(request(&proxied, "garbage")),
+            Some(PROXY.parse().unwrap())
+        );
+    }
+}
@@ -0,0 +1,198 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt::Write;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{
+    HeaderName, HeaderValue, CONTENT_SECURITY_POLICY, REFERRER_POLICY, STRICT_TRANSPORT_SECURITY,
+    X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+};
+use actix_web::http::StatusCode;
+use actix_web::{Error, HttpMessage};
+use futures::future::{ok, Ready};
+use futures::Future;
+use rand::RngCore;
+
+/// The nonce generated for the request, stored in the request extensions
+/// such that it can be picked up by the Session extractor,
+/// and used by templates to allow their inline scripts and styles.
+#[derive(Clone)]
+pub struct CspNonce(pub String);
+
+impl CspNonce {
+    fn generate() -> CspNonce {
+        let mut bytes = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        CspNonce(bytes.iter().fold(String::with_capacity(32), |mut s, b| {
+            let _ = write!(s, "{:02x}", b);
+            s
+        }))
+    }
+}
+
+const STRICT_TRANSPORT_SECURITY_VALUE: &str = "max-age=31536000; includeSubDomains";
+const X_CONTENT_TYPE_OPTIONS_VALUE: &str = "nosniff";
+const REFERRER_POLICY_VALUE: &str = "origin";
+const PERMISSIONS_POLICY_VALUE: &str =
+    "camera=(), microphone=(), geolocation=(), payment=(), interest-cohort=()";
+const X_FRAME_OPTIONS_VALUE: &str = "DENY";
+
+// There are two steps in middleware processing.
+// 1. Middleware initialization, middleware factory gets called with
+//    next service in chain as parameter.
+// 2. Middleware's call method gets called with normal request.
+//
+// Hardens all responses using security headers, of which the Content-Security-Policy
+// only allows inline scripts and styles carrying the nonce generated for the request.
+#[derive(Default)]
+pub struct SecurityHeaders {
+    report_uri: Option<Rc<str>>,
+}
+
+impl SecurityHeaders {
+    /// Report Content-Security-Policy violations to the given URI, if any.
+    pub fn new(report_uri: Option<&str>) -> SecurityHeaders {
+        SecurityHeaders {
+            report_uri: report_uri.map(Rc::from),
+        }
+    }
+}
+
+// Middleware factory is `Transform` trait from actix-service crate
+// `S` - type of the next service
+// `B` - type of response's body
+impl<S, B> Transform<S> for SecurityHeaders
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SecurityHeadersMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(SecurityHeadersMiddleware {
+            service,
+            report_uri: self.report_uri.clone(),
+        })
+    }
+}
+
+pub struct SecurityHeadersMiddleware<S> {
+    service: S,
+    report_uri: Option<Rc<str>>,
+}
+
+impl<S, B> Service for SecurityHeadersMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let nonce = CspNonce::generate();
+        let policy = content_security_policy(&nonce.0, self.report_uri.as_deref());
+        req.extensions_mut().insert(nonce);
+
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let mut res = fut.await?;
+            // a revalidated page keeps the policy (and thus nonce) it was served with,
+            // as browsers update the headers of their copy with those of the 304 response
+            let revalidated = res.status() == StatusCode::NOT_MODIFIED;
+            let headers = res.headers_mut();
+            if let Ok(policy) = HeaderValue::from_str(&policy) {
+                if !revalidated {
+                    headers.insert(CONTENT_SECURITY_POLICY, policy);
+                }
+            }
+            headers.insert(
+                STRICT_TRANSPORT_SECURITY,
+                HeaderValue::from_static(STRICT_TRANSPORT_SECURITY_VALUE),
+            );
+            headers.insert(
+                X_CONTENT_TYPE_OPTIONS,
+                HeaderValue::from_static(X_CONTENT_TYPE_OPTIONS_VALUE),
+            );
+            headers.insert(
+                REFERRER_POLICY,
+                HeaderValue::from_static(REFERRER_POLICY_VALUE),
+            );
+            headers.insert(
+                HeaderName::from_static("permissions-policy"),
+                HeaderValue::from_static(PERMISSIONS_POLICY_VALUE),
+            );
+            headers.insert(
+                X_FRAME_OPTIONS,
+                HeaderValue::from_static(X_FRAME_OPTIONS_VALUE),
+            );
+            Ok(res)
+        })
+    }
+}
+
+/// The policy only allowing resources of the site itself,
+/// and inline scripts and styles carrying the given nonce.
+fn content_security_policy(nonce: &str, report_uri: Option<&str>) -> String {
+    let mut policy = format!(
+        "default-src 'self'; script-src 'self' 'nonce-{nonce}'; style-src 'self' 'nonce-{nonce}'; \
+         img-src 'self' data:; object-src 'none'; base-uri 'self'; form-action 'self'; \
+         frame-ancestors 'none'",
+        nonce = nonce
+    );
+    if let Some(uri) = report_uri {
+        let _ = write!(policy, "; report-uri {}", uri);
+    }
+    policy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy_contains_nonce_and_report_uri() {
+        let nonce = CspNonce::generate();
+        assert_eq!(nonce.0.len(), 32);
+        assert_ne!(nonce.0, CspNonce::generate().0);
+
+        let policy = content_security_policy(&nonce.0, None);
+        assert!(policy.contains(&format!("script-src 'self' 'nonce-{}'", nonce.0)));
+        assert!(!policy.contains("report-uri"));
+
+        let policy = content_security_policy(&nonce.0, Some("/csp-report"));
+        assert!(policy.ends_with("; report-uri /csp-report"));
+    }
+}
@@ -20,10 +20,19 @@ use fnv::FnvHasher;
 use lazy_static::lazy_static;
 
 pub mod assets;
+pub mod cache;
+pub mod conditional;
+pub mod content;
+pub mod csp;
+pub mod csrf;
+pub mod digest;
 pub mod extractors;
+pub mod feeds;
 pub mod l18n;
 pub mod middleware;
 pub mod pages;
+pub mod rate_limit;
+pub mod spam;
 pub mod state;
 
 lazy_static! {
@@ -59,4 +68,10 @@ impl SiteInfo {
             repository: "https://github.com/plabayo/news",
         }
     }
+
+    /// The fingerprinted URL of the asset with the given name,
+    /// e.g. `/assets/style.<hash>.css` for `style.css`.
+    pub fn asset_url(&self, name: &str) -> String {
+        assets::url(name)
+    }
 }
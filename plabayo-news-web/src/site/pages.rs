@@ -14,19 +14,36 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use actix_web::dev::HttpServiceFactory;
-use actix_web::{web, HttpResponse, Result};
-use chrono::{DateTime, Utc};
+use actix_web::http::header::{self, HttpDate, IntoHeaderValue, LOCATION, RETRY_AFTER};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
 
-use plabayo_news_data::models::User;
+use plabayo_news_data::models::{
+    self as data, ApiKeyLimits, ApiKeyOwner, DigestFrequency, ItemContent, ItemKind, ItemRelation,
+    SpamReason, SpamVerdict, User, UserKind, UserPreferences, UserState,
+};
+use plabayo_news_data::sites;
 
-use crate::site::extractors::Session;
+use crate::site::content;
+use crate::site::extractors::{self, CsrfFailure, CsrfForm, Session};
 use crate::site::l18n::locales::Locale;
-use crate::site::l18n::pages::models::{ContentItem, ContentItems, ContentSearch, Item};
-use crate::site::l18n::pages::{static_response, PageItem, PageItems, PageSearch};
+use crate::site::l18n::pages::models::{
+    ApiAccessRequest, ApiKey, Comment, ContentAdminApi, ContentApi, ContentComments, ContentDelete,
+    ContentEdit, ContentFavorites, ContentForbidden, ContentItem, ContentItems, ContentNewest,
+    ContentPast, ContentRateLimited, ContentSearch, ContentThreads, Item, Revision,
+};
+use crate::site::l18n::pages::{
+    static_response, PageAdminApi, PageApi, PageComments, PageDelete, PageEdit, PageFavorites,
+    PageForbidden, PageItem, PageItems, PageNewest, PagePast, PageRateLimited, PageSearch,
+    PageThreads,
+};
+use crate::site::spam::Submission;
 use crate::site::state::AppState;
 
 //---------------------------------------
@@ -34,7 +51,9 @@ use crate::site::state::AppState;
 //---------------------------------------
 
 pub fn factory() -> impl HttpServiceFactory + 'static {
-    web::resource("/{resource:.*}").route(web::get().to(serve_page))
+    web::resource("/{resource:.*}")
+        .route(web::get().to(serve_page))
+        .route(web::post().to(serve_form))
 }
 
 //---------------------------------------
@@ -43,10 +62,23 @@ pub fn factory() -> impl HttpServiceFactory + 'static {
 
 pub struct PageState {
     pub locale: Locale,
+    pub path_locale: Option<Locale>,
     pub path: String,
     pub query: BTreeMap<String, String>,
     pub gen_date_time: DateTime<Utc>,
     pub user: Option<User>,
+    /// The time within which authors can still edit and delete their own items.
+    pub edit_window: std::time::Duration,
+    /// The relations (hidden, favorite, followed) the user has with items.
+    pub relations: data::ItemRelations,
+    /// Amount of replies to the items of the user made since they last read them.
+    pub unread_replies: usize,
+    pub csrf_token: Option<String>,
+    /// The public origin of the site, used for the absolute links of the page.
+    pub origin: String,
+    /// The nonce to add to inline scripts and styles, such that the
+    /// Content-Security-Policy allows them, empty if there is no such policy.
+    pub csp_nonce: String,
 }
 
 // TODO: clean up this mess, so we can use cleanly in html templates,
@@ -55,18 +87,108 @@ pub struct PageState {
 //  and overwrite/add the (new) ones given.
 
 impl PageState {
-    pub fn new(
-        locale: Locale,
-        path: String,
-        query: BTreeMap<String, String>,
-        user: Option<User>,
-    ) -> PageState {
+    pub fn new(session: &Session, path: String, query: BTreeMap<String, String>) -> PageState {
         PageState {
-            locale,
+            locale: session.locale(),
+            path_locale: session.path_locale(),
             path,
             query,
             gen_date_time: chrono::offset::Utc::now(),
-            user,
+            user: session.user(),
+            edit_window: session.edit_window(),
+            relations: session.item_relations().clone(),
+            unread_replies: session.unread_replies(),
+            csrf_token: session.csrf_token().map(str::to_owned),
+            origin: session.origin().to_owned(),
+            csp_nonce: session.csp_nonce().unwrap_or_default().to_owned(),
+        }
+    }
+
+    /// The token to embed in all forms, empty in case there is no user.
+    pub fn csrf(&self) -> &str {
+        self.csrf_token.as_deref().unwrap_or_default()
+    }
+
+    /// The link of the current page, used to return to it after submitting a form.
+    pub fn current_href(&self) -> String {
+        format!(
+            "{}{}{}",
+            self.root(),
+            self.path,
+            self.page_query_for(self.path.as_str(), "")
+        )
+    }
+
+    /// The ID of the current user, to fetch the items as seen by them.
+    pub fn viewer(&self) -> Option<data::UserID> {
+        self.user.as_ref().map(|user| user.id)
+    }
+
+    /// Returns true if the current user is allowed to edit and delete the given item.
+    pub fn can_edit(&self, item: &data::Item) -> bool {
+        match &self.user {
+            Some(user) => item.is_editable_by(user, self.edit_window, SystemTime::now()),
+            None => false,
+        }
+    }
+
+    /// The link to the page performing the given action (e.g. `edit`) on an item.
+    pub fn item_action_href(&self, action: &str, id: &data::ItemID) -> String {
+        // TODO: delete the user id once we have a reg/login system
+        match &self.user {
+            Some(user) => format!("{}/{}?item={}&id={}", self.root(), action, id, user.id),
+            None => format!("{}/{}?item={}", self.root(), action, id),
+        }
+    }
+
+    /// The root to prefix all site links with, such that a locale
+    /// selected using the path (e.g. `/nl`) is preserved while navigating.
+    pub fn root(&self) -> String {
+        match self.path_locale {
+            Some(locale) => format!("/{}", locale),
+            None => String::new(),
+        }
+    }
+
+    /// All (hreflang, absolute href) pairs of the current page,
+    /// one for each supported locale and a final one for the `x-default` variant.
+    pub fn locale_alternates(&self) -> Vec<(String, String)> {
+        let query = self.page_query_for(self.path.as_str(), "");
+        let path = if self.path == "/" {
+            ""
+        } else {
+            self.path.as_str()
+        };
+        Locale::all()
+            .map(|locale| {
+                (
+                    locale.to_string(),
+                    format!("{}/{}{}{}", self.origin, locale, path, query),
+                )
+            })
+            .chain(std::iter::once((
+                "x-default".to_owned(),
+                format!("{}{}{}", self.origin, self.path, query),
+            )))
+            .collect()
+    }
+
+    /// The link of the feed (`rss` or `atom`) matching the current page,
+    /// falling back to the feed of the front page.
+    pub fn feed_href(&self, format: &str) -> String {
+        let (list, param) = match self.path.as_str() {
+            "/newest" => ("/newest", None),
+            "/ask" => ("/ask", None),
+            "/user" => ("/user", Some("id")),
+            "/from" => ("/from", Some("site")),
+            _ => ("", None),
+        };
+        match param {
+            None => format!("{}{}/{}", self.root(), list, format),
+            Some(key) => match self.query.get(key) {
+                Some(value) => format!("{}{}/{}?{}={}", self.root(), list, format, key, value),
+                None => format!("{}/{}", self.root(), format),
+            },
         }
     }
 
@@ -128,8 +250,49 @@ async fn serve_page(
     match path.as_str() {
         "" | "index" => serve_news_ranked("/", query, app_state, session).await,
         "news" => serve_news_ranked("/news", query, app_state, session).await,
+        "from" => serve_news_from_site("/from", query, app_state, session).await,
+        "newest" => serve_news_newest("/newest", query, app_state, session).await,
+        "past" => serve_news_past("/past", query, app_state, session).await,
+        "ask" => serve_ask_ranked("/ask", query, app_state, session).await,
+        "comments" => serve_comments_newest("/comments", query, app_state, session).await,
+        "favorites" => serve_favorites("/favorites", query, app_state, session).await,
+        "threads" => serve_threads("/threads", query, app_state, session, None).await,
         "search" => serve_search("/search", query, session).await,
-        "item" => serve_item("/item", query, session).await,
+        "item" => serve_item("/item", query, app_state, session).await,
+        "edit" => serve_edit("/edit", query, app_state, session, None).await,
+        "delete" => serve_delete("/delete", query, app_state, session).await,
+        "api" => serve_api("/api", query, session, ContentApi::default()),
+        "admin/api" => serve_admin_api("/admin/api", query, app_state, session, None).await,
+        _ => serve_static(path.as_str(), query, session),
+    }
+}
+
+async fn serve_form(
+    path: web::Path<(String,)>,
+    query: web::Query<BTreeMap<String, String>>,
+    form: CsrfForm,
+    session: Session,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let path = path.into_inner().0.to_lowercase();
+    let query = query.into_inner();
+    let form = form.into_inner();
+    let app_state = app_state.into_inner();
+
+    match path.as_str() {
+        "hide" => serve_item_relation(ItemRelation::Hidden, query, form, app_state, session).await,
+        "favorite" => {
+            serve_item_relation(ItemRelation::Favorite, query, form, app_state, session).await
+        }
+        "follow" => {
+            serve_item_relation(ItemRelation::Followed, query, form, app_state, session).await
+        }
+        "favorites" => serve_favorites_request("/favorites", query, form, app_state, session).await,
+        "threads" => serve_threads_request("/threads", query, form, app_state, session).await,
+        "edit" => serve_edit_request("/edit", query, form, app_state, session).await,
+        "delete" => serve_delete_request("/delete", query, form, app_state, session).await,
+        "api" => serve_api_request("/api", query, form, app_state, session).await,
+        "admin/api" => serve_admin_api_action("/admin/api", query, form, app_state, session).await,
         _ => serve_static(path.as_str(), query, session),
     }
 }
@@ -140,38 +303,405 @@ async fn serve_news_ranked(
     app_state: Arc<AppState>,
     session: Session,
 ) -> Result<HttpResponse> {
-    let locale = session.locale();
-    let user = session.user();
+    let page_state = PageState::new(&session, path.to_string(), query);
 
     let content = ContentItems {
-        items: app_state
-            .db
-            .get_news_ranked()
-            .await
-            .into_iter()
-            .map(Item::from_data)
-            .collect(),
+        items: listing(
+            app_state.db.get_news_ranked(page_state.viewer()).await,
+            &page_state,
+        ),
     };
 
-    let page_state = PageState::new(locale, path.to_string(), query, user);
+    PageItems::new_response(page_state, content)
+}
+
+async fn serve_news_from_site(
+    path: &str,
+    query: BTreeMap<String, String>,
+    app_state: Arc<AppState>,
+    session: Session,
+) -> Result<HttpResponse> {
+    let site = query.get("site").cloned().unwrap_or_default();
+    let page_state = PageState::new(&session, path.to_string(), query);
+
+    let content = ContentItems {
+        items: listing(
+            app_state
+                .db
+                .get_news_from_site(&site, page_state.viewer())
+                .await,
+            &page_state,
+        ),
+    };
 
     PageItems::new_response(page_state, content)
 }
 
-async fn serve_search(
+/// Amount of items shown on a single page of a paginated listing.
+const ITEMS_PER_PAGE: usize = 30;
+
+async fn serve_news_newest(
+    path: &str,
+    query: BTreeMap<String, String>,
+    app_state: Arc<AppState>,
+    session: Session,
+) -> Result<HttpResponse> {
+    let page = query
+        .get("p")
+        .and_then(|p| p.parse::<usize>().ok())
+        .filter(|p| *p > 0)
+        .unwrap_or(1);
+    let show_hidden = matches!(query.get("hidden").map(|s| s.as_str()), Some("1" | "true"));
+
+    let page_state = PageState::new(&session, path.to_string(), query);
+
+    // fetch one extra item to know whether there is a next page
+    let mut items = app_state
+        .db
+        .get_news_newest_page(
+            (page - 1) * ITEMS_PER_PAGE,
+            ITEMS_PER_PAGE + 1,
+            show_hidden,
+            page_state.viewer(),
+        )
+        .await;
+    let next_page = if items.len() > ITEMS_PER_PAGE {
+        items.truncate(ITEMS_PER_PAGE);
+        Some(page + 1)
+    } else {
+        None
+    };
+
+    let content = ContentNewest {
+        items: if show_hidden {
+            // also show the items hidden by the user, such that they can be unhidden
+            items
+                .into_iter()
+                .map(|item| Item::from_data(item, &page_state))
+                .collect()
+        } else {
+            listing(items, &page_state)
+        },
+        show_hidden,
+        next_page,
+    };
+
+    PageNewest::new_response(page_state, content)
+}
+
+async fn serve_news_past(
+    path: &str,
+    query: BTreeMap<String, String>,
+    app_state: Arc<AppState>,
+    session: Session,
+) -> Result<HttpResponse> {
+    let today = Utc::now().naive_utc().date();
+    // the most recent day that is complete is shown by default,
+    // future days are not allowed
+    let day = query
+        .get("day")
+        .and_then(|day| NaiveDate::parse_from_str(day, "%Y-%m-%d").ok())
+        .filter(|day| day <= &today)
+        .unwrap_or_else(|| today - Duration::days(1));
+
+    let start = match day.and_hms_opt(0, 0, 0) {
+        Some(midnight) => Utc.from_utc_datetime(&midnight),
+        None => return serve_static(path.trim_start_matches('/'), query, session),
+    };
+    let end = start + Duration::days(1);
+
+    let page_state = PageState::new(&session, path.to_string(), query);
+
+    let day_str = day.format("%Y-%m-%d").to_string();
+    let content = ContentPast {
+        title: page_state.locale.strings().page.past.title(&day_str),
+        items: listing(
+            app_state
+                .db
+                .get_news_ranked_between(start.into(), end.into(), page_state.viewer())
+                .await,
+            &page_state,
+        ),
+        prev_day: (day - Duration::days(1)).format("%Y-%m-%d").to_string(),
+        next_day: if day < today {
+            Some((day + Duration::days(1)).format("%Y-%m-%d").to_string())
+        } else {
+            None
+        },
+    };
+
+    PagePast::new_response(page_state, content)
+}
+
+async fn serve_ask_ranked(
+    path: &str,
+    query: BTreeMap<String, String>,
+    app_state: Arc<AppState>,
+    session: Session,
+) -> Result<HttpResponse> {
+    let page_state = PageState::new(&session, path.to_string(), query);
+
+    let content = ContentItems {
+        items: listing(
+            app_state.db.get_ask_ranked(page_state.viewer()).await,
+            &page_state,
+        ),
+    };
+
+    PageItems::new_response(page_state, content)
+}
+
+async fn serve_comments_newest(
     path: &str,
     query: BTreeMap<String, String>,
+    app_state: Arc<AppState>,
+    session: Session,
+) -> Result<HttpResponse> {
+    let page_state = PageState::new(&session, path.to_string(), query);
+
+    let mut comments = Vec::new();
+    for comment in app_state.db.get_comments_newest(page_state.viewer()).await {
+        let story = get_story_of_comment(&app_state, &comment, &page_state).await;
+        comments.push(Comment::from_data(comment, story, &page_state));
+    }
+
+    PageComments::new_response(page_state, ContentComments { comments })
+}
+
+/// The view of the items of a listing, leaving out those hidden by the user.
+fn listing(items: Vec<data::Item>, page_state: &PageState) -> Vec<Item> {
+    items
+        .into_iter()
+        .filter(|item| !page_state.relations.hidden.contains(&item.id))
+        .map(|item| Item::from_data(item, page_state))
+        .collect()
+}
+
+/// The replies to the items of the user, marking them as read,
+/// as well as the settings of the email digest of those replies.
+async fn serve_threads(
+    path: &str,
+    mut query: BTreeMap<String, String>,
+    app_state: Arc<AppState>,
     session: Session,
+    form: Option<ContentThreads>,
 ) -> Result<HttpResponse> {
+    let user = match session.user() {
+        Some(user) => user,
+        None => return serve_static(path.trim_start_matches('/'), query, session),
+    };
+
+    // the link sent to the user to confirm their email address
+    let confirmed = match query.remove("confirm") {
+        Some(token) => Some(
+            app_state
+                .db
+                .confirm_email(user.id, &token, SystemTime::now())
+                .await,
+        ),
+        None => None,
+    };
+    let preferences = app_state
+        .db
+        .get_user(user.id)
+        .await
+        .and_then(|user| user.preferences)
+        .unwrap_or_default();
+
+    let mut page_state = PageState::new(&session, path.to_string(), query);
+
+    let seen_time = app_state.db.get_replies_seen_time(user.id).await;
+    let mut comments = Vec::new();
+    for reply in app_state.db.get_replies(user.id).await {
+        let unread = !matches!(seen_time, Some(seen_time) if reply.time <= seen_time);
+        let story = get_story_of_comment(&app_state, &reply, &page_state).await;
+        comments.push(Comment {
+            unread,
+            ..Comment::from_data(reply, story, &page_state)
+        });
+    }
+    app_state.db.mark_replies_seen(user.id).await;
+    page_state.unread_replies = 0;
+
+    let title = page_state
+        .locale
+        .strings()
+        .page
+        .threads
+        .title(&user.public_username());
+    let strings = &page_state.locale.strings().page.threads.digest;
+    let mut content = match form {
+        Some(form) => ContentThreads {
+            title,
+            comments,
+            ..form
+        },
+        None => ContentThreads {
+            title,
+            comments,
+            email: preferences.email.clone().unwrap_or_default(),
+            digest: digest_frequency_name(preferences.digest).to_owned(),
+            email_verified: false,
+            confirmation_sent: false,
+            confirmed: confirmed == Some(true),
+            error: match confirmed {
+                Some(false) => Some(strings.invalid_confirmation.to_owned()),
+                _ => None,
+            },
+            saved: false,
+        },
+    };
+    content.email_verified =
+        preferences.email_verified && preferences.email.as_ref() == Some(&content.email);
+
+    PageThreads::new_response(page_state, content)
+}
+
+async fn serve_threads_request(
+    path: &str,
+    query: BTreeMap<String, String>,
+    form: BTreeMap<String, String>,
+    app_state: Arc<AppState>,
+    session: Session,
+) -> Result<HttpResponse> {
+    let user = match session.user() {
+        Some(user) => user,
+        None => return serve_static(path.trim_start_matches('/'), query, session),
+    };
+
     let locale = session.locale();
-    let user = session.user();
+    let strings = &locale.strings().page.threads.digest;
+    let email = form
+        .get("email")
+        .map(|s| s.trim())
+        .unwrap_or("")
+        .to_string();
+    let digest = parse_digest_frequency(form.get("digest").map(|s| s.as_str()).unwrap_or(""));
+    let mut content = ContentThreads {
+        title: String::new(),
+        comments: Vec::new(),
+        email,
+        digest: digest_frequency_name(digest).to_owned(),
+        email_verified: false,
+        confirmation_sent: false,
+        confirmed: false,
+        error: None,
+        saved: false,
+    };
+
+    if !content.email.is_empty() && !is_valid_email(&content.email) {
+        content.error = Some(strings.invalid_email.to_owned());
+    } else if content.email.is_empty() && digest != DigestFrequency::Never {
+        content.error = Some(strings.missing_email.to_owned());
+    } else {
+        let preferences = UserPreferences {
+            email: Some(content.email.clone()).filter(|email| !email.is_empty()),
+            digest,
+            ..user.preferences.clone().unwrap_or_default()
+        };
+        content.saved = app_state
+            .db
+            .set_user_preferences(user.id, preferences)
+            .await;
+
+        // digests are only sent once the user confirmed to own the address
+        let verified = app_state
+            .db
+            .get_user(user.id)
+            .await
+            .and_then(|user| user.preferences)
+            .is_some_and(|preferences| preferences.email_verified);
+        if let (true, false, Some(mailer)) = (content.saved, verified, &app_state.mailer) {
+            if !content.email.is_empty() {
+                let token = app_state
+                    .db
+                    .create_email_confirmation(user.id, &content.email)
+                    .await;
+                match mailer
+                    .send_confirmation(&user, &content.email, &token)
+                    .await
+                {
+                    Ok(()) => content.confirmation_sent = true,
+                    Err(err) => {
+                        log::error!("{}", err);
+                        content.error = Some(strings.confirmation_failed.to_owned());
+                    }
+                }
+            }
+        }
+    }
+
+    serve_threads(path, query, app_state, session, Some(content)).await
+}
+
+fn digest_frequency_name(frequency: DigestFrequency) -> &'static str {
+    match frequency {
+        DigestFrequency::Never => "never",
+        DigestFrequency::Daily => "daily",
+        DigestFrequency::Weekly => "weekly",
+    }
+}
+
+fn parse_digest_frequency(name: &str) -> DigestFrequency {
+    match name {
+        "daily" => DigestFrequency::Daily,
+        "weekly" => DigestFrequency::Weekly,
+        _ => DigestFrequency::Never,
+    }
+}
+
+/// A permissive check of an email address, only meant to catch obvious mistakes,
+/// while guaranteeing it can be used safely as an email header.
+fn is_valid_email(email: &str) -> bool {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            email.len() <= 254
+                && !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !domain.contains('@')
+                && !email
+                    .chars()
+                    .any(|c| c.is_whitespace() || c.is_control() || "<>,;\"".contains(c))
+        }
+        None => false,
+    }
+}
+
+/// The story (or question) a comment was made on,
+/// found by walking up the thread of parents.
+async fn get_story_of_comment(
+    app_state: &AppState,
+    comment: &data::Item,
+    page_state: &PageState,
+) -> Option<data::Item> {
+    // walk up the thread, guarding against cycles
+    let mut visited = HashSet::new();
+    let mut parent = comment.parent;
+    while let Some(id) = parent {
+        if !visited.insert(id) {
+            return None;
+        }
+        let item = app_state.db.get_item(id, page_state.viewer()).await?;
+        if !matches!(item.kind, data::ItemKind::Comment) {
+            return Some(item);
+        }
+        parent = item.parent;
+    }
+    None
+}
 
-    // TODO: sanitize?!
+async fn serve_search(
+    path: &str,
+    query: BTreeMap<String, String>,
+    session: Session,
+) -> Result<HttpResponse> {
     let q = query.get("q").map(|s| s.as_str()).unwrap_or("").to_string();
 
     let content = ContentSearch { q };
 
-    let page_state = PageState::new(locale, path.to_string(), query, user);
+    let page_state = PageState::new(&session, path.to_string(), query);
 
     PageSearch::new_response(page_state, content)
 }
@@ -179,30 +709,559 @@ async fn serve_search(
 async fn serve_item(
     path: &str,
     query: BTreeMap<String, String>,
+    app_state: Arc<AppState>,
     session: Session,
 ) -> Result<HttpResponse> {
-    let locale = session.locale();
-    let user = session.user();
-
-    // TODO: sanitize?!
     let q = query.get("q").map(|s| s.as_str()).unwrap_or("").to_string();
 
+    // personalized pages can change without the item being modified,
+    // so only anonymous visitors get to revalidate by modification time
+    let last_modified = match (
+        session.user(),
+        query.get("id").and_then(|id| id.parse().ok()),
+    ) {
+        (None, Some(id)) => app_state
+            .db
+            .get_item(id, None)
+            .await
+            .map(|item| item.mod_time),
+        _ => None,
+    };
+
     let content = ContentItem { q };
 
-    let page_state = PageState::new(locale, path.to_string(), query, user);
+    let page_state = PageState::new(&session, path.to_string(), query);
 
-    PageItem::new_response(page_state, content)
+    let mut response = PageItem::new_response(page_state, content)?;
+    if let Some(value) =
+        last_modified.and_then(|time| IntoHeaderValue::try_into(HttpDate::from(time)).ok())
+    {
+        response.headers_mut().insert(header::LAST_MODIFIED, value);
+    }
+    Ok(response)
 }
 
-fn serve_static(
-    endpoint: &str,
+async fn serve_favorites(
+    path: &str,
     query: BTreeMap<String, String>,
+    app_state: Arc<AppState>,
     session: Session,
 ) -> Result<HttpResponse> {
+    let owner = match query.get("id").and_then(|id| id.parse().ok()) {
+        Some(id) => app_state.db.get_user(id).await,
+        None => None,
+    };
+    let own = matches!((&owner, session.user()), (Some(owner), Some(user)) if owner.id == user.id);
+    let (owner, public) = match owner {
+        Some(owner) => {
+            let public = app_state
+                .db
+                .get_item_relations(owner.id)
+                .await
+                .public_favorites;
+            (owner, public)
+        }
+        None => return serve_static(path.trim_start_matches('/'), query, session),
+    };
+    // the favorites of a user are private unless made public by that user
+    let visible = own || (public && owner.state == UserState::Public);
+    if !visible {
+        return serve_static(path.trim_start_matches('/'), query, session);
+    }
+
+    let page_state = PageState::new(&session, path.to_string(), query);
+
+    let content = ContentFavorites {
+        title: page_state
+            .locale
+            .strings()
+            .page
+            .favorites
+            .title(&owner.public_username()),
+        items: listing(
+            app_state
+                .db
+                .get_favorites(owner.id, page_state.viewer())
+                .await,
+            &page_state,
+        ),
+        own,
+        public,
+    };
+
+    PageFavorites::new_response(page_state, content)
+}
+
+async fn serve_favorites_request(
+    path: &str,
+    query: BTreeMap<String, String>,
+    form: BTreeMap<String, String>,
+    app_state: Arc<AppState>,
+    session: Session,
+) -> Result<HttpResponse> {
+    let user = match session.user() {
+        Some(user) => user,
+        None => return serve_static(path.trim_start_matches('/'), query, session),
+    };
+
+    let public = form.get("public").map(|s| s.as_str()) == Some("1");
+    app_state.db.set_public_favorites(user.id, public).await;
+
+    let page_state = PageState::new(&session, path.to_string(), query);
+    redirect_to(&page_state.current_href())
+}
+
+/// Add (or with `undo` remove) a relation between the user and the item
+/// referred to by the `item` query parameter, returning to the page
+/// the form was submitted from (`goto`) afterwards.
+/// Submitting a followed thread with `seen` marks its replies as seen instead.
+async fn serve_item_relation(
+    relation: ItemRelation,
+    query: BTreeMap<String, String>,
+    form: BTreeMap<String, String>,
+    app_state: Arc<AppState>,
+    session: Session,
+) -> Result<HttpResponse> {
+    let (user, id) = match (
+        session.user(),
+        query.get("item").and_then(|id| id.parse().ok()),
+    ) {
+        (Some(user), Some(id)) => (user, id),
+        _ => return serve_static("", query, session),
+    };
+
+    let flag = |name: &str| form.get(name).map(|s| s.as_str()) == Some("1");
+    if relation == ItemRelation::Followed && flag("seen") {
+        app_state.db.mark_thread_seen(user.id, id).await;
+    } else if !app_state
+        .db
+        .set_item_relation(user.id, id, relation, !flag("undo"))
+        .await
+    {
+        return serve_static("", query, session);
+    }
+
+    let goto = match form.get("goto").filter(|goto| is_local_href(goto)) {
+        Some(goto) => goto.clone(),
+        None => format!("{}/", PageState::new(&session, String::new(), query).root()),
+    };
+    redirect_to(&goto)
+}
+
+/// Returns true if the href refers to a page of this site,
+/// preventing forms from redirecting to any other site.
+fn is_local_href(href: &str) -> bool {
+    href.starts_with('/') && !href.starts_with("//") && !href.contains('\\')
+}
+
+/// The item referred to by the `item` query parameter,
+/// only if it exists and the current user is allowed to edit it.
+async fn get_editable_item(
+    query: &BTreeMap<String, String>,
+    app_state: &AppState,
+    session: &Session,
+) -> Option<data::Item> {
+    let id = query.get("item")?.parse().ok()?;
+    let user = session.user()?;
+    let item = app_state.db.get_item(id, Some(user.id)).await?;
+    if item.is_editable_by(&user, session.edit_window(), SystemTime::now()) {
+        Some(item)
+    } else {
+        None
+    }
+}
+
+/// Redirect (see other) to the newest items, the page where
+/// the result of an edit or deletion is the easiest to find back.
+fn redirect_to_newest(page_state: &PageState) -> Result<HttpResponse> {
+    let location = format!(
+        "{}/newest{}",
+        page_state.root(),
+        page_state.page_query_for(page_state.path.as_str(), "item")
+    );
+    redirect_to(&location)
+}
+
+/// Redirect (see other) to the given location, as the result of a submitted form.
+fn redirect_to(location: &str) -> Result<HttpResponse> {
+    Ok(HttpResponse::SeeOther().header(LOCATION, location).finish())
+}
+
+async fn serve_edit(
+    path: &str,
+    query: BTreeMap<String, String>,
+    app_state: Arc<AppState>,
+    session: Session,
+    form: Option<ContentEdit>,
+) -> Result<HttpResponse> {
+    let item = match get_editable_item(&query, &app_state, &session).await {
+        Some(item) => item,
+        None => return serve_static(path.trim_start_matches('/'), query, session),
+    };
+
+    let page_state = PageState::new(&session, path.to_string(), query);
+
+    let revisions = match &page_state.user {
+        Some(user) if matches!(user.kind, UserKind::Moderator | UserKind::Admin) => Some(
+            app_state
+                .db
+                .get_item_revisions(item.id)
+                .await
+                .into_iter()
+                .map(|revision| Revision::from_data(revision, &page_state))
+                .collect(),
+        ),
+        _ => None,
+    };
+
+    let content = match form {
+        Some(form) => ContentEdit { revisions, ..form },
+        None => ContentEdit {
+            id: item.id,
+            url: match item.kind {
+                ItemKind::Story => Some(item.url.unwrap_or_default()),
+                _ => None,
+            },
+            title: item.title,
+            text: item.text.unwrap_or_default(),
+            error: None,
+            revisions,
+        },
+    };
+
+    PageEdit::new_response(page_state, content)
+}
+
+async fn serve_edit_request(
+    path: &str,
+    query: BTreeMap<String, String>,
+    form: BTreeMap<String, String>,
+    app_state: Arc<AppState>,
+    session: Session,
+) -> Result<HttpResponse> {
+    let (item, user) = match (
+        get_editable_item(&query, &app_state, &session).await,
+        session.user(),
+    ) {
+        (Some(item), Some(user)) => (item, user),
+        _ => return serve_static(path.trim_start_matches('/'), query, session),
+    };
+
     let locale = session.locale();
-    let user = session.user();
+    let strings = &locale.strings().page.edit;
+    let field = |name: &str| form.get(name).map(|s| s.trim()).unwrap_or("").to_string();
+    let mut content = ContentEdit {
+        id: item.id,
+        title: item.title.as_ref().map(|_| field("title")),
+        url: match item.kind {
+            ItemKind::Story => Some(field("url")),
+            _ => None,
+        },
+        text: field("text"),
+        error: None,
+        revisions: None,
+    };
+
+    let url = match content.url.as_deref() {
+        Some("") | None => None,
+        Some(url) => {
+            match content::sanitize_url(url).filter(|url| sites::site_of_url(url).is_some()) {
+                Some(url) => Some(url),
+                None => {
+                    content.error = Some(strings.invalid_url.to_owned());
+                    return serve_edit(path, query, app_state, session, Some(content)).await;
+                }
+            }
+        }
+    };
+    if matches!(content.title.as_deref(), Some("")) {
+        content.error = Some(strings.missing_title.to_owned());
+        return serve_edit(path, query, app_state, session, Some(content)).await;
+    }
+    // a story needs either an url or text, any other item always needs text
+    if content.text.is_empty() && (item.kind != ItemKind::Story || url.is_none()) {
+        content.error = Some(strings.missing_text.to_owned());
+        return serve_edit(path, query, app_state, session, Some(content)).await;
+    }
+
+    let text = Some(content.text.clone()).filter(|text| !text.is_empty());
+    let item_content = ItemContent {
+        title: content.title.clone(),
+        text,
+        url,
+    };
+
+    // shadow banned content is accepted, as if nothing happened
+    let submission = Submission {
+        kind: item.kind,
+        item: Some(item.id),
+        content: &item_content,
+    };
+    let decision = app_state
+        .spam_policy
+        .screen(&app_state.db, &user, &submission, SystemTime::now())
+        .await;
+    let spam = &strings.spam;
+    let error = match decision.map(|decision| (decision.verdict, decision.reason)) {
+        Some((SpamVerdict::Rejected, SpamReason::ProbationLimit)) => Some(&spam.probation_limit),
+        Some((SpamVerdict::Rejected, SpamReason::ProbationLink)) => Some(&spam.probation_link),
+        Some((SpamVerdict::Rejected, SpamReason::BlockedDomain)) => Some(&spam.blocked_domain),
+        Some((SpamVerdict::Rejected, SpamReason::DuplicateText)) => Some(&spam.duplicate_text),
+        Some((SpamVerdict::Rejected, SpamReason::TooManyLinks)) => Some(&spam.too_many_links),
+        _ => None,
+    };
+    if let Some(error) = error {
+        content.error = Some(error.to_string());
+        return serve_edit(path, query, app_state, session, Some(content)).await;
+    }
+
+    app_state.db.edit_item(item.id, user.id, item_content).await;
+
+    redirect_to_newest(&PageState::new(&session, path.to_string(), query))
+}
+
+async fn serve_delete(
+    path: &str,
+    query: BTreeMap<String, String>,
+    app_state: Arc<AppState>,
+    session: Session,
+) -> Result<HttpResponse> {
+    let item = match get_editable_item(&query, &app_state, &session).await {
+        Some(item) => item,
+        None => return serve_static(path.trim_start_matches('/'), query, session),
+    };
+
+    let page_state = PageState::new(&session, path.to_string(), query);
 
-    let page_state = PageState::new(locale, format!("/{}", endpoint), query, user);
+    let content = ContentDelete {
+        id: item.id,
+        summary: item.title.or(item.text).unwrap_or_default(),
+    };
+
+    PageDelete::new_response(page_state, content)
+}
+
+async fn serve_delete_request(
+    path: &str,
+    query: BTreeMap<String, String>,
+    form: BTreeMap<String, String>,
+    app_state: Arc<AppState>,
+    session: Session,
+) -> Result<HttpResponse> {
+    let item = match get_editable_item(&query, &app_state, &session).await {
+        Some(item) => item,
+        None => return serve_static(path.trim_start_matches('/'), query, session),
+    };
+
+    if form.get("confirm").map(|s| s.as_str()) == Some("yes") {
+        app_state.db.delete_item(item.id).await;
+    }
+
+    redirect_to_newest(&PageState::new(&session, path.to_string(), query))
+}
+
+fn serve_api(
+    path: &str,
+    query: BTreeMap<String, String>,
+    session: Session,
+    content: ContentApi,
+) -> Result<HttpResponse> {
+    let page_state = PageState::new(&session, path.to_string(), query);
+
+    PageApi::new_response(page_state, content)
+}
+
+async fn serve_api_request(
+    path: &str,
+    query: BTreeMap<String, String>,
+    form: BTreeMap<String, String>,
+    app_state: Arc<AppState>,
+    session: Session,
+) -> Result<HttpResponse> {
+    let locale = session.locale();
+    let strings = &locale.strings().page.api.request;
+    let field = |name: &str| form.get(name).map(|s| s.trim()).unwrap_or("").to_string();
+    let mut content = ContentApi {
+        organization: field("organization"),
+        motivation: field("motivation"),
+        ..ContentApi::default()
+    };
+
+    let user = match session.user() {
+        Some(user) => user,
+        None => {
+            content.error = Some(strings.login.to_owned());
+            return serve_api(path, query, session, content);
+        }
+    };
+    if content.motivation.is_empty() {
+        content.error = Some(strings.missing_motivation.to_owned());
+        return serve_api(path, query, session, content);
+    }
+
+    let owner = if content.organization.is_empty() {
+        ApiKeyOwner::User(user.id)
+    } else {
+        ApiKeyOwner::Organization {
+            name: content.organization.clone(),
+            contact: user.id,
+        }
+    };
+    app_state
+        .db
+        .create_api_access_request(owner, content.motivation.clone())
+        .await;
+
+    serve_api(
+        path,
+        query,
+        session,
+        ContentApi {
+            sent: true,
+            ..ContentApi::default()
+        },
+    )
+}
+
+async fn serve_admin_api(
+    path: &str,
+    query: BTreeMap<String, String>,
+    app_state: Arc<AppState>,
+    session: Session,
+    issued_key: Option<String>,
+) -> Result<HttpResponse> {
+    if !is_admin(&session) {
+        return serve_static(path.trim_start_matches('/'), query, session);
+    }
+
+    let page_state = PageState::new(&session, path.to_string(), query);
+
+    let content = ContentAdminApi {
+        issued_key,
+        default_limits: ApiKeyLimits::default(),
+        requests: app_state
+            .db
+            .get_api_access_requests()
+            .await
+            .into_iter()
+            .map(|request| ApiAccessRequest::from_data(request, &page_state))
+            .collect(),
+        keys: app_state
+            .db
+            .get_api_keys()
+            .await
+            .into_iter()
+            .map(|key| ApiKey::from_data(key, &page_state))
+            .collect(),
+    };
+
+    PageAdminApi::new_response(page_state, content)
+}
+
+async fn serve_admin_api_action(
+    path: &str,
+    query: BTreeMap<String, String>,
+    form: BTreeMap<String, String>,
+    app_state: Arc<AppState>,
+    session: Session,
+) -> Result<HttpResponse> {
+    if !is_admin(&session) {
+        return serve_static(path.trim_start_matches('/'), query, session);
+    }
+
+    let id = form.get("id").and_then(|id| id.parse().ok());
+    let mut issued_key = None;
+    match (form.get("action").map(|s| s.as_str()), id) {
+        (Some("approve"), Some(id)) => {
+            let defaults = ApiKeyLimits::default();
+            let limit = |name: &str| {
+                form.get(name)
+                    .and_then(|value| value.trim().parse::<u32>().ok())
+                    .filter(|value| *value > 0)
+            };
+            let limits = ApiKeyLimits {
+                requests_per_minute: limit("requests_per_minute")
+                    .unwrap_or(defaults.requests_per_minute),
+                requests_per_day: limit("requests_per_day").unwrap_or(defaults.requests_per_day),
+            };
+            issued_key = app_state.db.approve_api_access_request(id, limits).await;
+        }
+        (Some("reject"), Some(id)) => {
+            app_state.db.reject_api_access_request(id).await;
+        }
+        (Some("revoke"), Some(id)) => {
+            app_state.db.revoke_api_key(id).await;
+        }
+        _ => (),
+    }
+
+    serve_admin_api(path, query, app_state, session, issued_key).await
+}
+
+/// The (localized) page explaining why a form submission was refused,
+/// see [`CsrfForm`].
+pub fn forbidden_response(
+    req: &HttpRequest,
+    session: &Session,
+    failure: CsrfFailure,
+) -> Result<HttpResponse> {
+    let query = web::Query::<BTreeMap<String, String>>::from_query(req.query_string())
+        .map(web::Query::into_inner)
+        .unwrap_or_default();
+    let page_state = PageState::new(session, req.path().to_owned(), query);
+
+    let strings = &page_state.locale.strings().page.forbidden;
+    let content = ContentForbidden {
+        message: match failure {
+            CsrfFailure::Origin => strings.origin,
+            CsrfFailure::Token => strings.token,
+        }
+        .to_owned(),
+        back: extractors::referer_path(req, session.origin())
+            .unwrap_or_else(|| format!("{}/", page_state.root())),
+    };
+
+    let mut response = PageForbidden::new_response(page_state, content)?;
+    *response.status_mut() = StatusCode::FORBIDDEN;
+    Ok(response)
+}
+
+/// The (localized) page asking the client to slow down,
+/// as it exceeded its budget for the requested kind of request.
+pub fn rate_limited_response(
+    req: &HttpRequest,
+    session: &Session,
+    retry_after: std::time::Duration,
+) -> Result<HttpResponse> {
+    let query = web::Query::<BTreeMap<String, String>>::from_query(req.query_string())
+        .map(web::Query::into_inner)
+        .unwrap_or_default();
+    let page_state = PageState::new(session, req.path().to_owned(), query);
+
+    // round up, such that the client does not retry too early
+    let retry_after_sec = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    let content = ContentRateLimited { retry_after_sec };
+
+    let mut response = PageRateLimited::new_response(page_state, content)?;
+    *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, header::HeaderValue::from(retry_after_sec));
+    Ok(response)
+}
+
+/// Only admins are allowed to manage the API access,
+/// for anyone else the admin pages do not exist.
+fn is_admin(session: &Session) -> bool {
+    matches!(session.user(), Some(user) if user.kind == UserKind::Admin)
+}
+
+fn serve_static(
+    endpoint: &str,
+    query: BTreeMap<String, String>,
+    session: Session,
+) -> Result<HttpResponse> {
+    let page_state = PageState::new(&session, format!("/{}", endpoint), query);
 
     static_response(endpoint, page_state)
 }
@@ -211,3 +1270,148 @@ fn serve_static(
 // and use it within here to start to get data,
 // so we are working towards that structure already,
 // probably will require plenty of iterations on its own right
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use actix_web::{test, App};
+    use askama::Template;
+
+    use plabayo_news_data::models;
+
+    use super::*;
+    use crate::site::middleware::LocalePath;
+
+    const HOSTILE: &str = r#""><script>alert('xss')</script><a href="javascript:alert(1)">"#;
+
+    fn hostile_page_state(path: &str) -> PageState {
+        let mut query = BTreeMap::new();
+        query.insert("q".to_owned(), HOSTILE.to_owned());
+        PageState {
+            locale: Locale::default(),
+            path_locale: None,
+            path: path.to_owned(),
+            query,
+            gen_date_time: Utc::now(),
+            user: None,
+            edit_window: crate::site::state::DEFAULT_EDIT_WINDOW,
+            relations: models::ItemRelations::default(),
+            unread_replies: 0,
+            csrf_token: None,
+            origin: "https://news.plabayo.tech".to_owned(),
+            csp_nonce: String::new(),
+        }
+    }
+
+    #[test]
+    fn locale_alternates_are_absolute() {
+        let mut page = hostile_page_state("/");
+        page.query.clear();
+        let alternates = page.locale_alternates();
+        assert_eq!(alternates.len(), Locale::all().count() + 1);
+        assert!(alternates.contains(&("nl".to_owned(), "https://news.plabayo.tech/nl".to_owned())));
+        assert_eq!(
+            alternates.last(),
+            Some(&(
+                "x-default".to_owned(),
+                "https://news.plabayo.tech/".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn locale_prefixed_pages_are_served_with_their_alternates() {
+        actix_web::rt::System::new("test").block_on(async {
+            let mut app = test::init_service(
+                App::new()
+                    .app_data(web::Data::new(AppState::new()))
+                    .wrap(LocalePath)
+                    .service(factory()),
+            )
+            .await;
+            let req = test::TestRequest::get().uri("/nl/newest?p=2").to_request();
+            let res = test::call_service(&mut app, req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let html = String::from_utf8(test::read_body(res).await.to_vec()).unwrap();
+            assert!(html.contains("<html lang=\"nl\">"));
+            // the prefix is kept by all links of the page
+            assert!(html.contains("<a href=\"/nl/ask\">"));
+            for locale in Locale::all() {
+                assert!(html.contains(&format!(
+                    "<link rel=\"alternate\" hreflang=\"{0}\" \
+                     href=\"https://news.plabayo.tech/{0}/newest?p=2\">",
+                    locale
+                )));
+            }
+            assert!(html.contains(
+                "<link rel=\"alternate\" hreflang=\"x-default\" \
+                 href=\"https://news.plabayo.tech/newest?p=2\">"
+            ));
+        })
+    }
+
+    fn assert_no_injection(html: &str) {
+        assert!(
+            !html.contains("<script>"),
+            "found injected script in:\n{}",
+            html
+        );
+        assert!(
+            !html.contains("href=\"javascript:"),
+            "found injected link in:\n{}",
+            html
+        );
+    }
+
+    #[test]
+    fn search_page_escapes_query() {
+        let page = hostile_page_state("/search");
+        let content = ContentSearch {
+            q: HOSTILE.to_owned(),
+        };
+        let html = PageSearch::new(page, content).render().unwrap();
+        assert_no_injection(&html);
+        assert!(html.contains("&lt;script&gt;"));
+    }
+
+    #[test]
+    fn item_page_escapes_query() {
+        let page = hostile_page_state("/item");
+        let content = ContentItem {
+            q: HOSTILE.to_owned(),
+        };
+        let html = PageItem::new(page, content).render().unwrap();
+        assert_no_injection(&html);
+        assert!(html.contains("&lt;script&gt;"));
+    }
+
+    #[test]
+    fn items_page_escapes_item() {
+        let page = hostile_page_state("/news");
+        let item = Item::from_data(
+            models::Item {
+                id: 1,
+                state: models::ItemState::Alive,
+                kind: models::ItemKind::Story,
+                by: 1,
+                time: SystemTime::now(),
+                mod_time: SystemTime::now(),
+                votes: 1,
+                text: Some(HOSTILE.to_owned()),
+                parent: None,
+                kids: vec![],
+                url: Some("javascript:alert(1)".to_owned()),
+                title: Some(HOSTILE.to_owned()),
+            },
+            &page,
+        );
+        assert!(item.url.is_none());
+        assert_no_injection(&item.text.as_ref().unwrap().to_string());
+
+        let content = ContentItems { items: vec![item] };
+        let html = PageItems::new(page, content).render().unwrap();
+        assert_no_injection(&html);
+        assert!(html.contains("&lt;script&gt;"));
+    }
+}
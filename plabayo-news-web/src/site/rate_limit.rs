@@ -0,0 +1,352 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Token bucket rate limiting of the requests of each client,
+//! with a separate budget per kind of request.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::http::Method;
+use anyhow::{anyhow, Context, Error};
+use cached::{Cached, SizedCache};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use plabayo_news_data::models::UserID;
+
+use crate::site::assets;
+use crate::site::csp;
+
+/// Default amount of buckets kept in memory by the [`MemoryStore`],
+/// the least recently used ones are evicted (and thus reset) first.
+pub const DEFAULT_CAPACITY: usize = 100_000;
+
+/// The kind of request, each with its own budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequestKind {
+    PageView,
+    Submission,
+    Comment,
+    Vote,
+    Login,
+}
+
+impl RequestKind {
+    /// The kind of the request with the given method and path,
+    /// none for requests which are not limited, such as assets.
+    pub fn of(method: &Method, path: &str) -> Option<RequestKind> {
+        if path.split('/').nth(1) == Some(assets::ROOT) || path == csp::REPORT_PATH {
+            return None;
+        }
+        if method != Method::POST {
+            return Some(RequestKind::PageView);
+        }
+        Some(match path.trim_start_matches('/') {
+            "login" => RequestKind::Login,
+            "comment" | "reply" => RequestKind::Comment,
+            "vote" => RequestKind::Vote,
+            _ => RequestKind::Submission,
+        })
+    }
+}
+
+/// The client a budget belongs to, limited by both its address
+/// and (if logged in) its user, as either can be used to spread abuse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Client {
+    Ip(IpAddr),
+    User(UserID),
+}
+
+/// Key of a single token bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BucketKey {
+    pub kind: RequestKind,
+    pub client: Client,
+}
+
+/// A budget of `burst` requests, replenished at a rate of `burst` requests per `period`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limit {
+    pub burst: u32,
+    pub period: Duration,
+}
+
+impl Limit {
+    pub const fn per_minute(burst: u32) -> Limit {
+        Limit {
+            burst,
+            period: Duration::from_secs(60),
+        }
+    }
+
+    pub const fn per_hour(burst: u32) -> Limit {
+        Limit {
+            burst,
+            period: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// Parses limits such as `300/m`, using `s`, `m`, `h` or `d` as the period.
+impl FromStr for Limit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Limit, Error> {
+        let (burst, period) = s
+            .split_once('/')
+            .ok_or_else(|| anyhow!("limit '{}' is not of the form <requests>/<s|m|h|d>", s))?;
+        let burst = burst
+            .trim()
+            .parse()
+            .with_context(|| format!("parse amount of requests of limit '{}'", s))?;
+        let period = match period.trim() {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            unit => return Err(anyhow!("unknown period '{}' of limit '{}'", unit, s)),
+        };
+        Ok(Limit {
+            burst,
+            period: Duration::from_secs(period),
+        })
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = match self.period.as_secs() {
+            1 => "s",
+            60 => "m",
+            3600 => "h",
+            86400 => "d",
+            secs => return write!(f, "{}/{}s", self.burst, secs),
+        };
+        write!(f, "{}/{}", self.burst, unit)
+    }
+}
+
+impl Serialize for Limit {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Limit {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Limit, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+/// The limits of each kind of request.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub page_views: Limit,
+    pub submissions: Limit,
+    pub comments: Limit,
+    pub votes: Limit,
+    pub logins: Limit,
+}
+
+impl Limits {
+    pub fn get(&self, kind: RequestKind) -> Limit {
+        match kind {
+            RequestKind::PageView => self.page_views,
+            RequestKind::Submission => self.submissions,
+            RequestKind::Comment => self.comments,
+            RequestKind::Vote => self.votes,
+            RequestKind::Login => self.logins,
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            page_views: Limit::per_minute(300),
+            submissions: Limit::per_minute(30),
+            comments: Limit::per_minute(10),
+            votes: Limit::per_minute(60),
+            logins: Limit::per_hour(10),
+        }
+    }
+}
+
+/// Storage of the token buckets, kept in memory by default,
+/// but could as well be shared between multiple instances of the site.
+pub trait BucketStore: Send + Sync {
+    /// Take a token from the bucket with the given key, returning the time
+    /// after which a token is available again in case the bucket is empty.
+    fn take(&self, key: BucketKey, limit: Limit, now: Instant) -> Result<(), Duration>;
+}
+
+/// Buckets kept in the memory of the process.
+pub struct MemoryStore {
+    buckets: Mutex<SizedCache<BucketKey, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl MemoryStore {
+    pub fn new(capacity: usize) -> MemoryStore {
+        MemoryStore {
+            buckets: Mutex::new(SizedCache::with_size(capacity)),
+        }
+    }
+}
+
+impl BucketStore for MemoryStore {
+    fn take(&self, key: BucketKey, limit: Limit, now: Instant) -> Result<(), Duration> {
+        let rate = limit.burst as f64 / limit.period.as_secs_f64();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.cache_get_or_set_with(key, || Bucket {
+            tokens: limit.burst as f64,
+            updated: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(limit.burst as f64);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
+
+/// Limits the requests of all clients, according to the configured limits.
+pub struct RateLimiter {
+    limits: Limits,
+    store: Box<dyn BucketStore>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: Limits, store: Box<dyn BucketStore>) -> RateLimiter {
+        RateLimiter { limits, store }
+    }
+
+    /// Take a token from the budgets of the client for the given kind of request,
+    /// returning the time to wait before retrying in case a budget is exhausted.
+    pub fn check(
+        &self,
+        kind: RequestKind,
+        ip: Option<IpAddr>,
+        user: Option<UserID>,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let limit = self.limits.get(kind);
+        let clients = ip.map(Client::Ip).into_iter().chain(user.map(Client::User));
+        let mut retry_after = None;
+        for client in clients {
+            if let Err(wait) = self.store.take(BucketKey { kind, client }, limit, now) {
+                retry_after = Some(retry_after.map_or(wait, |other: Duration| other.max(wait)));
+            }
+        }
+        match retry_after {
+            Some(wait) => Err(wait),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> RateLimiter {
+        RateLimiter::new(
+            Limits::default(),
+            Box::new(MemoryStore::new(DEFAULT_CAPACITY)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_are_parsed() {
+        assert_eq!("300/m".parse::<Limit>().unwrap(), Limit::per_minute(300));
+        assert_eq!("10/h".parse::<Limit>().unwrap(), Limit::per_hour(10));
+        assert_eq!(Limit::per_hour(10).to_string(), "10/h");
+        assert!("10".parse::<Limit>().is_err());
+        assert!("10/w".parse::<Limit>().is_err());
+        assert!("x/m".parse::<Limit>().is_err());
+    }
+
+    #[test]
+    fn requests_are_classified() {
+        assert_eq!(
+            RequestKind::of(&Method::GET, "/news"),
+            Some(RequestKind::PageView)
+        );
+        assert_eq!(
+            RequestKind::of(&Method::POST, "/login"),
+            Some(RequestKind::Login)
+        );
+        assert_eq!(
+            RequestKind::of(&Method::POST, "/favorite"),
+            Some(RequestKind::Submission)
+        );
+        assert_eq!(RequestKind::of(&Method::GET, "/assets/style.css"), None);
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let limiter = RateLimiter::new(
+            Limits {
+                votes: "2/m".parse().unwrap(),
+                ..Limits::default()
+            },
+            Box::new(MemoryStore::new(16)),
+        );
+        let ip = Some("127.0.0.1".parse().unwrap());
+        let now = Instant::now();
+        assert!(limiter.check(RequestKind::Vote, ip, Some(1), now).is_ok());
+        assert!(limiter.check(RequestKind::Vote, ip, Some(1), now).is_ok());
+        let wait = limiter
+            .check(RequestKind::Vote, ip, Some(1), now)
+            .unwrap_err();
+        assert_eq!(wait.as_secs(), 30);
+        // other budgets and clients are not affected
+        assert!(limiter
+            .check(RequestKind::Comment, ip, Some(1), now)
+            .is_ok());
+        assert!(limiter.check(RequestKind::Vote, None, Some(2), now).is_ok());
+        // the user is limited from any address
+        assert!(limiter
+            .check(RequestKind::Vote, None, Some(1), now)
+            .is_err());
+        assert!(limiter
+            .check(
+                RequestKind::Vote,
+                ip,
+                Some(1),
+                now + Duration::from_secs(30)
+            )
+            .is_ok());
+    }
+}
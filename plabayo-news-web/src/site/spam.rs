@@ -0,0 +1,406 @@
+// Plabayo News
+// Copyright (C) 2021  Glen Henri J. De Cauwsemaecker
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Spam prevention of the content submitted by users, restricting new accounts
+//! during their probation and screening all content using a couple of heuristics.
+//! Its decisions are logged as actions, and can shadow ban a user, making
+//! all of their items visible only to themselves.
+
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+
+use plabayo_news_data::models::{
+    Item, ItemContent, ItemID, ItemKind, SpamDecision, SpamReason, SpamVerdict, User, UserID,
+    UserKind, UserState,
+};
+use plabayo_news_data::{sites, Database};
+
+use crate::site::content::bare_urls;
+
+/// The default time after its creation during which an account is on probation.
+pub const DEFAULT_PROBATION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// The time within which the submissions of a user on probation are limited.
+const PROBATION_LIMIT_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Texts shorter than this (once normalized) are too common to be considered duplicates.
+const MIN_DUPLICATE_TEXT_LEN: usize = 20;
+
+/// The rules the content submitted by users has to follow.
+#[derive(Debug, Clone)]
+pub struct SpamPolicy {
+    /// Time after its creation during which an account is on probation.
+    pub probation: Duration,
+    /// Karma with which an account is no longer on probation, even if still young.
+    pub probation_karma: i64,
+    /// Max new items a user on probation can submit within a day.
+    pub probation_max_items: usize,
+    /// Max links a comment can contain.
+    pub max_comment_links: usize,
+    /// Sites no one can link to.
+    pub blocked_domains: HashSet<String>,
+    /// Sites users on probation can link to, none if empty.
+    pub allowed_domains: HashSet<String>,
+    /// Time within which the same text cannot be submitted again.
+    pub duplicate_window: Duration,
+}
+
+impl Default for SpamPolicy {
+    fn default() -> SpamPolicy {
+        SpamPolicy {
+            probation: DEFAULT_PROBATION,
+            probation_karma: 10,
+            probation_max_items: 3,
+            max_comment_links: 3,
+            blocked_domains: HashSet::new(),
+            allowed_domains: HashSet::new(),
+            duplicate_window: Duration::from_secs(7 * 24 * 60 * 60),
+        }
+    }
+}
+
+/// Content submitted by a user, either for a new item or an existing one.
+pub struct Submission<'a> {
+    pub kind: ItemKind,
+    /// The edited item, none in case of a new item.
+    pub item: Option<ItemID>,
+    pub content: &'a ItemContent,
+}
+
+/// What is known of the site's recent activity when screening a submission.
+pub struct History<'a> {
+    /// The items submitted within the (largest) window of the policy.
+    pub recent_items: &'a [Item],
+    pub shadow_banned: &'a HashSet<UserID>,
+    /// The other users who used any of the addresses of the submitting user.
+    pub sharing_ips: &'a [User],
+}
+
+impl SpamPolicy {
+    /// Returns true if the user is still on probation,
+    /// being a young account that did not earn enough karma yet.
+    pub fn is_on_probation(&self, user: &User, now: SystemTime) -> bool {
+        user.karma < self.probation_karma
+            && matches!(now.duration_since(user.create_time), Ok(age) if age < self.probation)
+    }
+
+    /// Screen the submission of the given user,
+    /// logging the decision in case it is considered to be spam.
+    pub async fn screen(
+        &self,
+        db: &Database,
+        user: &User,
+        submission: &Submission<'_>,
+        now: SystemTime,
+    ) -> Option<SpamDecision> {
+        let since = now
+            .checked_sub(self.duplicate_window.max(PROBATION_LIMIT_PERIOD))
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        let recent_items = db.get_items_since(since).await;
+        let shadow_banned = db.get_shadow_banned_users().await;
+        let sharing_ips = if self.is_on_probation(user, now) {
+            db.get_users_sharing_ips(user.id, &user.ips).await
+        } else {
+            Vec::new()
+        };
+        let history = History {
+            recent_items: &recent_items,
+            shadow_banned: &shadow_banned,
+            sharing_ips: &sharing_ips,
+        };
+
+        let (verdict, reason) = self.check(user, submission, &history, now)?;
+        let decision = SpamDecision {
+            user: user.id,
+            item: submission.item,
+            verdict,
+            reason,
+        };
+        db.record_spam_decision(decision).await;
+        Some(decision)
+    }
+
+    /// Check the submission of the given user against the policy,
+    /// returning the verdict and its reason in case it is considered to be spam.
+    pub fn check(
+        &self,
+        user: &User,
+        submission: &Submission<'_>,
+        history: &History<'_>,
+        now: SystemTime,
+    ) -> Option<(SpamVerdict, SpamReason)> {
+        // moderators are trusted, and shadow banned users are only talking to themselves
+        if matches!(user.kind, UserKind::Moderator | UserKind::Admin)
+            || history.shadow_banned.contains(&user.id)
+        {
+            return None;
+        }
+        let on_probation = self.is_on_probation(user, now);
+
+        if on_probation
+            && history.sharing_ips.iter().any(|other| {
+                matches!(other.state, UserState::Locked)
+                    || history.shadow_banned.contains(&other.id)
+            })
+        {
+            return Some((SpamVerdict::ShadowBanned, SpamReason::BanEvasion));
+        }
+
+        let sites: Vec<Option<String>> = links_of(submission.content)
+            .iter()
+            .map(|link| sites::site_of_url(link))
+            .collect();
+        if sites
+            .iter()
+            .flatten()
+            .any(|site| self.blocked_domains.contains(site))
+        {
+            // a new account linking to a blocked site is most likely created to do just that
+            let verdict = if on_probation {
+                SpamVerdict::ShadowBanned
+            } else {
+                SpamVerdict::Rejected
+            };
+            return Some((verdict, SpamReason::BlockedDomain));
+        }
+        if submission.kind == ItemKind::Comment && sites.len() > self.max_comment_links {
+            return Some((SpamVerdict::Rejected, SpamReason::TooManyLinks));
+        }
+
+        if on_probation {
+            if sites
+                .iter()
+                .any(|site| !matches!(site, Some(site) if self.allowed_domains.contains(site)))
+            {
+                return Some((SpamVerdict::Rejected, SpamReason::ProbationLink));
+            }
+            let since = now.checked_sub(PROBATION_LIMIT_PERIOD);
+            let submitted = history
+                .recent_items
+                .iter()
+                .filter(|item| item.by == user.id && Some(item.time) >= since)
+                .count();
+            if submission.item.is_none() && submitted >= self.probation_max_items {
+                return Some((SpamVerdict::Rejected, SpamReason::ProbationLimit));
+            }
+        }
+
+        let text = normalize_text(submission.content.text.as_deref().unwrap_or(""));
+        if text.len() >= MIN_DUPLICATE_TEXT_LEN {
+            let since = now.checked_sub(self.duplicate_window);
+            let duplicate = history.recent_items.iter().any(|item| {
+                Some(item.id) != submission.item
+                    && Some(item.time) >= since
+                    && matches!(&item.text, Some(other) if normalize_text(other) == text)
+            });
+            if duplicate {
+                return Some((SpamVerdict::Rejected, SpamReason::DuplicateText));
+            }
+        }
+
+        None
+    }
+}
+
+/// The links within the content, being its url and those found in its text.
+fn links_of(content: &ItemContent) -> Vec<String> {
+    let mut links: Vec<String> = content.url.iter().cloned().collect();
+    if let Some(text) = &content.text {
+        links.extend(bare_urls(text).into_iter().map(str::to_owned));
+    }
+    links
+}
+
+/// The words of the text, lowercased and separated by a single space,
+/// such that trivial variations of the same text are still seen as equal.
+fn normalize_text(text: &str) -> String {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plabayo_news_data::models::ItemState;
+
+    fn user(id: UserID, age: Duration, now: SystemTime) -> User {
+        User {
+            id,
+            state: UserState::Public,
+            kind: UserKind::Member,
+            username: None,
+            name: None,
+            locale: None,
+            location: None,
+            create_time: now - age,
+            last_login_time: now,
+            karma: 1,
+            about: None,
+            items: Vec::new(),
+            ips: vec!["127.0.0.1".to_owned()],
+            authentications: Vec::new(),
+            preferences: None,
+        }
+    }
+
+    fn comment(id: ItemID, by: UserID, text: &str, time: SystemTime) -> Item {
+        Item {
+            id,
+            state: ItemState::Alive,
+            kind: ItemKind::Comment,
+            by,
+            time,
+            mod_time: time,
+            votes: 0,
+            text: Some(text.to_owned()),
+            parent: Some(1),
+            kids: Vec::new(),
+            url: None,
+            title: None,
+        }
+    }
+
+    fn text(text: &str) -> ItemContent {
+        ItemContent {
+            title: None,
+            text: Some(text.to_owned()),
+            url: None,
+        }
+    }
+
+    #[test]
+    fn probation_restricts_links_and_submissions() {
+        let now = SystemTime::now();
+        let policy = SpamPolicy {
+            allowed_domains: ["plabayo.tech".to_owned()].into_iter().collect(),
+            ..SpamPolicy::default()
+        };
+        let young = user(2, Duration::from_secs(60), now);
+        let old = user(3, 2 * DEFAULT_PROBATION, now);
+        let history = History {
+            recent_items: &[],
+            shadow_banned: &HashSet::new(),
+            sharing_ips: &[],
+        };
+        let check = |user: &User, content: &ItemContent| {
+            let submission = Submission {
+                kind: ItemKind::Comment,
+                item: None,
+                content,
+            };
+            policy.check(user, &submission, &history, now)
+        };
+
+        let link = text("see https://example.com/buy.");
+        assert_eq!(
+            check(&young, &link),
+            Some((SpamVerdict::Rejected, SpamReason::ProbationLink))
+        );
+        assert_eq!(check(&old, &link), None);
+        assert_eq!(
+            check(&young, &text("see (https://news.plabayo.tech)")),
+            None
+        );
+
+        let items: Vec<Item> = (1..=3)
+            .map(|id| comment(id, young.id, &format!("comment {}", id), now))
+            .collect();
+        let history = History {
+            recent_items: &items,
+            ..history
+        };
+        let submission = Submission {
+            kind: ItemKind::Comment,
+            item: None,
+            content: &text("yet another"),
+        };
+        assert_eq!(
+            policy.check(&young, &submission, &history, now),
+            Some((SpamVerdict::Rejected, SpamReason::ProbationLimit))
+        );
+        assert_eq!(policy.check(&old, &submission, &history, now), None);
+    }
+
+    #[test]
+    fn spam_is_detected() {
+        let now = SystemTime::now();
+        let policy = SpamPolicy {
+            blocked_domains: ["spam.com".to_owned()].into_iter().collect(),
+            ..SpamPolicy::default()
+        };
+        let young = user(2, Duration::from_secs(60), now);
+        let old = user(3, 2 * DEFAULT_PROBATION, now);
+        let banned = user(4, 2 * DEFAULT_PROBATION, now);
+        let items = [comment(1, banned.id, "Buy cheap watches, right now!", now)];
+        let shadow_banned = [banned.id].into_iter().collect();
+        let history = History {
+            recent_items: &items,
+            shadow_banned: &shadow_banned,
+            sharing_ips: &[],
+        };
+        let check = |user: &User, content: &ItemContent| {
+            let submission = Submission {
+                kind: ItemKind::Comment,
+                item: None,
+                content,
+            };
+            policy.check(user, &submission, &history, now)
+        };
+
+        let blocked = text("http://www.spam.com");
+        assert_eq!(
+            check(&young, &blocked),
+            Some((SpamVerdict::ShadowBanned, SpamReason::BlockedDomain))
+        );
+        assert_eq!(
+            check(&old, &blocked),
+            Some((SpamVerdict::Rejected, SpamReason::BlockedDomain))
+        );
+        assert_eq!(
+            check(&young, &text("see HTTPS://WWW.SPAM.COM/watches")),
+            Some((SpamVerdict::ShadowBanned, SpamReason::BlockedDomain))
+        );
+        assert_eq!(check(&banned, &blocked), None);
+        assert_eq!(
+            check(
+                &old,
+                &text("http://a.com http://b.com http://c.com http://d.com")
+            ),
+            Some((SpamVerdict::Rejected, SpamReason::TooManyLinks))
+        );
+        assert_eq!(
+            check(&old, &text("buy  CHEAP watches... right now")),
+            Some((SpamVerdict::Rejected, SpamReason::DuplicateText))
+        );
+
+        let history = History {
+            sharing_ips: std::slice::from_ref(&banned),
+            ..history
+        };
+        let submission = Submission {
+            kind: ItemKind::Comment,
+            item: None,
+            content: &text("hello"),
+        };
+        assert_eq!(
+            policy.check(&young, &submission, &history, now),
+            Some((SpamVerdict::ShadowBanned, SpamReason::BanEvasion))
+        );
+    }
+}
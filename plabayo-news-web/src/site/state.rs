@@ -14,16 +14,46 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::sync::Arc;
+use std::time::Duration;
+
 use plabayo_news_data::Database;
 
-#[derive(Clone, Copy)]
+use crate::site::csrf::CsrfSecret;
+use crate::site::digest::DigestMailer;
+use crate::site::spam::SpamPolicy;
+
+/// The default time within which authors can still edit and delete their own items.
+pub const DEFAULT_EDIT_WINDOW: Duration = Duration::from_secs(2 * 60 * 60);
+
+/// The default public origin of the site.
+pub const DEFAULT_ORIGIN: &str = "https://news.plabayo.tech";
+
+#[derive(Clone)]
 pub struct AppState {
     pub db: Database,
+    /// The time within which authors can still edit and delete their own items,
+    /// afterwards only moderators can.
+    pub edit_window: Duration,
+    pub csrf_secret: CsrfSecret,
+    pub spam_policy: SpamPolicy,
+    /// The public origin of the site (e.g. `https://news.plabayo.tech`),
+    /// used for its absolute links.
+    pub origin: String,
+    /// Sends the emails, if configured.
+    pub mailer: Option<Arc<DigestMailer>>,
 }
 
 impl AppState {
     pub fn new() -> AppState {
-        AppState { db: Database {} }
+        AppState {
+            db: Database::new(),
+            edit_window: DEFAULT_EDIT_WINDOW,
+            csrf_secret: CsrfSecret::generate(),
+            spam_policy: SpamPolicy::default(),
+            origin: DEFAULT_ORIGIN.to_owned(),
+            mailer: None,
+        }
     }
 }
 